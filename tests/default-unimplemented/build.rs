@@ -0,0 +1,11 @@
+extern crate tower_grpc_build;
+
+fn main() {
+    tower_grpc_build::Config::new()
+        .enable_server(true)
+        .enable_client(true)
+        .enable_mock(true)
+        .default_unimplemented(true)
+        .build(&["proto/echo.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
+}
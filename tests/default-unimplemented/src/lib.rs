@@ -0,0 +1,74 @@
+extern crate bytes;
+extern crate futures;
+extern crate prost;
+extern crate tower_grpc;
+
+#[cfg(test)]
+extern crate tokio;
+
+pub mod echo {
+    include!(concat!(env!("OUT_DIR"), "/echo.rs"));
+}
+
+#[cfg(test)]
+mod tests {
+    use echo::client;
+    use echo::mock::MockEcho;
+    use echo::server::{self, EchoServer};
+    use echo::Message;
+
+    use futures::{future, Future, Stream};
+    use tokio::runtime::current_thread::Runtime;
+    use tower_grpc::client::InProcess;
+    use tower_grpc::server::{BoxFuture, BoxStream};
+    use tower_grpc::{Code, Request, Response};
+
+    /// Only implements `Say`, leaving the other methods to their defaults.
+    #[derive(Clone, Debug)]
+    struct Echo;
+
+    impl server::Echo for Echo {
+        fn say(&mut self, request: Request<Message>) -> BoxFuture<Response<Message>> {
+            Box::new(future::ok(Response::new(request.into_inner())))
+        }
+    }
+
+    fn message(text: &str) -> Message {
+        Message {
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn unimplemented_methods_fail() {
+        let (transport, connection) = InProcess::pair(EchoServer::new(Echo));
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(connection);
+
+        let mut client = client::Echo::new(transport);
+
+        let response = rt
+            .block_on(client.say(Request::new(message("hello"))))
+            .unwrap();
+        assert_eq!(response.into_inner(), message("hello"));
+
+        let status = rt
+            .block_on(client.repeat(Request::new(message("hello"))))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+        assert_eq!(status.message(), "unimplemented method: /echo.Echo/Repeat");
+    }
+
+    #[test]
+    fn mocks_boxed_methods() {
+        let mut mock = MockEcho::new();
+        mock.repeat.respond(vec![message("a"), message("b")]);
+
+        let stream: BoxStream<Message> =
+            server::Echo::repeat(&mut mock, Request::new(message("a")))
+                .wait()
+                .unwrap()
+                .into_inner();
+        assert_eq!(stream.collect().wait().unwrap().len(), 2);
+    }
+}
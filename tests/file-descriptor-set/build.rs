@@ -0,0 +1,10 @@
+extern crate tower_grpc_build;
+
+fn main() {
+    tower_grpc_build::Config::new()
+        .enable_server(true)
+        .enable_client(true)
+        .file_descriptor_set("hello_descriptor")
+        .build(&["proto/hello.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
+}
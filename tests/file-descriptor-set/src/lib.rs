@@ -0,0 +1,28 @@
+extern crate bytes;
+extern crate prost;
+extern crate prost_types;
+extern crate tower_grpc;
+
+pub mod hello {
+    include!(concat!(env!("OUT_DIR"), "/hello.rs"));
+}
+
+include!(concat!(env!("OUT_DIR"), "/hello_descriptor.rs"));
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_types::FileDescriptorSet;
+
+    #[test]
+    fn includes_compiled_and_imported_files() {
+        let set = FileDescriptorSet::decode(super::FILE_DESCRIPTOR_SET).unwrap();
+
+        let names: Vec<_> = set.file.iter().map(|f| f.name()).collect();
+        assert_eq!(names, vec!["google/protobuf/empty.proto", "hello.proto"]);
+
+        let hello = &set.file[1];
+        assert_eq!(hello.service[0].name(), "Greeter");
+        assert_eq!(hello.service[0].method[0].name(), "SayHello");
+    }
+}
@@ -0,0 +1,9 @@
+extern crate tower_grpc_build;
+
+fn main() {
+    tower_grpc_build::Config::new()
+        .enable_client(true)
+        .enable_mock(true)
+        .build(&["proto/calc.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
+}
@@ -0,0 +1,104 @@
+extern crate bytes;
+extern crate futures;
+extern crate prost;
+extern crate tower_grpc;
+
+#[cfg(test)]
+extern crate tokio;
+
+pub mod calc {
+    include!(concat!(env!("OUT_DIR"), "/calc.rs"));
+}
+
+#[cfg(test)]
+mod tests {
+    use calc::client::Calculator;
+    use calc::mock::MockCalculator;
+    use calc::server::CalculatorServer;
+    use calc::Number;
+
+    use futures::{stream, Future, Stream};
+    use tokio::runtime::current_thread::Runtime;
+    use tower_grpc::client::InProcess;
+    use tower_grpc::{Code, Request, Response, Status};
+
+    fn client(mock: &MockCalculator, rt: &mut Runtime) -> Calculator<InProcess> {
+        let (transport, connection) = InProcess::pair(CalculatorServer::new(mock.clone()));
+        rt.spawn(connection);
+        Calculator::new(transport)
+    }
+
+    fn number(value: i64) -> Number {
+        Number { value }
+    }
+
+    #[test]
+    fn answers_and_records_unary_calls() {
+        let mock = MockCalculator::new();
+        mock.double.respond(number(4));
+
+        let mut rt = Runtime::new().unwrap();
+        let mut client = client(&mock, &mut rt);
+
+        let mut request = Request::new(number(2));
+        request
+            .metadata_mut()
+            .insert("x-user", "alice".parse().unwrap());
+
+        let response = rt.block_on(client.double(request)).unwrap();
+        assert_eq!(response.into_inner(), number(4));
+
+        let calls = mock.double.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(*calls[0].get_ref(), number(2));
+        assert_eq!(calls[0].metadata().get("x-user").unwrap(), "alice");
+    }
+
+    #[test]
+    fn answers_streaming_calls() {
+        let mock = MockCalculator::new();
+        mock.sum.respond_with(|request| {
+            let sum = request.get_ref().iter().map(|n| n.value).sum();
+            Ok(Response::new(number(sum)))
+        });
+        mock.running.respond(vec![number(1), number(3)]);
+
+        let mut rt = Runtime::new().unwrap();
+        let mut client = client(&mock, &mut rt);
+
+        let numbers = || stream::iter_ok::<_, Status>(vec![number(1), number(2)]);
+
+        let response = rt.block_on(client.sum(Request::new(numbers()))).unwrap();
+        assert_eq!(response.into_inner(), number(3));
+        assert_eq!(*mock.sum.calls()[0].get_ref(), vec![number(1), number(2)]);
+
+        let running = client
+            .running(Request::new(numbers()))
+            .and_then(|response| response.into_inner().collect());
+        let running = rt.block_on(running).unwrap();
+        assert_eq!(running, vec![number(1), number(3)]);
+    }
+
+    #[test]
+    fn fails_with_status() {
+        let mock = MockCalculator::new();
+        mock.count
+            .fail(Status::new(Code::InvalidArgument, "negative"));
+
+        let mut rt = Runtime::new().unwrap();
+        let mut client = client(&mock, &mut rt);
+
+        let status = rt
+            .block_on(client.count(Request::new(number(-1))))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // Methods without a response configured are unimplemented.
+        let status = rt
+            .block_on(client.double(Request::new(number(1))))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+        assert_eq!(mock.count.call_count(), 1);
+        assert_eq!(mock.double.call_count(), 1);
+    }
+}
@@ -0,0 +1,612 @@
+use filter::{Filter, Limits};
+use proto::grpc_log_entry::{EventType, Logger as Side, Payload};
+use proto::{address, Address, ClientHeader, GrpcLogEntry, Metadata, MetadataEntry};
+use proto::{Message, ServerHeader, Trailer};
+use sink::Sink;
+
+use base64;
+use http;
+use percent_encoding::percent_decode;
+use prost_types;
+use tower_grpc::stats::{self, Handler, RpcInfo};
+use tower_grpc::{Code, Status};
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{cmp, fmt};
+
+/// Writes the calls made or handled by a service to a binary log.
+///
+/// A `Logger` is a `tower_grpc::stats::Handler`, so the entries are written
+/// as the client `Grpc` and the server codec send and receive headers,
+/// messages and trailers. Clones share the same sink.
+///
+/// ```rust,ignore
+/// let logger = Logger::new("*{h:1024;m:4096}".parse()?, FileSink::create("grpc.binlog")?);
+///
+/// let greeter = Stats::new(GreeterServer::new(Greet), logger.clone());
+/// let router = Router::new().add_service(greeter);
+///
+/// let client = Greeter::new(conn).stats_handler(logger);
+/// ```
+///
+/// Unary and client streaming calls end on the client as soon as their
+/// response message is received, so their `ServerTrailer` entry only holds
+/// the status of the call, without the trailing metadata.
+#[derive(Clone)]
+pub struct Logger {
+    inner: Arc<Shared>,
+}
+
+struct Shared {
+    filter: Filter,
+    sink: Box<dyn Sink>,
+    /// The calls being logged, by `RpcInfo::id`.
+    calls: Mutex<HashMap<u64, Arc<CallLog>>>,
+}
+
+/// The log of a single call.
+struct CallLog {
+    side: Side,
+    id: u64,
+    limits: Limits,
+    sequence: AtomicUsize,
+    /// Whether the trailer was logged, ending the call.
+    trailer: AtomicBool,
+}
+
+// ===== impl Logger =====
+
+impl Logger {
+    /// Create a new `Logger` writing the calls selected by `filter` to
+    /// `sink`.
+    pub fn new<K>(filter: Filter, sink: K) -> Self
+    where
+        K: Sink,
+    {
+        let shared = Shared {
+            filter,
+            sink: Box::new(sink),
+            calls: Mutex::new(HashMap::new()),
+        };
+
+        Logger {
+            inner: Arc::new(shared),
+        }
+    }
+
+    /// Returns the filter selecting the calls that are logged.
+    pub fn filter(&self) -> &Filter {
+        &self.inner.filter
+    }
+
+    /// Write the entry built by `f`, if the call of `info` is logged.
+    fn log<F>(&self, info: &RpcInfo, f: F)
+    where
+        F: FnOnce(&CallLog) -> GrpcLogEntry,
+    {
+        let call = self.inner.calls.lock().unwrap().get(&info.id()).cloned();
+        if let Some(call) = call {
+            self.send(f(&call));
+        }
+    }
+
+    fn send(&self, entry: GrpcLogEntry) {
+        if let Err(e) = self.inner.sink.write(&entry) {
+            warn!("failed to write binary log entry: {}", e);
+        }
+    }
+}
+
+impl Handler for Logger {
+    fn begin(&self, info: &RpcInfo) {
+        let limits = match self.inner.filter.limits(info.method()) {
+            Some(limits) => limits,
+            None => return,
+        };
+
+        let side = if info.is_client() {
+            Side::Client
+        } else {
+            Side::Server
+        };
+        let call = CallLog {
+            side,
+            id: info.id(),
+            limits,
+            sequence: AtomicUsize::new(1),
+            trailer: AtomicBool::new(false),
+        };
+
+        let mut calls = self.inner.calls.lock().unwrap();
+        calls.insert(info.id(), Arc::new(call));
+    }
+
+    fn out_header(&self, info: &RpcInfo, headers: &http::HeaderMap) {
+        self.log(info, |call| {
+            if info.is_client() {
+                call.client_header(info, headers)
+            } else {
+                call.server_header(headers)
+            }
+        });
+    }
+
+    fn in_header(&self, info: &RpcInfo, headers: &http::HeaderMap) {
+        self.log(info, |call| {
+            if info.is_client() {
+                call.server_header(headers)
+            } else {
+                call.client_header(info, headers)
+            }
+        });
+    }
+
+    fn out_payload(&self, info: &RpcInfo, payload: &stats::Payload) {
+        let event = if info.is_client() {
+            EventType::ClientMessage
+        } else {
+            EventType::ServerMessage
+        };
+        self.log(info, |call| call.message(event, payload.data()));
+    }
+
+    fn in_payload(&self, info: &RpcInfo, payload: &stats::Payload) {
+        let event = if info.is_client() {
+            EventType::ServerMessage
+        } else {
+            EventType::ClientMessage
+        };
+        self.log(info, |call| call.message(event, payload.data()));
+    }
+
+    fn half_close(&self, info: &RpcInfo) {
+        self.log(info, |call| {
+            call.entry(EventType::ClientHalfClose, None, false, None)
+        });
+    }
+
+    fn out_trailer(&self, info: &RpcInfo, trailers: &http::HeaderMap) {
+        self.log(info, |call| call.trailer(trailers));
+    }
+
+    fn in_trailer(&self, info: &RpcInfo, trailers: &http::HeaderMap) {
+        self.log(info, |call| call.trailer(trailers));
+    }
+
+    fn end(&self, info: &RpcInfo, status: &Status) {
+        let call = match self.inner.calls.lock().unwrap().remove(&info.id()) {
+            Some(call) => call,
+            None => return,
+        };
+
+        if call.trailer.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let entry = if status.code() == Code::Cancelled {
+            call.entry(EventType::Cancel, None, false, None)
+        } else {
+            call.status(status)
+        };
+        self.send(entry);
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Logger")
+            .field("filter", &self.inner.filter)
+            .finish()
+    }
+}
+
+// ===== impl CallLog =====
+
+impl CallLog {
+    fn client_header(&self, info: &RpcInfo, headers: &http::HeaderMap) -> GrpcLogEntry {
+        let (metadata, truncated) = self.metadata(headers);
+        let header = ClientHeader {
+            metadata: Some(metadata),
+            method_name: info.method().to_string(),
+            authority: info.authority().unwrap_or_default().to_string(),
+            timeout: headers
+                .get("grpc-timeout")
+                .and_then(|value| decode_timeout(value.to_str().ok()?)),
+        };
+
+        // The server logs the address of the client with its header.
+        let peer = info.peer().map(address);
+
+        let payload = Payload::ClientHeader(header);
+        self.entry(EventType::ClientHeader, Some(payload), truncated, peer)
+    }
+
+    fn server_header(&self, headers: &http::HeaderMap) -> GrpcLogEntry {
+        let (metadata, truncated) = self.metadata(headers);
+        let header = ServerHeader {
+            metadata: Some(metadata),
+        };
+
+        let payload = Payload::ServerHeader(header);
+        self.entry(EventType::ServerHeader, Some(payload), truncated, None)
+    }
+
+    fn trailer(&self, trailers: &http::HeaderMap) -> GrpcLogEntry {
+        let (metadata, truncated) = self.metadata(trailers);
+        let status_code = trailers
+            .get("grpc-status")
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .unwrap_or(Code::Unknown as u32);
+        let status_message = trailers
+            .get("grpc-message")
+            .map(|value| {
+                percent_decode(value.as_bytes())
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+            .unwrap_or_default();
+        let status_details = trailers
+            .get("grpc-status-details-bin")
+            .map(|value| decode_bin(value.as_bytes()))
+            .unwrap_or_default();
+
+        let trailer = Trailer {
+            metadata: Some(metadata),
+            status_code,
+            status_message,
+            status_details,
+        };
+
+        self.trailer.store(true, Ordering::SeqCst);
+        let payload = Payload::Trailer(trailer);
+        self.entry(EventType::ServerTrailer, Some(payload), truncated, None)
+    }
+
+    /// The trailer of a call that ended without its trailers being seen.
+    fn status(&self, status: &Status) -> GrpcLogEntry {
+        let trailer = Trailer {
+            metadata: Some(Metadata::default()),
+            status_code: status.code() as u32,
+            status_message: status.message().to_string(),
+            status_details: status.details().to_vec(),
+        };
+
+        let payload = Payload::Trailer(trailer);
+        self.entry(EventType::ServerTrailer, Some(payload), false, None)
+    }
+
+    fn message(&self, event: EventType, data: &[u8]) -> GrpcLogEntry {
+        let keep = match self.limits.message_bytes() {
+            Some(limit) => cmp::min(limit, data.len()),
+            None => data.len(),
+        };
+        let message = Message {
+            length: data.len() as u32,
+            data: data[..keep].to_vec(),
+        };
+
+        let truncated = keep < data.len();
+        self.entry(event, Some(Payload::Message(message)), truncated, None)
+    }
+
+    fn entry(
+        &self,
+        event: EventType,
+        payload: Option<Payload>,
+        truncated: bool,
+        peer: Option<Address>,
+    ) -> GrpcLogEntry {
+        GrpcLogEntry {
+            timestamp: Some(timestamp(SystemTime::now())),
+            call_id: self.id,
+            sequence_id_within_call: self.sequence.fetch_add(1, Ordering::SeqCst) as u64,
+            r#type: event as i32,
+            logger: self.side as i32,
+            payload,
+            payload_truncated: truncated,
+            peer,
+        }
+    }
+
+    /// Returns the metadata to log from `headers`, and whether any was left
+    /// out to keep within the limits.
+    fn metadata(&self, headers: &http::HeaderMap) -> (Metadata, bool) {
+        let limit = self.limits.header_bytes();
+        let mut size = 0;
+        let mut entry = Vec::new();
+
+        for (key, value) in headers {
+            let key = key.as_str();
+            if is_omitted(key) {
+                continue;
+            }
+
+            let value = if key.ends_with("-bin") {
+                decode_bin(value.as_bytes())
+            } else {
+                value.as_bytes().to_vec()
+            };
+
+            // `grpc-trace-bin` doesn't count towards the limit.
+            if key != "grpc-trace-bin" {
+                size += key.len() + value.len();
+                if limit.map_or(false, |limit| size > limit) {
+                    return (Metadata { entry }, true);
+                }
+            }
+
+            entry.push(MetadataEntry {
+                key: key.to_string(),
+                value,
+            });
+        }
+
+        (Metadata { entry }, false)
+    }
+}
+
+/// Returns whether `key` is one of the keys used by gRPC or HTTP/2 rather
+/// than application metadata, which are never logged.
+fn is_omitted(key: &str) -> bool {
+    match key {
+        "grpc-trace-bin" => false,
+        "lb-token" | "content-encoding" | "content-type" | "user-agent" | "te" => true,
+        _ => key.starts_with("grpc-"),
+    }
+}
+
+/// Decodes the base64 value of a binary metadata entry.
+fn decode_bin(value: &[u8]) -> Vec<u8> {
+    let value = match value.iter().position(|&b| b == b'=') {
+        Some(idx) => &value[..idx],
+        None => value,
+    };
+    base64::decode_config(value, base64::STANDARD_NO_PAD).unwrap_or_else(|_| value.to_vec())
+}
+
+/// Decodes a `grpc-timeout` header value.
+fn decode_timeout(value: &str) -> Option<prost_types::Duration> {
+    if value.is_empty() || value.len() > 9 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let n: u64 = digits.parse().ok()?;
+    let timeout = match unit {
+        "H" => Duration::from_secs(n * 60 * 60),
+        "M" => Duration::from_secs(n * 60),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    };
+
+    Some(prost_types::Duration {
+        seconds: timeout.as_secs() as i64,
+        nanos: timeout.subsec_nanos() as i32,
+    })
+}
+
+fn timestamp(time: SystemTime) -> prost_types::Timestamp {
+    let since_epoch = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0));
+
+    prost_types::Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+fn address(addr: SocketAddr) -> Address {
+    let kind = match addr {
+        SocketAddr::V4(_) => address::Type::Ipv4,
+        SocketAddr::V6(_) => address::Type::Ipv6,
+    };
+
+    Address {
+        r#type: kind as i32,
+        address: addr.ip().to_string(),
+        ip_port: u32::from(addr.port()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, FutureResult};
+    use futures::Poll;
+    use tokio::runtime::current_thread::Runtime;
+    use tower_grpc::client::{Grpc, InProcess};
+    use tower_grpc::codegen::server::grpc::Never;
+    use tower_grpc::server::{self, unary};
+    use tower_grpc::stats::Stats;
+    use tower_grpc::{BoxBody, Encode, Request, Response};
+    use tower_service::Service;
+
+    use std::io;
+
+    #[derive(Clone, Default)]
+    struct Entries(Arc<Mutex<Vec<GrpcLogEntry>>>);
+
+    impl Sink for Entries {
+        fn write(&self, entry: &GrpcLogEntry) -> io::Result<()> {
+            self.0.lock().unwrap().push(entry.clone());
+            Ok(())
+        }
+    }
+
+    impl Entries {
+        fn events(&self) -> Vec<EventType> {
+            let entries = self.0.lock().unwrap();
+            entries
+                .iter()
+                .map(|entry| EventType::from_i32(entry.r#type).unwrap())
+                .collect()
+        }
+    }
+
+    /// A unary method replying with the message it receives, served as
+    /// `/test.Svc/Echo`.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<u32>> for Echo {
+        type Response = Response<u32>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: Request<u32>) -> Self::Future {
+            future::ok(Response::new(request.into_inner()))
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for Echo {
+        type Response = http::Response<Encode<unary::Once<u32>>>;
+        type Error = Never;
+        type Future = unary::ResponseFuture<Echo, BoxBody, u32>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            server::unary(Echo, request)
+        }
+    }
+
+    fn path() -> http::uri::PathAndQuery {
+        http::uri::PathAndQuery::from_static("/test.Svc/Echo")
+    }
+
+    fn request() -> Request<u32> {
+        let mut request = Request::new(21);
+        request
+            .metadata_mut()
+            .insert("x-request", "1".parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn logs_both_sides_of_a_call() {
+        let filter = Filter::new().all(Limits::new().max_message_bytes(1));
+        let server_entries = Entries::default();
+        let server_logger = Logger::new(filter.clone(), server_entries.clone());
+        let client_entries = Entries::default();
+        let client_logger = Logger::new(filter, client_entries.clone());
+
+        let (transport, connection) = InProcess::pair(Stats::new(Echo, server_logger));
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(connection);
+
+        let mut client = Grpc::new(transport).stats_handler(client_logger);
+        let response = rt
+            .block_on(client.unary::<_, u32, _>(request(), path()))
+            .unwrap();
+        assert_eq!(response.into_inner(), 21);
+
+        drop(client);
+        rt.run().unwrap();
+
+        assert_eq!(
+            client_entries.events(),
+            [
+                EventType::ClientHeader,
+                EventType::ClientMessage,
+                EventType::ClientHalfClose,
+                EventType::ServerHeader,
+                EventType::ServerMessage,
+                EventType::ServerTrailer,
+            ]
+        );
+        // A unary method doesn't read the end of the request.
+        assert_eq!(
+            server_entries.events(),
+            [
+                EventType::ClientHeader,
+                EventType::ClientMessage,
+                EventType::ServerHeader,
+                EventType::ServerMessage,
+                EventType::ServerTrailer,
+            ]
+        );
+
+        let entries = server_entries.0.lock().unwrap();
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.call_id, entries[0].call_id);
+            assert_eq!(entry.sequence_id_within_call, i as u64 + 1);
+            assert_eq!(entry.logger, Side::Server as i32);
+        }
+
+        match entries[0].payload {
+            Some(Payload::ClientHeader(ref header)) => {
+                assert_eq!(header.method_name, "/test.Svc/Echo");
+
+                let keys: Vec<_> = header
+                    .metadata
+                    .as_ref()
+                    .unwrap()
+                    .entry
+                    .iter()
+                    .map(|e| &e.key[..])
+                    .collect();
+                assert_eq!(keys, ["x-request"]);
+            }
+            ref payload => panic!("unexpected payload: {:?}", payload),
+        }
+
+        match entries[3].payload {
+            Some(Payload::Message(ref message)) => {
+                assert_eq!(message.length, 2);
+                assert_eq!(message.data, [0x08]);
+                assert!(entries[3].payload_truncated);
+            }
+            ref payload => panic!("unexpected payload: {:?}", payload),
+        }
+
+        match entries[4].payload {
+            Some(Payload::Trailer(ref trailer)) => assert_eq!(trailer.status_code, 0),
+            ref payload => panic!("unexpected payload: {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn logs_cancelled_calls() {
+        let entries = Entries::default();
+        let logger = Logger::new("*".parse().unwrap(), entries.clone());
+
+        let (transport, connection) = InProcess::pair(Echo);
+        let mut client = Grpc::new(transport).stats_handler(logger);
+
+        // The request body, queued on the connection, is part of the call.
+        drop(client.unary::<_, u32, _>(request(), path()));
+        drop(connection);
+
+        assert_eq!(
+            entries.events(),
+            [EventType::ClientHeader, EventType::Cancel]
+        );
+    }
+
+    #[test]
+    fn skips_filtered_calls() {
+        let entries = Entries::default();
+        let logger = Logger::new("*,-test.Svc/Echo".parse().unwrap(), entries.clone());
+
+        let (transport, connection) = InProcess::pair(Echo);
+        let mut client = Grpc::new(transport).stats_handler(logger);
+        drop(client.unary::<_, u32, _>(request(), path()));
+        drop(connection);
+
+        assert!(entries.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Selects the methods whose calls are logged, and how much of them.
+///
+/// The most specific rule matching a method applies: an exclusion, then a
+/// rule for the method, then one for its service, then the rule for all
+/// methods. Calls to methods without any rule are not logged.
+///
+/// A `Filter` can also be parsed from the `GRPC_BINARY_LOG_FILTER` syntax
+/// used by other gRPC implementations:
+///
+/// ```
+/// # use tower_grpc_binlog::Filter;
+/// let filter: Filter = "*{h:256;m:1024},-foo.Bar/Secret,foo.Baz/*{h}".parse().unwrap();
+/// ```
+///
+/// See https://github.com/grpc/proposal/blob/master/A16-binary-logging.md
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    all: Option<Limits>,
+    services: HashMap<String, Limits>,
+    methods: HashMap<String, Limits>,
+    excluded: HashSet<String>,
+}
+
+/// How many bytes of the metadata and messages of a call are logged.
+///
+/// Entries are still written for metadata and messages over the limits, but
+/// with truncated payloads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    header_bytes: Option<usize>,
+    message_bytes: Option<usize>,
+}
+
+/// An error parsing a `Filter`.
+#[derive(Debug)]
+pub struct ParseError {
+    message: String,
+}
+
+// ===== impl Filter =====
+
+impl Filter {
+    /// Create a new `Filter` that doesn't log any calls.
+    pub fn new() -> Self {
+        Filter::default()
+    }
+
+    /// Log the calls to every method with `limits`.
+    pub fn all(mut self, limits: Limits) -> Self {
+        self.all = Some(limits);
+        self
+    }
+
+    /// Log the calls to every method of the `package.Service` named
+    /// `service` with `limits`.
+    pub fn service(mut self, service: &str, limits: Limits) -> Self {
+        self.services.insert(service.to_string(), limits);
+        self
+    }
+
+    /// Log the calls to the `package.Service/Method` named `method` with
+    /// `limits`.
+    pub fn method(mut self, method: &str, limits: Limits) -> Self {
+        self.methods.insert(method.to_string(), limits);
+        self
+    }
+
+    /// Never log the calls to the `package.Service/Method` named `method`.
+    pub fn exclude(mut self, method: &str) -> Self {
+        self.excluded.insert(method.to_string());
+        self
+    }
+
+    /// Returns the limits for calls to the method at `path`, or `None` if
+    /// they aren't logged.
+    pub fn limits(&self, path: &str) -> Option<Limits> {
+        let method = path.trim_start_matches('/');
+        let service = match method.find('/') {
+            Some(idx) => &method[..idx],
+            None => return None,
+        };
+
+        if self.excluded.contains(method) {
+            return None;
+        }
+
+        self.methods
+            .get(method)
+            .or_else(|| self.services.get(service))
+            .or_else(|| self.all.as_ref())
+            .cloned()
+    }
+
+    fn parse_rule(mut self, rule: &str) -> Result<Self, ParseError> {
+        if rule.starts_with('-') {
+            let method = &rule[1..];
+            if !is_method(method) {
+                return Err(ParseError::new(format!(
+                    "only methods can be excluded: {:?}",
+                    rule
+                )));
+            }
+            self.excluded.insert(method.to_string());
+            return Ok(self);
+        }
+
+        let (pattern, limits) = match rule.find('{') {
+            Some(idx) if rule.ends_with('}') => {
+                let limits = Limits::parse(&rule[idx + 1..rule.len() - 1])
+                    .ok_or_else(|| ParseError::new(format!("invalid limits: {:?}", rule)))?;
+                (&rule[..idx], limits)
+            }
+            Some(_) => return Err(ParseError::new(format!("unclosed limits: {:?}", rule))),
+            None => (rule, Limits::default()),
+        };
+
+        let duplicate = if pattern == "*" {
+            self.all.replace(limits).is_some()
+        } else if pattern.ends_with("/*") && !pattern[..pattern.len() - 2].contains('/') {
+            let service = &pattern[..pattern.len() - 2];
+            self.services.insert(service.to_string(), limits).is_some()
+        } else if is_method(pattern) {
+            self.methods.insert(pattern.to_string(), limits).is_some()
+        } else {
+            return Err(ParseError::new(format!("invalid pattern: {:?}", rule)));
+        };
+
+        if duplicate {
+            return Err(ParseError::new(format!("duplicate pattern: {:?}", pattern)));
+        }
+
+        Ok(self)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .fold(Ok(Filter::new()), |filter, rule| filter?.parse_rule(rule))
+    }
+}
+
+/// Returns whether `pattern` names a single method.
+fn is_method(pattern: &str) -> bool {
+    let mut parts = pattern.split('/');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(service), Some(method), None) => {
+            !service.is_empty() && !method.is_empty() && !pattern.contains('*')
+        }
+        _ => false,
+    }
+}
+
+// ===== impl Limits =====
+
+impl Limits {
+    /// Create new `Limits`, logging metadata and messages in full.
+    pub fn new() -> Self {
+        Limits::default()
+    }
+
+    /// Log at most `max` bytes of the metadata of each header and trailer.
+    ///
+    /// Metadata entries are logged in order, for as long as they fit.
+    pub fn max_header_bytes(mut self, max: usize) -> Self {
+        self.header_bytes = Some(max);
+        self
+    }
+
+    /// Log at most `max` bytes of each message.
+    pub fn max_message_bytes(mut self, max: usize) -> Self {
+        self.message_bytes = Some(max);
+        self
+    }
+
+    /// Returns the maximum number of metadata bytes logged, if limited.
+    pub fn header_bytes(&self) -> Option<usize> {
+        self.header_bytes
+    }
+
+    /// Returns the maximum number of message bytes logged, if limited.
+    pub fn message_bytes(&self) -> Option<usize> {
+        self.message_bytes
+    }
+
+    /// Parses the `h:N;m:N` part of a rule, where a missing `h` or `m` means
+    /// nothing of the metadata or messages is logged.
+    fn parse(s: &str) -> Option<Self> {
+        let mut limits = Limits::new().max_header_bytes(0).max_message_bytes(0);
+        let mut seen = (false, false);
+
+        for option in s.split(';').map(str::trim) {
+            let mut parts = option.splitn(2, ':');
+            let (kind, max) = (parts.next()?, parts.next());
+
+            let max = match max {
+                Some(max) => Some(max.trim().parse().ok()?),
+                None => None,
+            };
+
+            match kind {
+                "h" if !seen.0 => {
+                    seen.0 = true;
+                    limits.header_bytes = max;
+                }
+                "m" if !seen.1 => {
+                    seen.1 = true;
+                    limits.message_bytes = max;
+                }
+                _ => return None,
+            }
+        }
+
+        Some(limits)
+    }
+}
+
+// ===== impl ParseError =====
+
+impl ParseError {
+    fn new(message: String) -> Self {
+        ParseError { message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid binary log filter: {}", self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_rule_applies() {
+        let filter: Filter = "*{h:256;m:1024}, -foo.Bar/Secret, foo.Bar/*{h}, foo.Bar/Big{m}"
+            .parse()
+            .unwrap();
+
+        let all = Limits::new().max_header_bytes(256).max_message_bytes(1024);
+        let headers = Limits::new().max_message_bytes(0);
+        let messages = Limits::new().max_header_bytes(0);
+
+        assert_eq!(filter.limits("/other.Svc/Method"), Some(all));
+        assert_eq!(filter.limits("/foo.Bar/Method"), Some(headers));
+        assert_eq!(filter.limits("/foo.Bar/Big"), Some(messages));
+        assert_eq!(filter.limits("/foo.Bar/Secret"), None);
+        assert_eq!(filter.limits("/not-a-method"), None);
+    }
+
+    #[test]
+    fn unmatched_methods_are_not_logged() {
+        let filter: Filter = "foo.Bar/Method".parse().unwrap();
+
+        assert_eq!(filter.limits("/foo.Bar/Method"), Some(Limits::new()));
+        assert_eq!(filter.limits("/foo.Bar/Other"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        for invalid in &[
+            "-*",
+            "-foo.Bar/*",
+            "*{h:x}",
+            "*{h;h}",
+            "*{h:1",
+            "foo/bar/baz",
+            "*,*{h}",
+        ] {
+            assert!(invalid.parse::<Filter>().is_err(), "{}", invalid);
+        }
+    }
+}
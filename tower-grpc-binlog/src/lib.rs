@@ -0,0 +1,48 @@
+//! gRPC binary logging for tower-grpc.
+//!
+//! A `Logger` records the headers, messages and trailers of the calls made
+//! by a client or handled by a server as `grpc.binarylog.v1.GrpcLogEntry`
+//! records, in the format shared with other gRPC implementations. It is a
+//! `tower_grpc::stats::Handler`, so it hooks into the client `Grpc` and the
+//! server codec like any other. Which calls are logged, and how much of
+//! their metadata and messages, is chosen by a `Filter`; where the entries
+//! go by a `Sink`, such as a `FileSink`.
+//!
+//! ```rust,ignore
+//! let filter = std::env::var("GRPC_BINARY_LOG_FILTER")?.parse()?;
+//! let logger = Logger::new(filter, FileSink::create("/tmp/grpc.binlog")?);
+//!
+//! let greeter = Stats::new(GreeterServer::new(Greet), logger);
+//! ```
+
+#![deny(warnings, missing_debug_implementations)]
+
+extern crate base64;
+#[cfg(test)]
+extern crate futures;
+extern crate http;
+#[macro_use]
+extern crate log;
+extern crate percent_encoding;
+extern crate prost;
+extern crate prost_types;
+#[cfg(test)]
+extern crate tokio;
+extern crate tower_grpc;
+#[cfg(test)]
+extern crate tower_service;
+
+/// Types generated from `grpc/binlog/v1/binarylog.proto`.
+pub mod proto {
+    #![allow(dead_code)]
+    #![allow(unused_imports)]
+    include!(concat!(env!("OUT_DIR"), "/grpc.binarylog.v1.rs"));
+}
+
+mod binary_log;
+mod filter;
+mod sink;
+
+pub use binary_log::Logger;
+pub use filter::{Filter, Limits, ParseError};
+pub use sink::{FileSink, Sink};
@@ -0,0 +1,60 @@
+use proto::GrpcLogEntry;
+
+use prost::Message;
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Receives binary log entries.
+pub trait Sink: Send + Sync + 'static {
+    /// Write a single entry.
+    fn write(&self, entry: &GrpcLogEntry) -> io::Result<()>;
+}
+
+/// Appends entries to a file, each prefixed by its varint-encoded length.
+///
+/// Every entry is written as it is logged, so that the file is complete
+/// even if the process is later killed.
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+// ===== impl FileSink =====
+
+impl FileSink {
+    /// Open the file at `path` for appending, creating it if needed.
+    pub fn create<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink::new(file))
+    }
+
+    /// Write entries to `file`.
+    pub fn new(file: File) -> Self {
+        FileSink {
+            file: Mutex::new(file),
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn write(&self, entry: &GrpcLogEntry) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(entry.encoded_len() + 10);
+        entry
+            .encode_length_delimited(&mut buf)
+            .expect("Vec has enough capacity");
+
+        self.file.lock().unwrap().write_all(&buf)
+    }
+}
+
+impl fmt::Debug for FileSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FileSink").finish()
+    }
+}
@@ -62,6 +62,26 @@ impl ServiceGenerator {
             .line("let inner = grpc::Grpc::new(inner);")
             .line("Self { inner }");
 
+        imp.new_fn("with_service_config")
+            .doc("Create a client applying the matching method config to each call.")
+            .vis("pub")
+            .arg("inner", "T")
+            .arg("config", "grpc::ServiceConfig")
+            .ret("Self")
+            .line("let inner = grpc::Grpc::with_service_config(inner, config);")
+            .line("Self { inner }");
+
+        imp.new_fn("stats_handler")
+            .doc("Report the events of each call to `handler`.")
+            .vis("pub")
+            .generic("H")
+            .bound("H", "grpc::StatsHandler")
+            .arg_self()
+            .arg("handler", "H")
+            .ret("Self")
+            .line("let inner = self.inner.stats_handler(handler);")
+            .line("Self { inner }");
+
         imp.new_fn("poll_ready")
             .doc("Poll whether this client is ready to send another request.")
             .generic("R")
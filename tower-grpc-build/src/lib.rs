@@ -3,10 +3,14 @@ extern crate heck;
 extern crate prost_build;
 
 mod client;
+mod mock;
 mod server;
 
+use std::env;
+use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 use heck::CamelCase;
 
@@ -15,11 +19,15 @@ pub struct Config {
     prost: prost_build::Config,
     build_client: bool,
     build_server: bool,
+    build_mock: bool,
+    default_unimplemented: bool,
+    file_descriptor_set: Option<String>,
 }
 
 struct ServiceGenerator {
     client: Option<client::ServiceGenerator>,
     server: Option<server::ServiceGenerator>,
+    mock: Option<mock::ServiceGenerator>,
     root_scope: codegen::Scope,
 }
 
@@ -35,6 +43,13 @@ impl Config {
 
             // Disable server code gen by default
             build_server: false,
+
+            // Disable mock code gen by default
+            build_mock: false,
+
+            default_unimplemented: false,
+
+            file_descriptor_set: None,
         }
     }
 
@@ -55,6 +70,46 @@ impl Config {
         self
     }
 
+    /// Enable generation of mock service implementations
+    ///
+    /// For each service trait `Foo`, a `mock::MockFoo` implementation is
+    /// generated, which answers each method with canned responses, `Status`
+    /// errors or closures, and records the calls made to it. As the mocks
+    /// implement the server service traits, this also enables server code
+    /// generation.
+    pub fn enable_mock(&mut self, enable: bool) -> &mut Self {
+        self.build_mock = enable;
+        self
+    }
+
+    /// Generate service traits whose methods default to failing with
+    /// `Code::Unimplemented`
+    ///
+    /// Rather than each having an associated future type, the methods then
+    /// return boxed futures, and boxed streams for server streaming methods.
+    /// This way a service only implements the methods it supports, and
+    /// keeps compiling when methods are added to its proto definition.
+    pub fn default_unimplemented(&mut self, enable: bool) -> &mut Self {
+        self.default_unimplemented = enable;
+        self
+    }
+
+    /// Also write the encoded `FileDescriptorSet` of the compiled protos to
+    /// `OUT_DIR`.
+    ///
+    /// The set is written to `{name}.bin`, along with a `{name}.rs` file
+    /// defining a `FILE_DESCRIPTOR_SET: &[u8]` constant that embeds it. The
+    /// set includes all imported files, so it describes exactly the schema
+    /// the code was generated from.
+    ///
+    /// ```rust,ignore
+    /// include!(concat!(env!("OUT_DIR"), "/helloworld_descriptor.rs"));
+    /// ```
+    pub fn file_descriptor_set(&mut self, name: &str) -> &mut Self {
+        self.file_descriptor_set = Some(name.to_string());
+        self
+    }
+
     /// Generate code
     pub fn build<P>(&mut self, protos: &[P], includes: &[P]) -> io::Result<()>
     where
@@ -65,8 +120,17 @@ impl Config {
         } else {
             None
         };
-        let server = if self.build_server {
-            Some(server::ServiceGenerator)
+        let server = if self.build_server || self.build_mock {
+            Some(server::ServiceGenerator {
+                default_unimplemented: self.default_unimplemented,
+            })
+        } else {
+            None
+        };
+        let mock = if self.build_mock {
+            Some(mock::ServiceGenerator {
+                default_unimplemented: self.default_unimplemented,
+            })
         } else {
             None
         };
@@ -75,11 +139,70 @@ impl Config {
         self.prost.service_generator(Box::new(ServiceGenerator {
             client,
             server,
+            mock,
             root_scope: codegen::Scope::new(),
         }));
 
-        self.prost.compile_protos(protos, includes)
+        self.prost.compile_protos(protos, includes)?;
+
+        if let Some(ref name) = self.file_descriptor_set {
+            write_file_descriptor_set(name, protos, includes)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `protoc` to write the encoded `FileDescriptorSet` of `protos` to
+/// `OUT_DIR`, along with the Rust source referencing it.
+fn write_file_descriptor_set<P>(name: &str, protos: &[P], includes: &[P]) -> io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let out_dir: PathBuf = env::var_os("OUT_DIR")
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "OUT_DIR environment variable is not set",
+            )
+        })?
+        .into();
+
+    let bin_name = format!("{}.bin", name);
+
+    // Mirror the arguments `prost-build` passes to `protoc`.
+    let mut cmd = Command::new(prost_build::protoc());
+    cmd.arg("--include_imports")
+        .arg("--include_source_info")
+        .arg("-o")
+        .arg(out_dir.join(&bin_name));
+
+    for include in includes {
+        cmd.arg("-I").arg(include.as_ref());
     }
+
+    cmd.arg("-I").arg(prost_build::protoc_include());
+
+    for proto in protos {
+        cmd.arg(proto.as_ref());
+    }
+
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("protoc failed: {}", String::from_utf8_lossy(&output.stderr)),
+        ));
+    }
+
+    let source = format!(
+        "/// The encoded `FileDescriptorSet` this code was generated from.\n\
+         pub const FILE_DESCRIPTOR_SET: &'static [u8] = \
+         include_bytes!(concat!(env!(\"OUT_DIR\"), \"/{}\"));\n",
+        bin_name
+    );
+
+    fs::write(out_dir.join(format!("{}.rs", name)), source)
 }
 
 impl prost_build::ServiceGenerator for ServiceGenerator {
@@ -95,6 +218,9 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
         if let Some(ref mut server_generator) = self.server {
             server_generator.generate(&service, &mut self.root_scope);
         }
+        if let Some(ref mut mock_generator) = self.mock {
+            mock_generator.generate(&service, &mut self.root_scope);
+        }
     }
 
     fn finalize(&mut self, buf: &mut String) {
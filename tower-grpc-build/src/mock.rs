@@ -0,0 +1,144 @@
+use super::ImportType;
+use codegen;
+use prost_build;
+
+/// Generates mock service code
+pub struct ServiceGenerator {
+    /// Whether the service traits were generated with default
+    /// `Unimplemented` methods, and so without associated types.
+    pub default_unimplemented: bool,
+}
+
+// ===== impl ServiceGenerator =====
+
+impl ServiceGenerator {
+    pub fn generate(&self, service: &prost_build::Service, scope: &mut codegen::Scope) {
+        self.define(service, scope);
+    }
+
+    fn define(&self, service: &prost_build::Service, scope: &mut codegen::Scope) {
+        // Create scope that contains the generated mock code.
+        let scope = scope
+            .get_or_new_module("mock")
+            .vis("pub")
+            .import("::tower_grpc::codegen::mock", "*")
+            .scope();
+
+        self.import_message_types(service, scope);
+        self.define_mock_struct(service, scope);
+        self.define_mock_impl(service, scope);
+        self.define_service_impl(service, scope);
+    }
+
+    fn import_message_types(&self, service: &prost_build::Service, scope: &mut codegen::Scope) {
+        for method in &service.methods {
+            scope.import_type(&method.input_type, 1);
+            scope.import_type(&method.output_type, 1);
+        }
+    }
+
+    fn define_mock_struct(&self, service: &prost_build::Service, scope: &mut codegen::Scope) {
+        let mut mock = codegen::Struct::new(&mock_name(service));
+        mock.vis("pub")
+            .derive("Debug")
+            .derive("Clone")
+            .derive("Default")
+            .doc(&format!(
+                "A mock implementation of `{}`.\n\n\
+                 Each method is answered by the `tower_grpc::mock::Method` field of \
+                 the same name, which records the calls made to it. Clones share \
+                 these fields.",
+                service.name
+            ));
+
+        for method in &service.methods {
+            let ty = format!(
+                "grpc::Method<{}, {}>",
+                input_type(method),
+                output_type(method)
+            );
+
+            mock.field(&format!("pub {}", method.name), &ty);
+        }
+
+        scope.push_struct(mock);
+    }
+
+    fn define_mock_impl(&self, service: &prost_build::Service, scope: &mut codegen::Scope) {
+        scope
+            .new_impl(&mock_name(service))
+            .new_fn("new")
+            .vis("pub")
+            .ret("Self")
+            .line("Self::default()");
+    }
+
+    fn define_service_impl(&self, service: &prost_build::Service, scope: &mut codegen::Scope) {
+        let imp = scope
+            .new_impl(&mock_name(service))
+            .impl_trait(&format!("super::server::{}", service.name));
+
+        for method in &service.methods {
+            let upper_name = ::to_upper_camel(&method.proto_name);
+            let input = ::unqualified(&method.input_type, &method.input_proto_type, 1);
+            let response_future = ::server::boxed_future_type(method, 1);
+
+            // Service traits with default methods have no associated types.
+            if !self.default_unimplemented {
+                if method.server_streaming {
+                    imp.associate_type(
+                        &format!("{}Stream", upper_name),
+                        &::server::boxed_stream_type(method, 1),
+                    );
+                }
+
+                imp.associate_type(&format!("{}Future", upper_name), &response_future);
+            }
+
+            let request_type = if method.client_streaming {
+                format!("grpc::Request<grpc::Streaming<{}>>", input)
+            } else {
+                format!("grpc::Request<{}>", input)
+            };
+
+            let answer = match (method.client_streaming, method.server_streaming) {
+                (false, false) => "unary",
+                (false, true) => "server_streaming",
+                (true, false) => "client_streaming",
+                (true, true) => "streaming",
+            };
+
+            imp.new_fn(&method.name)
+                .arg_mut_self()
+                .arg("request", &request_type)
+                .ret(&response_future)
+                .line(&format!("self.{}.{}(request)", method.name, answer));
+        }
+    }
+}
+
+fn mock_name(service: &prost_build::Service) -> String {
+    format!("Mock{}", service.name)
+}
+
+/// The message type recorded by the mock of `method`.
+fn input_type(method: &prost_build::Method) -> String {
+    let ty = ::unqualified(&method.input_type, &method.input_proto_type, 1);
+
+    if method.client_streaming {
+        format!("Vec<{}>", ty)
+    } else {
+        ty
+    }
+}
+
+/// The message type the mock of `method` answers with.
+fn output_type(method: &prost_build::Method) -> String {
+    let ty = ::unqualified(&method.output_type, &method.output_proto_type, 1);
+
+    if method.server_streaming {
+        format!("Vec<{}>", ty)
+    } else {
+        ty
+    }
+}
@@ -4,7 +4,11 @@ use comments_to_rustdoc;
 use prost_build;
 
 /// Generates service code
-pub struct ServiceGenerator;
+pub struct ServiceGenerator {
+    /// Whether service trait methods return boxed futures and default to
+    /// `Unimplemented`.
+    pub default_unimplemented: bool,
+}
 
 impl ServiceGenerator {
     /// Generate the gRPC server code
@@ -58,7 +62,7 @@ macro_rules! try_ready {
             for method in &service.methods {
                 methods.import_type(&method.input_type, 2);
 
-                if !method.server_streaming {
+                if !method.server_streaming || self.default_unimplemented {
                     methods.import_type(&method.output_type, 2);
                 }
 
@@ -77,6 +81,39 @@ macro_rules! try_ready {
         for method in &service.methods {
             let name = &method.name;
             let upper_name = ::to_upper_camel(&method.proto_name);
+
+            for &ty in [&method.input_type, &method.output_type].iter() {
+                if ::should_import(ty) {
+                    let (path, ty) = ::super_import(ty, 1);
+
+                    scope.import(&path, &ty);
+                }
+            }
+
+            let input_type = ::unqualified(&method.input_type, &method.input_proto_type, 1);
+
+            let request_type = if method.client_streaming {
+                format!("grpc::Request<grpc::Streaming<{}>>", input_type)
+            } else {
+                format!("grpc::Request<{}>", input_type)
+            };
+
+            if self.default_unimplemented {
+                service_trait
+                    .new_fn(&name)
+                    .arg_mut_self()
+                    .arg("request", &request_type)
+                    .ret(&boxed_future_type(method, 1))
+                    .doc(&comments_to_rustdoc(&method.comments))
+                    .line("let _ = request;")
+                    .line(&format!(
+                        "Box::new(futures::err(grpc::Status::new(grpc::Code::Unimplemented, concat!(\"unimplemented method: \", {}))))",
+                        ::method_path(service, method)
+                    ));
+
+                continue;
+            }
+
             let future_bound;
 
             if method.server_streaming {
@@ -107,22 +144,6 @@ macro_rules! try_ready {
                 .associated_type(&future_name)
                 .bound(&future_bound);
 
-            for &ty in [&method.input_type, &method.output_type].iter() {
-                if ::should_import(ty) {
-                    let (path, ty) = ::super_import(ty, 1);
-
-                    scope.import(&path, &ty);
-                }
-            }
-
-            let input_type = ::unqualified(&method.input_type, &method.input_proto_type, 1);
-
-            let request_type = if method.client_streaming {
-                format!("grpc::Request<grpc::Streaming<{}>>", input_type)
-            } else {
-                format!("grpc::Request<{}>", input_type)
-            };
-
             service_trait
                 .new_fn(&name)
                 .arg_mut_self()
@@ -157,6 +178,11 @@ macro_rules! try_ready {
             .ret("Self")
             .line(format!("Self {{ {} }}", lower_name));
 
+        scope.raw(&format!(
+            "impl<T: {}> grpc::NamedService for {}<T> {{\n    const NAME: &'static str = \"{}.{}\";\n}}",
+            service.name, name, service.package, service.proto_name
+        ));
+
         let response_type = format!("http::Response<{}::ResponseBody<T>>", lower_name);
 
         // Implement service trait
@@ -516,7 +542,14 @@ macro_rules! try_ready {
         let mut request = codegen::Type::new("grpc::Request");
         let mut response = codegen::Type::new("grpc::Response");
         let request_stream = streaming_input_type(&method, 3);
-        let response_stream = format!("T::{}Stream", &upper_name);
+        let (response_stream, response_future) = if self.default_unimplemented {
+            (boxed_stream_type(method, 3), boxed_future_type(method, 3))
+        } else {
+            (
+                format!("T::{}Stream", &upper_name),
+                format!("T::{}Future", &upper_name),
+            )
+        };
 
         match (method.client_streaming, method.server_streaming) {
             (false, false) => {
@@ -566,7 +599,7 @@ macro_rules! try_ready {
             .bound("T", &service.name)
             .associate_type("Response", response)
             .associate_type("Error", "grpc::Status")
-            .associate_type("Future", &format!("T::{}Future", &upper_name));
+            .associate_type("Future", &response_future);
 
         imp.new_fn("poll_ready")
             .arg_mut_self()
@@ -693,6 +726,26 @@ fn response_body_kind(service: &prost_build::Service) -> String {
     ret
 }
 
+/// The type returned by `method` when the service trait methods are boxed.
+pub fn boxed_future_type(method: &prost_build::Method, level: usize) -> String {
+    let response = if method.server_streaming {
+        boxed_stream_type(method, level)
+    } else {
+        ::unqualified(&method.output_type, &method.output_proto_type, level)
+    };
+
+    format!("grpc::BoxFuture<grpc::Response<{}>>", response)
+}
+
+/// The response stream type of `method` when the service trait methods are
+/// boxed.
+pub fn boxed_stream_type(method: &prost_build::Method, level: usize) -> String {
+    format!(
+        "grpc::BoxStream<{}>",
+        ::unqualified(&method.output_type, &method.output_proto_type, level)
+    )
+}
+
 fn streaming_input_type(method: &prost_build::Method, level: usize) -> String {
     format!(
         "grpc::Streaming<{}>",
@@ -8,15 +8,13 @@ extern crate prost;
 extern crate tokio;
 extern crate tower_grpc;
 extern crate tower_hyper;
-extern crate tower_request_modifier;
 extern crate tower_service;
-extern crate tower_util;
 
 use futures::Future;
 use hyper::client::connect::{Destination, HttpConnector};
+use tower_grpc::client::Channel;
 use tower_grpc::Request;
 use tower_hyper::{client, util};
-use tower_util::MakeService;
 
 pub mod hello_world {
     include!(concat!(env!("OUT_DIR"), "/helloworld.rs"));
@@ -30,22 +28,15 @@ pub fn main() {
     let dst = Destination::try_from_uri(uri.clone()).unwrap();
     let connector = util::Connector::new(HttpConnector::new(4));
     let settings = client::Builder::new().http2_only(true).clone();
-    let mut make_client = client::Connect::new(connector, settings);
+    let make_client = client::Connect::new(connector, settings);
 
-    let say_hello = make_client
-        .make_service(dst)
-        .map_err(|e| panic!("connect error: {:?}", e))
-        .and_then(move |conn| {
-            use hello_world::client::Greeter;
+    let conn = Channel::new(uri, dst, make_client).expect("valid origin");
 
-            let conn = tower_request_modifier::Builder::new()
-                .set_origin(uri)
-                .build(conn)
-                .unwrap();
+    use hello_world::client::Greeter;
 
-            // Wait until the client is ready...
-            Greeter::new(conn).ready()
-        })
+    // Wait until the client is ready...
+    let say_hello = Greeter::new(conn)
+        .ready()
         .and_then(|mut client| {
             use hello_world::HelloRequest;
 
@@ -16,6 +16,7 @@ use hello_world::{server, HelloReply, HelloRequest};
 
 use futures::{future, Future, Stream};
 use tokio::net::TcpListener;
+use tower_grpc::server::{ConnectionInfo, WithConnectionInfo};
 use tower_grpc::{Request, Response};
 use tower_hyper::server::{Http, Server};
 
@@ -26,7 +27,7 @@ impl server::Greeter for Greet {
     type SayHelloFuture = future::FutureResult<Response<HelloReply>, tower_grpc::Status>;
 
     fn say_hello(&mut self, request: Request<HelloRequest>) -> Self::SayHelloFuture {
-        println!("REQUEST = {:?}", request);
+        println!("REQUEST = {:?} from {:?}", request, request.remote_addr());
 
         let response = Response::new(HelloReply {
             message: "Zomg, it works!".to_string(),
@@ -41,8 +42,6 @@ pub fn main() {
 
     let new_service = server::GreeterServer::new(Greet);
 
-    let mut server = Server::new(new_service);
-
     let http = Http::new().http2_only(true).clone();
 
     let addr = "[::1]:50051".parse().unwrap();
@@ -55,6 +54,9 @@ pub fn main() {
                 return Err(e);
             }
 
+            let info = ConnectionInfo::new(sock.peer_addr()?, sock.local_addr()?);
+            let mut server = Server::new(WithConnectionInfo::new(new_service.clone(), info));
+
             let serve = server.serve_with(sock, http.clone());
             tokio::spawn(serve.map_err(|e| error!("hyper error: {:?}", e)));
 
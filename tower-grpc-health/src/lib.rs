@@ -0,0 +1,290 @@
+//! A `grpc.health.v1.Health` service implementation.
+//!
+//! The service is split in two halves: a `HealthService`, to be served like
+//! any other generated service, and a `HealthReporter` handle used by the
+//! application to update the serving status of each of its services.
+//!
+//! ```rust,ignore
+//! let (mut reporter, service) = tower_grpc_health::health_reporter();
+//! reporter.set_serving("helloworld.Greeter");
+//!
+//! let health = HealthServer::new(service);
+//! ```
+
+#![deny(warnings, missing_debug_implementations)]
+
+#[macro_use]
+extern crate futures;
+#[macro_use]
+extern crate log;
+extern crate prost;
+extern crate tower_grpc;
+
+/// Types generated from `grpc/health/v1/health.proto`.
+pub mod proto {
+    #![allow(dead_code)]
+    #![allow(unused_imports)]
+    include!(concat!(env!("OUT_DIR"), "/grpc.health.v1.rs"));
+}
+
+pub use proto::health_check_response::ServingStatus;
+pub use proto::server::HealthServer;
+
+use proto::{server, HealthCheckRequest, HealthCheckResponse};
+
+use futures::sync::mpsc;
+use futures::{future, Async, Poll, Stream};
+use tower_grpc::{Code, Request, Response, Status};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Updates the serving status reported by a `HealthService`.
+#[derive(Debug, Clone)]
+pub struct HealthReporter {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// Implements the `grpc.health.v1.Health` service.
+#[derive(Debug, Clone)]
+pub struct HealthService {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// Stream of serving status updates returned by `Watch`.
+#[derive(Debug)]
+pub struct Watch {
+    rx: mpsc::UnboundedReceiver<ServingStatus>,
+    last: Option<ServingStatus>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    statuses: HashMap<String, ServingStatus>,
+    watchers: Vec<Watcher>,
+}
+
+#[derive(Debug)]
+struct Watcher {
+    service: String,
+    tx: mpsc::UnboundedSender<ServingStatus>,
+}
+
+/// Create a new `HealthReporter` and the `HealthService` it reports to.
+///
+/// The overall server health, named by the empty string, is initially
+/// reported as `SERVING`.
+pub fn health_reporter() -> (HealthReporter, HealthService) {
+    let mut statuses = HashMap::new();
+    statuses.insert(String::new(), ServingStatus::Serving);
+
+    let shared = Arc::new(Mutex::new(Shared {
+        statuses,
+        watchers: Vec::new(),
+    }));
+
+    let reporter = HealthReporter {
+        shared: shared.clone(),
+    };
+    let service = HealthService { shared };
+
+    (reporter, service)
+}
+
+// ===== impl HealthReporter =====
+
+impl HealthReporter {
+    /// Report `service` as `SERVING`.
+    pub fn set_serving(&mut self, service: &str) {
+        self.set_service_status(service, ServingStatus::Serving);
+    }
+
+    /// Report `service` as `NOT_SERVING`.
+    pub fn set_not_serving(&mut self, service: &str) {
+        self.set_service_status(service, ServingStatus::NotServing);
+    }
+
+    /// Set the serving status of `service`, notifying any watchers.
+    pub fn set_service_status(&mut self, service: &str, status: ServingStatus) {
+        debug!("health status of {:?} set to {:?}", service, status);
+
+        let mut shared = self.shared.lock().unwrap();
+        shared.statuses.insert(service.to_string(), status);
+        shared.notify(service, status);
+    }
+
+    /// Forget the serving status of `service`.
+    ///
+    /// `Check` calls for it will fail with `NOT_FOUND`, and watchers will
+    /// see `SERVICE_UNKNOWN`.
+    pub fn clear_service_status(&mut self, service: &str) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.statuses.remove(service).is_some() {
+            shared.notify(service, ServingStatus::ServiceUnknown);
+        }
+    }
+}
+
+// ===== impl HealthService =====
+
+impl HealthService {
+    fn status(&self, service: &str) -> Option<ServingStatus> {
+        self.shared.lock().unwrap().statuses.get(service).cloned()
+    }
+}
+
+impl server::Health for HealthService {
+    type CheckFuture = future::FutureResult<Response<HealthCheckResponse>, Status>;
+    type WatchStream = Watch;
+    type WatchFuture = future::FutureResult<Response<Self::WatchStream>, Status>;
+
+    fn check(&mut self, request: Request<HealthCheckRequest>) -> Self::CheckFuture {
+        let service = &request.get_ref().service;
+
+        match self.status(service) {
+            Some(status) => future::ok(Response::new(HealthCheckResponse {
+                status: status as i32,
+            })),
+            None => future::err(Status::new(
+                Code::NotFound,
+                format!("unknown service: {:?}", service),
+            )),
+        }
+    }
+
+    fn watch(&mut self, request: Request<HealthCheckRequest>) -> Self::WatchFuture {
+        let service = request.into_inner().service;
+        let (tx, rx) = mpsc::unbounded();
+
+        let mut shared = self.shared.lock().unwrap();
+
+        // The current status is always sent first.
+        let current = shared
+            .statuses
+            .get(&service)
+            .cloned()
+            .unwrap_or(ServingStatus::ServiceUnknown);
+        let _ = tx.unbounded_send(current);
+
+        shared.prune();
+        shared.watchers.push(Watcher { service, tx });
+
+        future::ok(Response::new(Watch { rx, last: None }))
+    }
+}
+
+// ===== impl Watch =====
+
+impl Stream for Watch {
+    type Item = HealthCheckResponse;
+    type Error = Status;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let status = match try_ready!(self.rx.poll().map_err(|_| unreachable_status())) {
+                Some(status) => status,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            // Only changes are pushed to the client.
+            if self.last == Some(status) {
+                continue;
+            }
+
+            self.last = Some(status);
+
+            let response = HealthCheckResponse {
+                status: status as i32,
+            };
+            return Ok(Async::Ready(Some(response)));
+        }
+    }
+}
+
+fn unreachable_status() -> Status {
+    Status::new(Code::Internal, "health status channel failed")
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    fn notify(&mut self, service: &str, status: ServingStatus) {
+        self.prune();
+        self.watchers
+            .retain(|w| w.service != service || w.tx.unbounded_send(status).is_ok());
+    }
+
+    /// Drop the watchers of all services whose stream has gone away.
+    fn prune(&mut self) {
+        self.watchers.retain(|w| !w.tx.is_closed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use proto::server::Health;
+
+    fn request(service: &str) -> Request<HealthCheckRequest> {
+        Request::new(HealthCheckRequest {
+            service: service.to_string(),
+        })
+    }
+
+    #[test]
+    fn check_reports_status() {
+        let (mut reporter, mut service) = health_reporter();
+
+        let status = service.check(request("")).wait().unwrap();
+        assert_eq!(status.get_ref().status, ServingStatus::Serving as i32);
+
+        let err = service.check(request("foo")).wait().unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        reporter.set_not_serving("foo");
+        let status = service.check(request("foo")).wait().unwrap();
+        assert_eq!(status.get_ref().status, ServingStatus::NotServing as i32);
+    }
+
+    #[test]
+    fn watch_sends_changes() {
+        let (mut reporter, mut service) = health_reporter();
+
+        let watch = service.watch(request("foo")).wait().unwrap().into_inner();
+        let mut watch = watch.wait();
+
+        let first = watch.next().unwrap().unwrap();
+        assert_eq!(first.status, ServingStatus::ServiceUnknown as i32);
+
+        reporter.set_serving("foo");
+        // Repeated statuses are not sent again.
+        reporter.set_serving("foo");
+        reporter.set_not_serving("foo");
+
+        let second = watch.next().unwrap().unwrap();
+        assert_eq!(second.status, ServingStatus::Serving as i32);
+
+        let third = watch.next().unwrap().unwrap();
+        assert_eq!(third.status, ServingStatus::NotServing as i32);
+    }
+
+    #[test]
+    fn drops_closed_watchers_of_other_services() {
+        let (mut reporter, mut service) = health_reporter();
+
+        let watch = service.watch(request("bar")).wait().unwrap();
+        drop(watch);
+
+        // `bar` never changes, but its closed watcher is dropped anyway.
+        reporter.set_serving("foo");
+        assert!(service.shared.lock().unwrap().watchers.is_empty());
+
+        let _watch = service.watch(request("foo")).wait().unwrap();
+        let watch = service.watch(request("bar")).wait().unwrap();
+        drop(watch);
+
+        let _watch = service.watch(request("baz")).wait().unwrap();
+        assert_eq!(service.shared.lock().unwrap().watchers.len(), 2);
+    }
+}
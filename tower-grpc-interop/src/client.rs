@@ -1,7 +1,6 @@
 extern crate console;
 #[macro_use]
 extern crate clap;
-extern crate domain;
 extern crate futures;
 extern crate http;
 extern crate pretty_env_logger;
@@ -125,7 +124,7 @@ enum ClientError {
 
 #[derive(Debug)]
 enum DnsError {
-    ResolveError(domain::resolv::error::Error),
+    ResolveError(Box<dyn Error + Send + Sync>),
     NoHosts,
 }
 
@@ -159,11 +158,6 @@ where
     }
 }
 
-impl From<domain::resolv::error::Error> for DnsError {
-    fn from(dns: domain::resolv::error::Error) -> Self {
-        DnsError::ResolveError(dns)
-    }
-}
 // pub struct TestResults {
 //     name: String,
 //     assertions: Vec<TestAssertion>,
@@ -915,52 +909,32 @@ impl ServerInfo {
         matches: &clap::ArgMatches<'a>,
         core: &mut reactor::Core,
     ) -> Result<Self, ClientError> {
-        use domain::bits::DNameBuf;
-        use domain::resolv::{lookup, Resolver};
-
-        let handle = core.handle();
-        // XXX this could probably look neater if only the DNS query was run in
-        //     a future...
-        let ip_future = future::result(value_t!(matches, "server_host", IpAddr))
-            .from_err::<ClientError>()
-            .or_else(|_| {
-                future::result(value_t!(matches, "server_host", DNameBuf))
-                    .from_err::<ClientError>()
-                    .and_then(move |name| {
-                        let resolver = Resolver::new(&handle);
-                        lookup::lookup_host(resolver, name)
-                            .from_err::<ClientError>()
-                            .and_then(|response| {
-                                response
-                                    .iter()
-                                    .next()
-                                    .ok_or(ClientError::from(DnsError::NoHosts))
-                            })
-                    })
-                    .from_err::<ClientError>()
-            })
-            .from_err::<ClientError>();
-        let info = ip_future.and_then(move |ip| {
-            future::result(value_t!(matches, "server_port", u16))
-                .from_err::<ClientError>()
-                .and_then(move |port| {
-                    let addr = SocketAddr::new(ip, port);
-                    info!("server_address={:?};", addr);
-                    let host = matches.value_of("server_host").expect(
-                        "`server_host` argument was not present, clap \
-                         should have already validated it was present.",
-                    );
-                    future::result(Uri::from_str(&format!("http://{}:{}", host, port)))
-                        .from_err::<ClientError>()
-                        .map(move |uri| ServerInfo {
-                            addr,
-                            uri,
-                            hostname_override: None, // unimplemented
-                        })
-                })
-                .from_err::<ClientError>()
-        });
-        core.run(info)
+        use tower_grpc::client::resolve::{Dns, Resolve};
+
+        let host = value_t!(matches, "server_host", String)?;
+        let port = value_t!(matches, "server_port", u16)?;
+
+        let ip = match IpAddr::from_str(&host) {
+            Ok(ip) => ip,
+            Err(_) => core
+                .run(Dns::new().resolve(&host, port))
+                .map_err(DnsError::ResolveError)?
+                .into_iter()
+                .next()
+                .ok_or(DnsError::NoHosts)?
+                .ip(),
+        };
+
+        let addr = SocketAddr::new(ip, port);
+        info!("server_address={:?};", addr);
+
+        let uri = Uri::from_str(&format!("http://{}:{}", host, port))?;
+
+        Ok(ServerInfo {
+            addr,
+            uri,
+            hostname_override: None, // unimplemented
+        })
     }
 }
 
@@ -7,13 +7,10 @@ extern crate pretty_env_logger;
 extern crate prost;
 extern crate tokio;
 extern crate tower_grpc;
-extern crate tower_h2;
 
 use futures::{future, stream, Future, Stream};
-use tokio::executor::DefaultExecutor;
-use tokio::net::TcpListener;
+use tower_grpc::server::Builder;
 use tower_grpc::{Code, Request, Response, Status};
-use tower_h2::Server;
 
 mod pb {
     #![allow(dead_code)]
@@ -56,8 +53,8 @@ impl pb::server::TestService for Test {
     type UnimplementedCallFuture = GrpcFut<pb::Empty>;
 
     /// One empty request followed by one empty response.
-    fn empty_call(&mut self, _request: Request<pb::Empty>) -> Self::EmptyCallFuture {
-        eprintln!("empty_call");
+    fn empty_call(&mut self, request: Request<pb::Empty>) -> Self::EmptyCallFuture {
+        eprintln!("empty_call from {:?}", request.remote_addr());
         Box::new(future::ok(Response::new(pb::Empty::default())))
     }
 
@@ -206,27 +203,12 @@ fn main() {
 
     let port = value_t!(matches, "port", u16).expect("port argument");
 
-    let new_service = pb::server::TestServiceServer::new(Test);
-
-    let h2_settings = Default::default();
-    let mut h2 = Server::new(new_service, h2_settings, DefaultExecutor::current());
-
     let addr = format!("0.0.0.0:{}", port).parse().unwrap();
-    let bind = TcpListener::bind(&addr).expect("bind");
-
-    let serve = bind
-        .incoming()
-        .for_each(move |sock| {
-            if let Err(e) = sock.set_nodelay(true) {
-                return Err(e);
-            }
-
-            let serve = h2.serve(sock);
-            tokio::spawn(serve.map_err(|e| error!("h2 error: {:?}", e)));
-
-            Ok(())
-        })
-        .map_err(|e| eprintln!("accept error: {}", e));
+    let serve = Builder::new()
+        .add_service(pb::server::TestServiceServer::new(Test))
+        .serve(&addr)
+        .expect("bind")
+        .map_err(|e| error!("server error: {}", e));
 
     eprintln!("grpc interop server listening on {}", addr);
     tokio::run(serve)
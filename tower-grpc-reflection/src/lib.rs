@@ -0,0 +1,533 @@
+//! A `grpc.reflection.v1alpha.ServerReflection` service implementation.
+//!
+//! The service answers queries from tools such as `grpcurl` using encoded
+//! `FileDescriptorProto`s registered by the application.
+//!
+//! ```rust,ignore
+//! let service = tower_grpc_reflection::Builder::new()
+//!     .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)?
+//!     .build()?;
+//!
+//! let reflection = ServerReflectionServer::new(service);
+//! ```
+
+#![deny(warnings, missing_debug_implementations)]
+
+extern crate bytes;
+extern crate futures;
+#[macro_use]
+extern crate log;
+extern crate prost;
+extern crate prost_types;
+extern crate tower_grpc;
+
+/// Types generated from `grpc/reflection/v1alpha/reflection.proto`.
+pub mod proto {
+    #![allow(dead_code)]
+    #![allow(unused_imports)]
+    include!(concat!(env!("OUT_DIR"), "/grpc.reflection.v1alpha.rs"));
+}
+
+pub use proto::server::ServerReflectionServer;
+
+use proto::server_reflection_request::MessageRequest;
+use proto::server_reflection_response::MessageResponse;
+use proto::{
+    server, ErrorResponse, ExtensionNumberResponse, ExtensionRequest, FileDescriptorResponse,
+    ListServiceResponse, ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+};
+
+use bytes::Bytes;
+use futures::{future, Stream};
+use prost::Message;
+use prost_types::{DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use tower_grpc::{Code, Request, Response, Status, Streaming};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::{error, fmt};
+
+/// Builds a `ReflectionService` from encoded file descriptors.
+#[derive(Debug, Default)]
+pub struct Builder {
+    files: Vec<FileDescriptorProto>,
+}
+
+/// Implements the `grpc.reflection.v1alpha.ServerReflection` service.
+#[derive(Debug, Clone)]
+pub struct ReflectionService {
+    index: Arc<Index>,
+}
+
+/// Errors produced while building a `ReflectionService`.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+}
+
+#[derive(Debug)]
+enum ErrorKind {
+    Decode(prost::DecodeError),
+    MissingName,
+    DuplicateFile(String),
+    DuplicateSymbol(String),
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    /// Encoded descriptors and their dependencies, by file name.
+    files: HashMap<String, FileEntry>,
+    /// Fully-qualified symbol names to the file declaring them.
+    symbols: HashMap<String, String>,
+    /// Extended type names to the extension numbers and declaring files.
+    extensions: HashMap<String, Vec<(i32, String)>>,
+    /// Fully-qualified names of all services.
+    services: Vec<String>,
+}
+
+#[derive(Debug)]
+struct FileEntry {
+    encoded: Bytes,
+    dependencies: Vec<String>,
+}
+
+// ===== impl Builder =====
+
+impl Builder {
+    /// Returns a new `Builder` with no registered files.
+    pub fn new() -> Self {
+        Builder::default()
+    }
+
+    /// Register every file in an encoded `FileDescriptorSet`, such as the
+    /// output of `protoc --descriptor_set_out`.
+    pub fn register_encoded_file_descriptor_set(
+        &mut self,
+        encoded: &[u8],
+    ) -> Result<&mut Self, Error> {
+        let set = FileDescriptorSet::decode(encoded).map_err(Error::decode)?;
+        self.files.extend(set.file);
+        Ok(self)
+    }
+
+    /// Register a single encoded `FileDescriptorProto`.
+    pub fn register_encoded_file_descriptor(&mut self, encoded: &[u8]) -> Result<&mut Self, Error> {
+        let file = FileDescriptorProto::decode(encoded).map_err(Error::decode)?;
+        self.files.push(file);
+        Ok(self)
+    }
+
+    /// Index the registered files and build the service.
+    pub fn build(&mut self) -> Result<ReflectionService, Error> {
+        let mut index = Index::default();
+
+        for file in self.files.drain(..) {
+            index.add_file(file)?;
+        }
+
+        index.services.sort();
+
+        Ok(ReflectionService {
+            index: Arc::new(index),
+        })
+    }
+}
+
+// ===== impl ReflectionService =====
+
+impl server::ServerReflection for ReflectionService {
+    type ServerReflectionInfoStream =
+        Box<dyn Stream<Item = ServerReflectionResponse, Error = Status> + Send>;
+    type ServerReflectionInfoFuture =
+        future::FutureResult<Response<Self::ServerReflectionInfoStream>, Status>;
+
+    fn server_reflection_info(
+        &mut self,
+        request: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Self::ServerReflectionInfoFuture {
+        let index = self.index.clone();
+
+        // Files already sent on this stream are not sent again.
+        let mut sent = HashSet::new();
+
+        let responses = request
+            .into_inner()
+            .map(move |request| index.respond(request, &mut sent));
+
+        future::ok(Response::new(Box::new(responses)))
+    }
+}
+
+// ===== impl Index =====
+
+impl Index {
+    fn add_file(&mut self, file: FileDescriptorProto) -> Result<(), Error> {
+        let name = match file.name {
+            Some(ref name) => name.clone(),
+            None => return Err(Error::new(ErrorKind::MissingName)),
+        };
+
+        if self.files.contains_key(&name) {
+            return Err(Error::new(ErrorKind::DuplicateFile(name)));
+        }
+
+        let package = file.package().to_string();
+
+        for service in &file.service {
+            let service_name = qualify(&package, service.name());
+            for method in &service.method {
+                self.add_symbol(qualify(&service_name, method.name()), &name)?;
+            }
+            self.add_symbol(service_name.clone(), &name)?;
+            self.services.push(service_name);
+        }
+
+        for message in &file.message_type {
+            self.add_message(&package, message, &name)?;
+        }
+
+        for enum_type in &file.enum_type {
+            self.add_symbol(qualify(&package, enum_type.name()), &name)?;
+        }
+
+        for extension in &file.extension {
+            self.add_extension(&package, extension, &name)?;
+        }
+
+        let mut encoded = Vec::with_capacity(file.encoded_len());
+        file.encode(&mut encoded)
+            .expect("Vec<u8> provides enough capacity");
+
+        trace!("registered file descriptor {:?}", name);
+
+        let entry = FileEntry {
+            encoded: encoded.into(),
+            dependencies: file.dependency,
+        };
+        self.files.insert(name, entry);
+
+        Ok(())
+    }
+
+    fn add_message(
+        &mut self,
+        prefix: &str,
+        message: &DescriptorProto,
+        file: &str,
+    ) -> Result<(), Error> {
+        let message_name = qualify(prefix, message.name());
+
+        for nested in &message.nested_type {
+            self.add_message(&message_name, nested, file)?;
+        }
+
+        for enum_type in &message.enum_type {
+            self.add_symbol(qualify(&message_name, enum_type.name()), file)?;
+        }
+
+        for extension in &message.extension {
+            self.add_extension(&message_name, extension, file)?;
+        }
+
+        self.add_symbol(message_name, file)
+    }
+
+    fn add_extension(
+        &mut self,
+        prefix: &str,
+        extension: &FieldDescriptorProto,
+        file: &str,
+    ) -> Result<(), Error> {
+        let extendee = extension.extendee().trim_start_matches('.').to_string();
+
+        self.extensions
+            .entry(extendee)
+            .or_insert_with(Vec::new)
+            .push((extension.number(), file.to_string()));
+
+        self.add_symbol(qualify(prefix, extension.name()), file)
+    }
+
+    fn add_symbol(&mut self, symbol: String, file: &str) -> Result<(), Error> {
+        if self.symbols.contains_key(&symbol) {
+            return Err(Error::new(ErrorKind::DuplicateSymbol(symbol)));
+        }
+
+        self.symbols.insert(symbol, file.to_string());
+        Ok(())
+    }
+
+    fn respond(
+        &self,
+        request: ServerReflectionRequest,
+        sent: &mut HashSet<String>,
+    ) -> ServerReflectionResponse {
+        let message_response = match request.message_request {
+            Some(MessageRequest::FileByFilename(ref filename)) => {
+                self.file_response(filename, sent)
+            }
+            Some(MessageRequest::FileContainingSymbol(ref symbol)) => {
+                match self.symbols.get(symbol.trim_start_matches('.')) {
+                    Some(filename) => self.file_response(filename, sent),
+                    None => not_found(format!("symbol not found: {}", symbol)),
+                }
+            }
+            Some(MessageRequest::FileContainingExtension(ExtensionRequest {
+                ref containing_type,
+                extension_number,
+            })) => {
+                let filename = self
+                    .extensions
+                    .get(containing_type.trim_start_matches('.'))
+                    .and_then(|exts| exts.iter().find(|&&(num, _)| num == extension_number));
+
+                match filename {
+                    Some(&(_, ref filename)) => self.file_response(filename, sent),
+                    None => not_found(format!(
+                        "extension not found: {}({})",
+                        containing_type, extension_number
+                    )),
+                }
+            }
+            Some(MessageRequest::AllExtensionNumbersOfType(ref type_name)) => {
+                let base_type_name = type_name.trim_start_matches('.');
+
+                if !self.symbols.contains_key(base_type_name) {
+                    not_found(format!("type not found: {}", type_name))
+                } else {
+                    let extension_number = self
+                        .extensions
+                        .get(base_type_name)
+                        .map(|exts| exts.iter().map(|&(num, _)| num).collect())
+                        .unwrap_or_else(Vec::new);
+
+                    MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                        base_type_name: base_type_name.to_string(),
+                        extension_number,
+                    })
+                }
+            }
+            Some(MessageRequest::ListServices(_)) => {
+                let service = self
+                    .services
+                    .iter()
+                    .map(|name| ServiceResponse { name: name.clone() })
+                    .collect();
+
+                MessageResponse::ListServicesResponse(ListServiceResponse { service })
+            }
+            None => MessageResponse::ErrorResponse(ErrorResponse {
+                error_code: Code::InvalidArgument as i32,
+                error_message: "missing message_request".to_string(),
+            }),
+        };
+
+        ServerReflectionResponse {
+            valid_host: request.host.clone(),
+            original_request: Some(request),
+            message_response: Some(message_response),
+        }
+    }
+
+    /// Respond with `filename` and its transitive dependencies that were not
+    /// yet sent.
+    fn file_response(&self, filename: &str, sent: &mut HashSet<String>) -> MessageResponse {
+        if !self.files.contains_key(filename) {
+            return not_found(format!("file not found: {}", filename));
+        }
+
+        let mut file_descriptor_proto = Vec::new();
+        let mut pending = vec![filename.to_string()];
+
+        while let Some(name) = pending.pop() {
+            // The requested file itself is always sent.
+            if name != filename && sent.contains(&name) {
+                continue;
+            }
+
+            if let Some(entry) = self.files.get(&name) {
+                file_descriptor_proto.push(entry.encoded.to_vec());
+                pending.extend(
+                    entry
+                        .dependencies
+                        .iter()
+                        .filter(|dep| !sent.contains(*dep))
+                        .cloned(),
+                );
+            }
+
+            sent.insert(name);
+        }
+
+        MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+            file_descriptor_proto,
+        })
+    }
+}
+
+fn qualify(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+fn not_found(error_message: String) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: Code::NotFound as i32,
+        error_message,
+    })
+}
+
+// ===== impl Error =====
+
+impl Error {
+    fn new(kind: ErrorKind) -> Self {
+        Error { kind }
+    }
+
+    fn decode(err: prost::DecodeError) -> Self {
+        Error::new(ErrorKind::Decode(err))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Decode(ref err) => write!(f, "invalid file descriptor: {}", err),
+            ErrorKind::MissingName => f.write_str("file descriptor has no name"),
+            ErrorKind::DuplicateFile(ref name) => write!(f, "duplicate file: {}", name),
+            ErrorKind::DuplicateSymbol(ref name) => write!(f, "duplicate symbol: {}", name),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self.kind {
+            ErrorKind::Decode(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_types::{MethodDescriptorProto, ServiceDescriptorProto};
+
+    fn encoded_file(name: &str, dependency: Vec<String>) -> Vec<u8> {
+        let file = FileDescriptorProto {
+            name: Some(name.to_string()),
+            package: Some("test.pkg".to_string()),
+            dependency,
+            message_type: vec![DescriptorProto {
+                name: Some(format!("{}Message", name.trim_end_matches(".proto"))),
+                ..Default::default()
+            }],
+            service: vec![ServiceDescriptorProto {
+                name: Some(format!("{}Service", name.trim_end_matches(".proto"))),
+                method: vec![MethodDescriptorProto {
+                    name: Some("Call".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let mut buf = Vec::new();
+        file.encode(&mut buf).unwrap();
+        buf
+    }
+
+    fn request(message_request: MessageRequest) -> ServerReflectionRequest {
+        ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(message_request),
+        }
+    }
+
+    fn build() -> ReflectionService {
+        Builder::new()
+            .register_encoded_file_descriptor(&encoded_file("a.proto", vec![]))
+            .unwrap()
+            .register_encoded_file_descriptor(&encoded_file("b.proto", vec!["a.proto".into()]))
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn list_services() {
+        let service = build();
+        let res = service.index.respond(
+            request(MessageRequest::ListServices(String::new())),
+            &mut HashSet::new(),
+        );
+
+        match res.message_response {
+            Some(MessageResponse::ListServicesResponse(list)) => {
+                let names: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+                assert_eq!(names, vec!["test.pkg.aService", "test.pkg.bService"]);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn file_containing_symbol_includes_dependencies() {
+        let service = build();
+        let mut sent = HashSet::new();
+
+        let symbol = MessageRequest::FileContainingSymbol("test.pkg.bService.Call".into());
+        let res = service.index.respond(request(symbol), &mut sent);
+
+        match res.message_response {
+            Some(MessageResponse::FileDescriptorResponse(files)) => {
+                assert_eq!(files.file_descriptor_proto.len(), 2);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+
+        // Dependencies already sent on this stream are skipped.
+        let symbol = MessageRequest::FileContainingSymbol("test.pkg.bMessage".into());
+        let res = service.index.respond(request(symbol), &mut sent);
+
+        match res.message_response {
+            Some(MessageResponse::FileDescriptorResponse(files)) => {
+                assert_eq!(files.file_descriptor_proto.len(), 1);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_symbol() {
+        let service = build();
+        let symbol = MessageRequest::FileContainingSymbol("test.pkg.Nope".into());
+        let res = service.index.respond(request(symbol), &mut HashSet::new());
+
+        match res.message_response {
+            Some(MessageResponse::ErrorResponse(err)) => {
+                assert_eq!(err.error_code, Code::NotFound as i32);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_files_are_rejected() {
+        let file = encoded_file("a.proto", vec![]);
+        let err = Builder::new()
+            .register_encoded_file_descriptor(&file)
+            .unwrap()
+            .register_encoded_file_descriptor(&file)
+            .unwrap()
+            .build()
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "duplicate file: a.proto");
+    }
+}
@@ -9,7 +9,7 @@ use self::sealed::Sealed;
 use error::Error;
 use Status;
 
-type BytesBuf = <Bytes as IntoBuf>::Buf;
+pub(crate) type BytesBuf = <Bytes as IntoBuf>::Buf;
 
 /// A "trait alias" for `tower_http_service::Body` with bounds required by
 /// tower-grpc.
@@ -62,6 +62,10 @@ pub struct BoxBody {
 
 struct MapBody<B>(B);
 
+/// A body with no data or trailers.
+#[derive(Debug)]
+struct Empty;
+
 // ===== impl BoxBody =====
 
 impl BoxBody {
@@ -78,6 +82,11 @@ impl BoxBody {
     {
         BoxBody::new(Box::new(MapBody(inner)))
     }
+
+    /// Create a new, empty `BoxBody`.
+    pub fn empty() -> Self {
+        BoxBody::new(Box::new(Empty))
+    }
 }
 
 impl HttpBody for BoxBody {
@@ -127,6 +136,25 @@ where
     }
 }
 
+// ===== impl Empty =====
+
+impl HttpBody for Empty {
+    type Item = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        true
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        Ok(None.into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        Ok(None.into())
+    }
+}
+
 mod sealed {
     pub trait Sealed {}
 }
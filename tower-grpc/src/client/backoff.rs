@@ -0,0 +1,70 @@
+use rand::{self, Rng};
+
+use std::cmp;
+use std::time::Duration;
+
+/// Exponential backoff, as used for reconnecting.
+///
+/// See https://github.com/grpc/grpc/blob/master/doc/connection-backoff.md
+#[derive(Debug, Clone)]
+pub(crate) struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    jitter: f64,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max,
+            multiplier: 1.6,
+            jitter: 0.2,
+            current: initial,
+        }
+    }
+
+    /// Returns the delay before the next attempt, and grows the backoff.
+    pub fn next_delay(&mut self) -> Duration {
+        let factor = rand::thread_rng().gen_range(1.0 - self.jitter, 1.0 + self.jitter);
+        let delay = scale(self.current, factor);
+
+        self.current = cmp::min(scale(self.current, self.multiplier), self.max);
+        delay
+    }
+
+    /// Start over from the initial backoff, after a successful attempt.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+pub(crate) fn scale(duration: Duration, factor: f64) -> Duration {
+    let secs = (duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9) * factor;
+    let secs = secs.max(0.0);
+
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(3));
+
+        let first = backoff.next_delay();
+        assert!(first >= Duration::from_millis(800) && first <= Duration::from_millis(1200));
+
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+        assert!(backoff.next_delay() <= Duration::from_millis(3600));
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(1200));
+    }
+}
@@ -0,0 +1,332 @@
+//! Spreading calls across a set of endpoints.
+
+use super::channel::{self, Channel, Connectivity, ResponseFuture};
+use body::BoxBody;
+use error::Error;
+use {Code, Status};
+
+use futures::{Async, Poll};
+use http::{self, Uri};
+use tower_service::Service;
+use tower_util::MakeService;
+
+use std::fmt;
+
+/// How `Balance` picks the endpoint for each call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Cycle through every ready endpoint.
+    ///
+    /// All endpoints are connected to at once.
+    RoundRobin,
+    /// Send every call to the first ready endpoint, in the order endpoints
+    /// were added.
+    ///
+    /// Endpoints are connected to one at a time, moving on to the next one
+    /// only when the previous ones fail.
+    PickFirst,
+}
+
+/// A `GrpcService` balancing calls over a set of endpoints.
+///
+/// Each endpoint is a `Channel`. Endpoints that fail to connect are skipped
+/// until they manage to reconnect. If none of the endpoints can be used, the
+/// balancer stays ready and calls fail with `Code::Unavailable`, except for
+/// calls made with `WaitForReady`, which wait on one of the endpoints.
+///
+/// Like `Channel`, a `Balance` can be passed directly to generated clients.
+pub struct Balance<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    make: M,
+    channel: channel::Builder,
+    policy: Policy,
+    endpoints: Vec<Channel<M, T>>,
+    /// Round-robin position.
+    next: usize,
+    /// The endpoint picked by `poll_ready`, used by the following `call`.
+    ready: Option<usize>,
+}
+
+// ===== impl Balance =====
+
+impl<M, T> Balance<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>> + Clone,
+{
+    /// Create a new `Balance` without any endpoints.
+    ///
+    /// `make` is cloned for every endpoint.
+    pub fn new(policy: Policy, make: M) -> Self {
+        Balance::with_channel_builder(policy, channel::Builder::new(), make)
+    }
+
+    /// Create a new `Balance`, configuring the channel to each endpoint with
+    /// `builder`.
+    pub fn with_channel_builder(policy: Policy, builder: channel::Builder, make: M) -> Self {
+        Balance {
+            make,
+            channel: builder,
+            policy,
+            endpoints: Vec::new(),
+            next: 0,
+            ready: None,
+        }
+    }
+
+    /// Add an endpoint at `origin`, connected to with `target`.
+    ///
+    /// Does nothing if there is already an endpoint with the same origin.
+    pub fn add_endpoint(&mut self, origin: Uri, target: T) -> Result<(), Error> {
+        if self.contains(&origin) {
+            return Ok(());
+        }
+
+        let channel = self
+            .channel
+            .clone()
+            .build(origin, target, self.make.clone())?;
+
+        self.endpoints.push(channel);
+        Ok(())
+    }
+
+    /// Remove the endpoint at `origin`, returning whether there was one.
+    pub fn remove_endpoint(&mut self, origin: &Uri) -> bool {
+        match self
+            .endpoints
+            .iter()
+            .position(|channel| same_origin(&channel.origin(), origin))
+        {
+            Some(idx) => {
+                debug!("removing endpoint {}", origin);
+                self.endpoints.remove(idx);
+                self.ready = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether there is an endpoint at `origin`.
+    pub fn contains(&self, origin: &Uri) -> bool {
+        self.endpoints
+            .iter()
+            .any(|channel| same_origin(&channel.origin(), origin))
+    }
+
+    /// Returns the origin and connectivity state of every endpoint.
+    pub fn endpoints(&self) -> Vec<(Uri, Connectivity)> {
+        self.endpoints
+            .iter()
+            .map(|channel| (channel.origin(), channel.connectivity()))
+            .collect()
+    }
+}
+
+impl<M, T, B> Service<http::Request<BoxBody>> for Balance<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>, Response = http::Response<B>>,
+    M::MakeError: Into<Error>,
+    M::Error: Into<Error>,
+    T: Clone,
+{
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<M, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.ready = None;
+
+        let len = self.endpoints.len();
+        let start = match self.policy {
+            Policy::RoundRobin => self.next,
+            Policy::PickFirst => 0,
+        };
+
+        let mut pending = false;
+
+        for i in 0..len {
+            let idx = (start + i) % len;
+            let channel = &mut self.endpoints[idx];
+
+            match channel.poll_ready()? {
+                Async::Ready(()) if channel.connectivity() == Connectivity::Ready => {
+                    if self.ready.is_none() {
+                        self.ready = Some(idx);
+                    }
+                }
+                // Ejected until it manages to reconnect.
+                Async::Ready(()) => {}
+                Async::NotReady => pending = true,
+            }
+
+            if self.policy == Policy::PickFirst && (self.ready.is_some() || pending) {
+                // Later endpoints are only used if this one fails.
+                break;
+            }
+        }
+
+        match self.ready {
+            Some(idx) => {
+                self.next = idx + 1;
+                Ok(Async::Ready(()))
+            }
+            None if pending => Ok(Async::NotReady),
+            // Fail fast when no endpoint can be used.
+            None => Ok(Async::Ready(())),
+        }
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        match self.ready.take() {
+            Some(idx) => self.endpoints[idx].call(request),
+            None if !self.endpoints.is_empty() && channel::wait_for_ready(&request) => {
+                // Wait on the first endpoint that isn't failing, if any.
+                let idx = self
+                    .endpoints
+                    .iter()
+                    .position(|channel| channel.connectivity() != Connectivity::TransientFailure)
+                    .unwrap_or(self.next % self.endpoints.len());
+                self.endpoints[idx].call(request)
+            }
+            None => {
+                let message = if self.endpoints.is_empty() {
+                    "no endpoints to balance over"
+                } else {
+                    "no endpoint is ready"
+                };
+                ResponseFuture::failed(Status::new(Code::Unavailable, message))
+            }
+        }
+    }
+}
+
+impl<M, T> fmt::Debug for Balance<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Balance")
+            .field("policy", &self.policy)
+            .field("endpoints", &self.endpoints)
+            .finish()
+    }
+}
+
+fn same_origin(a: &Uri, b: &Uri) -> bool {
+    a.scheme_part() == b.scheme_part() && a.authority_part() == b.authority_part()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, FutureResult};
+    use futures::Future;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Connects to any target not starting with "bad".
+    #[derive(Clone)]
+    struct MockConnect;
+
+    struct MockConnection(&'static str);
+
+    impl Service<&'static str> for MockConnect {
+        type Response = MockConnection;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, target: &'static str) -> Self::Future {
+            if target.starts_with("bad") {
+                future::err(Status::new(Code::Unavailable, "refused"))
+            } else {
+                future::ok(MockConnection(target))
+            }
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for MockConnection {
+        type Response = http::Response<&'static str>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            future::ok(http::Response::new(self.0))
+        }
+    }
+
+    fn balancer(policy: Policy, targets: &[&'static str]) -> Balance<MockConnect, &'static str> {
+        let mut balance = Balance::new(policy, MockConnect);
+        for target in targets {
+            let origin = format!("http://{}", target).parse().unwrap();
+            balance.add_endpoint(origin, *target).unwrap();
+        }
+        balance
+    }
+
+    fn calls(balance: &mut Balance<MockConnect, &'static str>, n: usize) -> Vec<&'static str> {
+        let mut rt = Runtime::new().unwrap();
+        (0..n)
+            .map(|_| {
+                rt.block_on(future::lazy(|| {
+                    try_ready!(balance.poll_ready());
+                    balance.call(http::Request::new(BoxBody::empty())).poll()
+                }))
+                .map(|response| response.into_body())
+                .unwrap_or("failed")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_skips_failed_endpoints() {
+        let mut balance = balancer(Policy::RoundRobin, &["a", "bad", "b"]);
+
+        assert_eq!(calls(&mut balance, 4), ["a", "b", "a", "b"]);
+
+        let ejected = balance
+            .endpoints()
+            .into_iter()
+            .filter(|&(_, state)| state == Connectivity::TransientFailure)
+            .count();
+        assert_eq!(ejected, 1);
+    }
+
+    #[test]
+    fn pick_first_uses_first_working_endpoint() {
+        let mut balance = balancer(Policy::PickFirst, &["bad", "a", "b"]);
+
+        assert_eq!(calls(&mut balance, 3), ["a", "a", "a"]);
+
+        // "b" is never connected to.
+        let (_, state) = balance.endpoints()[2].clone();
+        assert_eq!(state, Connectivity::Idle);
+    }
+
+    #[test]
+    fn fails_fast_without_endpoints() {
+        let mut balance = balancer(Policy::RoundRobin, &["bad"]);
+        assert_eq!(calls(&mut balance, 1), ["failed"]);
+
+        let mut balance = balancer(Policy::RoundRobin, &[]);
+        assert_eq!(calls(&mut balance, 1), ["failed"]);
+    }
+
+    #[test]
+    fn endpoints_can_be_removed() {
+        let mut balance = balancer(Policy::RoundRobin, &["a", "b"]);
+        assert!(balance.remove_endpoint(&"http://a".parse().unwrap()));
+        assert!(!balance.contains(&"http://a".parse().unwrap()));
+
+        assert_eq!(calls(&mut balance, 2), ["b", "b"]);
+    }
+}
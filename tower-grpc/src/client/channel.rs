@@ -0,0 +1,774 @@
+//! A client connection to a single endpoint.
+
+use super::backoff::Backoff;
+use super::config::{self, MethodConfig};
+use super::WaitForReady;
+use body::BoxBody;
+use error::Error;
+use {Code, Status};
+
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use http::uri::{Authority, Scheme};
+use http::{self, Uri};
+use tokio_timer::Delay;
+use tower_service::Service;
+use tower_util::MakeService;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A gRPC client connection to one endpoint.
+///
+/// A `Channel` fills in the scheme and authority of outgoing requests from
+/// its origin, so it can be passed directly to generated clients. The
+/// connection is made with `make` the first time the channel is polled for
+/// readiness, and made again if it is lost.
+///
+/// While reconnecting after a failure, the channel stays ready, and calls
+/// fail right away with `Code::Unavailable`, unless they are made with
+/// `WaitForReady`. Those calls wait for the connection instead, until their
+/// deadline if they have one.
+pub struct Channel<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    scheme: Scheme,
+    authority: Authority,
+    /// The origin set on requests, if not the channel's own.
+    request_origin: Option<(Scheme, Authority)>,
+    /// Shared with calls waiting for the connection.
+    inner: Arc<Mutex<Inner<M, T>>>,
+}
+
+/// Configures a `Channel`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    request_origin: Option<Uri>,
+}
+
+/// The connectivity state of a `Channel`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Not connected, and not trying to.
+    Idle,
+    /// A connection is being made.
+    Connecting,
+    /// Connected, possibly waiting on the connection to be ready.
+    Ready,
+    /// The last connection attempt failed, and the channel is waiting to
+    /// try again.
+    TransientFailure,
+}
+
+/// Response future returned by `Channel`.
+pub struct ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    kind: Kind<M, T>,
+}
+
+enum Kind<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    Inner(<M::Service as Service<http::Request<BoxBody>>>::Future),
+    /// A call sent once the connection is ready for it: a wait-for-ready
+    /// call, or one whose readiness was taken by such a call.
+    Waiting {
+        inner: Arc<Mutex<Inner<M, T>>>,
+        request: Option<http::Request<BoxBody>>,
+        deadline: Option<Delay>,
+        fail_fast: bool,
+    },
+    Error(Option<Status>),
+}
+
+struct Inner<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    make: M,
+    target: T,
+    backoff: Backoff,
+    state: State<M::Service, M::Future>,
+    /// Whether the connected service was polled ready, and not called since.
+    ready: bool,
+    last_error: Option<Status>,
+    /// Tasks of the calls waiting for the connection.
+    ///
+    /// The connection only wakes the last task to poll it, so the others are
+    /// woken whenever its state changes.
+    waiters: Vec<Task>,
+}
+
+enum State<S, F> {
+    Idle,
+    Connecting(F),
+    Connected(S),
+    Backoff(Delay),
+}
+
+// ===== impl Builder =====
+
+impl Builder {
+    /// Create a new `Builder` with the default reconnect backoff.
+    pub fn new() -> Self {
+        Builder {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(120),
+            request_origin: None,
+        }
+    }
+
+    /// Set the delay before the first reconnect attempt. Defaults to 1
+    /// second.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the maximum delay between reconnect attempts. Defaults to 120
+    /// seconds.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Send requests to `origin`, instead of to the origin of the channel.
+    ///
+    /// This lets several channels to the addresses of one name, such as the
+    /// endpoints a target resolves to, keep that name as the authority of
+    /// their requests.
+    pub fn request_origin(mut self, origin: Uri) -> Self {
+        self.request_origin = Some(origin);
+        self
+    }
+
+    /// Build a `Channel` to `origin`, connecting to `target` with `make`.
+    ///
+    /// `origin` must have a scheme and an authority, such as
+    /// `http://example.com:50051`, as must the request origin if set.
+    pub fn build<M, T>(self, origin: Uri, target: T, make: M) -> Result<Channel<M, T>, Error>
+    where
+        M: MakeService<T, http::Request<BoxBody>>,
+    {
+        let (scheme, authority) = split_origin(origin)?;
+        let request_origin = match self.request_origin {
+            Some(origin) => Some(split_origin(origin)?),
+            None => None,
+        };
+
+        let inner = Inner {
+            make,
+            target,
+            backoff: Backoff::new(self.initial_backoff, self.max_backoff),
+            state: State::Idle,
+            ready: false,
+            last_error: None,
+            waiters: Vec::new(),
+        };
+
+        Ok(Channel {
+            scheme,
+            authority,
+            request_origin,
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+/// Splits `origin` into its scheme and authority, which it must have.
+fn split_origin(origin: Uri) -> Result<(Scheme, Authority), Error> {
+    let mut parts = origin.into_parts();
+
+    match (parts.scheme.take(), parts.authority.take()) {
+        (Some(scheme), Some(authority)) => Ok((scheme, authority)),
+        _ => Err("origin must have a scheme and an authority".into()),
+    }
+}
+
+// ===== impl Channel =====
+
+impl<M, T> Channel<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    /// Create a `Channel` to `origin` with the default configuration.
+    ///
+    /// See `Builder::build`.
+    pub fn new(origin: Uri, target: T, make: M) -> Result<Self, Error> {
+        Builder::new().build(origin, target, make)
+    }
+
+    /// Returns the current connectivity state.
+    pub fn connectivity(&self) -> Connectivity {
+        match self.inner.lock().unwrap().state {
+            State::Idle => Connectivity::Idle,
+            State::Connecting(_) => Connectivity::Connecting,
+            State::Connected(_) => Connectivity::Ready,
+            State::Backoff(_) => Connectivity::TransientFailure,
+        }
+    }
+
+    /// Returns the origin of the channel.
+    ///
+    /// Requests are sent to it, unless the channel was built with another
+    /// `Builder::request_origin`.
+    pub fn origin(&self) -> Uri {
+        Uri::builder()
+            .scheme(self.scheme.clone())
+            .authority(self.authority.clone())
+            .path_and_query("/")
+            .build()
+            .expect("scheme and authority are valid")
+    }
+
+    fn set_origin<B>(&self, request: &mut http::Request<B>) {
+        let (scheme, authority) = match self.request_origin {
+            Some((ref scheme, ref authority)) => (scheme, authority),
+            None => (&self.scheme, &self.authority),
+        };
+
+        let mut parts = request.uri().clone().into_parts();
+        parts.scheme = Some(scheme.clone());
+        parts.authority = Some(authority.clone());
+
+        if parts.path_and_query.is_none() {
+            parts.path_and_query = Some("/".parse().expect("valid path"));
+        }
+
+        *request.uri_mut() = Uri::from_parts(parts).expect("origin and path are valid");
+    }
+}
+
+impl<M, T, B> Service<http::Request<BoxBody>> for Channel<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>, Response = http::Response<B>>,
+    M::MakeError: Into<Error>,
+    M::Error: Into<Error>,
+    T: Clone,
+{
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<M, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // Fail fast while waiting to reconnect.
+        self.inner.lock().unwrap().poll_ready(true)
+    }
+
+    fn call(&mut self, mut request: http::Request<BoxBody>) -> Self::Future {
+        self.set_origin(&mut request);
+
+        let mut inner = self.inner.lock().unwrap();
+        let wait = wait_for_ready(&request);
+        let connected = match inner.state {
+            State::Connected(_) => true,
+            _ => false,
+        };
+
+        let kind = if connected && inner.ready {
+            Kind::Inner(inner.call(request))
+        } else if connected || wait {
+            // Either waiting for a connection, or for the readiness a
+            // waiting call took since `poll_ready`.
+            trace!("waiting for a ready connection");
+            let deadline = request
+                .headers()
+                .get(config::TIMEOUT)
+                .and_then(config::decode_timeout)
+                .map(|timeout| Delay::new(Instant::now() + timeout));
+
+            Kind::Waiting {
+                inner: self.inner.clone(),
+                request: Some(request),
+                deadline,
+                fail_fast: !wait,
+            }
+        } else {
+            Kind::Error(Some(inner.unavailable()))
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl<M, T> fmt::Debug for Channel<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("origin", &self.origin())
+            .field("connectivity", &self.connectivity())
+            .finish()
+    }
+}
+
+/// Returns whether `request` waits for a connection rather than failing
+/// fast, either from its `WaitForReady` option or from its method config.
+pub(crate) fn wait_for_ready<B>(request: &http::Request<B>) -> bool {
+    let extensions = request.extensions();
+
+    if let Some(&WaitForReady(wait)) = extensions.get::<WaitForReady>() {
+        return wait;
+    }
+
+    extensions
+        .get::<Arc<MethodConfig>>()
+        .and_then(|config| config.wait_for_ready())
+        .unwrap_or(false)
+}
+
+// ===== impl Inner =====
+
+impl<M, T> Inner<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+    M::MakeError: Into<Error>,
+    M::Error: Into<Error>,
+    T: Clone,
+{
+    /// Drives the connection until it is ready.
+    ///
+    /// With `fail_fast`, returns ready while waiting to reconnect after a
+    /// failure, so calls fail right away.
+    fn poll_ready(&mut self, fail_fast: bool) -> Poll<(), Error> {
+        loop {
+            let next = match self.state {
+                State::Idle => match self.make.poll_ready() {
+                    Ok(Async::Ready(())) => {
+                        trace!("connecting");
+                        State::Connecting(self.make.make_service(self.target.clone()))
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => backoff(&mut self.backoff, &mut self.last_error, e.into()),
+                },
+                State::Connecting(ref mut fut) => match fut.poll() {
+                    Ok(Async::Ready(service)) => {
+                        trace!("connected");
+                        self.backoff.reset();
+                        self.last_error = None;
+                        State::Connected(service)
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => backoff(&mut self.backoff, &mut self.last_error, e.into()),
+                },
+                State::Connected(ref mut service) => match service.poll_ready() {
+                    Ok(Async::Ready(())) => {
+                        self.ready = true;
+                        wake(&mut self.waiters);
+                        return Ok(Async::Ready(()));
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        // Reconnect right away; backoff only applies to
+                        // failed connection attempts.
+                        let e: Error = e.into();
+                        debug!("connection lost: {}", e);
+                        self.last_error = Some(Status::new(
+                            Code::Unavailable,
+                            format!("connection lost: {}", e),
+                        ));
+                        self.ready = false;
+                        State::Idle
+                    }
+                },
+                State::Backoff(ref mut delay) => match delay.poll() {
+                    Ok(Async::NotReady) if fail_fast => return Ok(Async::Ready(())),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => State::Idle,
+                    Err(e) => return Err(e.into()),
+                },
+            };
+
+            self.state = next;
+            wake(&mut self.waiters);
+        }
+    }
+}
+
+impl<M, T> Inner<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    /// Calls the connected service, which must have been polled ready.
+    fn call(
+        &mut self,
+        request: http::Request<BoxBody>,
+    ) -> <M::Service as Service<http::Request<BoxBody>>>::Future {
+        debug_assert!(self.ready, "called without being ready");
+        self.ready = false;
+
+        match self.state {
+            State::Connected(ref mut service) => service.call(request),
+            _ => unreachable!("channel is ready without a connection"),
+        }
+    }
+
+    /// Registers the current task to be woken when the connection changes.
+    fn park(&mut self) {
+        if !self.waiters.iter().any(|task| task.will_notify_current()) {
+            self.waiters.push(task::current());
+        }
+    }
+
+    fn unavailable(&self) -> Status {
+        match self.last_error {
+            Some(ref status) => status.clone(),
+            None => Status::new(Code::Unavailable, "channel is not connected"),
+        }
+    }
+}
+
+fn wake(waiters: &mut Vec<Task>) {
+    for task in waiters.drain(..) {
+        task.notify();
+    }
+}
+
+fn backoff<S, F>(
+    backoff: &mut Backoff,
+    last_error: &mut Option<Status>,
+    err: Error,
+) -> State<S, F> {
+    let delay = backoff.next_delay();
+    debug!("connect failed, retrying in {:?}: {}", delay, err);
+
+    *last_error = Some(Status::new(
+        Code::Unavailable,
+        format!("connect failed: {}", err),
+    ));
+    State::Backoff(Delay::new(Instant::now() + delay))
+}
+
+// ===== impl ResponseFuture =====
+
+impl<M, T> ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    /// A response future that fails with `status` without sending anything.
+    pub(crate) fn failed(status: Status) -> Self {
+        ResponseFuture {
+            kind: Kind::Error(Some(status)),
+        }
+    }
+}
+
+impl<M, T, B> Future for ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>, Response = http::Response<B>>,
+    M::MakeError: Into<Error>,
+    M::Error: Into<Error>,
+    T: Clone,
+{
+    type Item = http::Response<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.kind {
+                Kind::Inner(ref mut fut) => return fut.poll().map_err(Into::into),
+                Kind::Waiting {
+                    ref inner,
+                    ref mut request,
+                    ref mut deadline,
+                    fail_fast,
+                } => {
+                    if let Some(ref mut deadline) = *deadline {
+                        if let Async::Ready(()) = deadline.poll()? {
+                            let status = Status::new(
+                                Code::DeadlineExceeded,
+                                "deadline expired while waiting for a connection",
+                            );
+                            return Err(status.into());
+                        }
+                    }
+
+                    let mut inner = inner.lock().unwrap();
+                    if let Async::NotReady = inner.poll_ready(fail_fast)? {
+                        inner.park();
+                        return Ok(Async::NotReady);
+                    }
+
+                    // Failing fast, the channel is ready while reconnecting.
+                    if !inner.ready {
+                        request.take();
+                        return Err(inner.unavailable().into());
+                    }
+
+                    let request = request.take().expect("polled after complete");
+                    Kind::Inner(inner.call(request))
+                }
+                Kind::Error(ref mut status) => {
+                    return Err(status.take().expect("polled after complete").into());
+                }
+            };
+
+            self.kind = next;
+        }
+    }
+}
+
+impl<M, T> Drop for ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn drop(&mut self) {
+        // This call may have been the one the connection would wake, so
+        // another waiting call has to poll it instead.
+        if let Kind::Waiting {
+            ref inner,
+            request: Some(_),
+            ..
+        } = self.kind
+        {
+            if let Ok(mut inner) = inner.lock() {
+                wake(&mut inner.waiters);
+            }
+        }
+    }
+}
+
+impl<M, T> fmt::Debug for ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let waiting = match self.kind {
+            Kind::Waiting { .. } => true,
+            _ => false,
+        };
+
+        f.debug_struct("channel::ResponseFuture")
+            .field("waiting", &waiting)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Never;
+    use futures::future::{self, FutureResult};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use tokio::runtime::current_thread::Runtime;
+
+    #[derive(Clone, Default)]
+    struct MockConnect {
+        connects: Arc<AtomicUsize>,
+        broken: Arc<AtomicBool>,
+    }
+
+    struct MockConnection {
+        broken: Arc<AtomicBool>,
+    }
+
+    impl Service<()> for MockConnect {
+        type Response = MockConnection;
+        type Error = Never;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            future::ok(MockConnection {
+                broken: self.broken.clone(),
+            })
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for MockConnection {
+        type Response = http::Response<Uri>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            if self.broken.swap(false, Ordering::SeqCst) {
+                return Err(Status::new(Code::Unavailable, "broken"));
+            }
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            future::ok(http::Response::new(request.uri().clone()))
+        }
+    }
+
+    /// Refuses the given number of connections, then connects.
+    #[derive(Clone)]
+    struct Refusing(Arc<AtomicUsize>);
+
+    impl Service<()> for Refusing {
+        type Response = MockConnection;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            if self.0.load(Ordering::SeqCst) > 0 {
+                self.0.fetch_sub(1, Ordering::SeqCst);
+                return future::err(Status::new(Code::Unavailable, "refused"));
+            }
+            future::ok(MockConnection {
+                broken: Arc::new(AtomicBool::new(false)),
+            })
+        }
+    }
+
+    fn refusing(refusals: usize, backoff: Duration) -> Channel<Refusing, ()> {
+        Builder::new()
+            .initial_backoff(backoff)
+            .max_backoff(backoff)
+            .build(
+                "http://example.com".parse().unwrap(),
+                (),
+                Refusing(Arc::new(AtomicUsize::new(refusals))),
+            )
+            .unwrap()
+    }
+
+    fn request() -> http::Request<BoxBody> {
+        http::Request::builder()
+            .uri("/pkg.Service/Method")
+            .body(BoxBody::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn connects_lazily_and_sets_origin() {
+        let connect = MockConnect::default();
+        let origin = "http://example.com:50051".parse().unwrap();
+        let mut channel = Channel::new(origin, (), connect.clone()).unwrap();
+
+        assert_eq!(channel.connectivity(), Connectivity::Idle);
+        assert_eq!(connect.connects.load(Ordering::SeqCst), 0);
+
+        let uri = future::lazy(|| {
+            try_ready!(channel.poll_ready());
+            channel.call(request()).poll()
+        })
+        .wait()
+        .unwrap()
+        .into_body();
+
+        assert_eq!(uri, "http://example.com:50051/pkg.Service/Method");
+        assert_eq!(channel.connectivity(), Connectivity::Ready);
+        assert_eq!(connect.connects.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn reconnects_when_connection_is_lost() {
+        let connect = MockConnect::default();
+        let origin = "http://example.com".parse().unwrap();
+        let mut channel = Channel::new(origin, (), connect.clone()).unwrap();
+
+        future::lazy(|| channel.poll_ready()).wait().unwrap();
+        assert_eq!(connect.connects.load(Ordering::SeqCst), 1);
+
+        connect.broken.store(true, Ordering::SeqCst);
+        future::lazy(|| channel.poll_ready()).wait().unwrap();
+        assert_eq!(connect.connects.load(Ordering::SeqCst), 2);
+        assert_eq!(channel.connectivity(), Connectivity::Ready);
+    }
+
+    #[test]
+    fn wait_for_ready_calls_wait_for_connection() {
+        let mut channel = refusing(1, Duration::from_millis(1));
+        let mut rt = Runtime::new().unwrap();
+
+        // Calls fail fast by default.
+        let err = rt
+            .block_on(future::lazy(|| {
+                try_ready!(channel.poll_ready());
+                channel.call(request()).poll()
+            }))
+            .unwrap_err();
+        assert_eq!(Status::from_error(&*err).code(), Code::Unavailable);
+        assert_eq!(channel.connectivity(), Connectivity::TransientFailure);
+
+        let mut request = request();
+        request.extensions_mut().insert(WaitForReady(true));
+        let response = rt.block_on(channel.call(request)).unwrap();
+
+        assert_eq!(
+            response.into_body(),
+            "http://example.com/pkg.Service/Method"
+        );
+        assert_eq!(channel.connectivity(), Connectivity::Ready);
+    }
+
+    #[test]
+    fn wait_for_ready_calls_respect_deadline() {
+        let mut channel = refusing(1, Duration::from_secs(60));
+        let mut rt = Runtime::new().unwrap();
+
+        rt.block_on(future::lazy(|| channel.poll_ready())).unwrap();
+
+        let mut request = request();
+        request.extensions_mut().insert(WaitForReady(true));
+        request.headers_mut().insert(
+            config::TIMEOUT,
+            http::header::HeaderValue::from_static("10m"),
+        );
+
+        let err = rt.block_on(channel.call(request)).unwrap_err();
+        assert_eq!(Status::from_error(&*err).code(), Code::DeadlineExceeded);
+    }
+
+    #[test]
+    fn wakes_every_call_waiting_for_ready() {
+        let mut channel = refusing(1, Duration::from_millis(10));
+        let mut rt = Runtime::new().unwrap();
+
+        rt.block_on(future::lazy(|| channel.poll_ready())).unwrap();
+        assert_eq!(channel.connectivity(), Connectivity::TransientFailure);
+
+        // Each call waits in its own task, and only the last one to poll the
+        // backoff delay is woken by it.
+        let succeeded = Arc::new(AtomicUsize::new(0));
+        for _ in 0..2 {
+            let mut request = request();
+            request.extensions_mut().insert(WaitForReady(true));
+            request.headers_mut().insert(
+                config::TIMEOUT,
+                http::header::HeaderValue::from_static("5S"),
+            );
+
+            let succeeded = succeeded.clone();
+            rt.spawn(channel.call(request).then(move |result| {
+                if result.is_ok() {
+                    succeeded.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(())
+            }));
+        }
+
+        rt.run().unwrap();
+        assert_eq!(succeeded.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn origin_requires_authority() {
+        let origin = "/just/a/path".parse().unwrap();
+        assert!(Channel::new(origin, (), MockConnect::default()).is_err());
+    }
+}
@@ -5,7 +5,7 @@ use Body;
 
 use std::fmt;
 
-use futures::{Future, Poll, Stream};
+use futures::{Async, Future, Poll};
 use http::{response, Response};
 use prost::Message;
 
@@ -50,16 +50,27 @@ where
                     ref mut head,
                     ref mut stream,
                 } => {
-                    let message = match try_ready!(stream.poll()) {
-                        Some(message) => message,
-                        None => {
-                            return Err(::Status::new(
-                                ::Code::Internal,
-                                "Missing response message.",
-                            ));
+                    // The call is ended here rather than by the stream, which
+                    // would end it successfully without a message.
+                    let message = match stream.poll_stream() {
+                        Ok(Async::Ready(Some(message))) => message,
+                        Ok(Async::Ready(None)) => {
+                            let status =
+                                ::Status::new(::Code::Internal, "Missing response message.");
+                            stream.recorder().end(&status);
+                            return Err(status);
+                        }
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(status) => {
+                            stream.recorder().end(&status);
+                            return Err(status);
                         }
                     };
 
+                    // The trailers aren't waited for once the message is
+                    // received, so the call ends here.
+                    stream.recorder().end(&::Status::new(::Code::Ok, ""));
+
                     let head = head.take().unwrap();
                     let response = Response::from_parts(head, message);
 
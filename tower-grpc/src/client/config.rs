@@ -0,0 +1,669 @@
+//! Per-method call settings from a gRPC service config.
+//!
+//! See https://github.com/grpc/grpc/blob/master/doc/service_config.md
+
+use super::throttle::Throttle;
+use super::{hedge, retry};
+use Code;
+
+use http::header::HeaderValue;
+use serde_json::{Map, Value};
+
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A parsed service config.
+///
+/// Only the `methodConfig` and `retryThrottling` fields are used; other
+/// fields are ignored.
+///
+/// Clones share the same retry throttle.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceConfig {
+    methods: Vec<Arc<MethodConfig>>,
+    throttle: Option<Throttle>,
+}
+
+/// The settings for the methods matching one of its names.
+///
+/// Settings that are not part of the config are `None`, leaving them to
+/// the rest of the client.
+#[derive(Debug, Clone, Default)]
+pub struct MethodConfig {
+    names: Vec<Name>,
+    wait_for_ready: Option<bool>,
+    timeout: Option<Duration>,
+    max_request_message_bytes: Option<usize>,
+    max_response_message_bytes: Option<usize>,
+    retry_policy: Option<Arc<retry::Policy>>,
+    hedging_policy: Option<Arc<hedge::Policy>>,
+}
+
+/// Error returned when a service config is invalid.
+#[derive(Debug)]
+pub struct ParseError {
+    message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Name {
+    /// `None` matches every service.
+    service: Option<String>,
+    /// `None` matches every method of the service.
+    method: Option<String>,
+}
+
+/// Attempts allowed by retry and hedging policies are capped to this.
+const MAX_ATTEMPTS: usize = 5;
+
+pub(crate) const TIMEOUT: &str = "grpc-timeout";
+
+// ===== impl ServiceConfig =====
+
+impl ServiceConfig {
+    /// Parse a service config from its JSON representation.
+    pub fn from_json(json: &str) -> Result<Self, ParseError> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| ParseError::new(format!("invalid JSON: {}", e)))?;
+
+        let object = value
+            .as_object()
+            .ok_or_else(|| ParseError::new("service config must be an object"))?;
+
+        let mut methods: Vec<Arc<MethodConfig>> = Vec::new();
+
+        if let Some(value) = object.get("methodConfig") {
+            let entries = value
+                .as_array()
+                .ok_or_else(|| ParseError::new("methodConfig must be a list"))?;
+
+            for entry in entries {
+                let method = MethodConfig::parse(entry)?;
+
+                for name in &method.names {
+                    if methods.iter().any(|m| m.names.contains(name)) {
+                        return Err(ParseError::new(format!("duplicate method name {}", name)));
+                    }
+                }
+
+                methods.push(Arc::new(method));
+            }
+        }
+
+        let throttle = match object.get("retryThrottling") {
+            Some(value) => Some(parse_throttle(value)?),
+            None => None,
+        };
+
+        Ok(ServiceConfig { methods, throttle })
+    }
+
+    /// Returns the throttle limiting retries and hedged attempts, shared by
+    /// every call using this config.
+    pub fn retry_throttling(&self) -> Option<&Throttle> {
+        self.throttle.as_ref()
+    }
+
+    /// Returns the config for the method at `path`, such as
+    /// `/helloworld.Greeter/SayHello`.
+    ///
+    /// A config naming the method is preferred over one naming only its
+    /// service, which is preferred over the default config.
+    pub fn method_config(&self, path: &str) -> Option<&MethodConfig> {
+        self.matching(path).map(|config| &**config)
+    }
+
+    pub(crate) fn matching(&self, path: &str) -> Option<&Arc<MethodConfig>> {
+        let mut parts = path.trim_start_matches('/').splitn(2, '/');
+        let service = parts.next().unwrap_or("");
+        let method = parts.next().unwrap_or("");
+
+        let find = |service: Option<&str>, method: Option<&str>| {
+            self.methods.iter().find(|config| {
+                config.names.iter().any(|name| {
+                    name.service.as_ref().map(String::as_str) == service
+                        && name.method.as_ref().map(String::as_str) == method
+                })
+            })
+        };
+
+        find(Some(service), Some(method))
+            .or_else(|| find(Some(service), None))
+            .or_else(|| find(None, None))
+    }
+}
+
+impl FromStr for ServiceConfig {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ServiceConfig::from_json(s)
+    }
+}
+
+// ===== impl MethodConfig =====
+
+impl MethodConfig {
+    /// Whether calls wait for a connection instead of failing fast.
+    pub fn wait_for_ready(&self) -> Option<bool> {
+        self.wait_for_ready
+    }
+
+    /// The default timeout of calls.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The largest request message that may be sent.
+    pub fn max_request_message_bytes(&self) -> Option<usize> {
+        self.max_request_message_bytes
+    }
+
+    /// The largest response message that may be received.
+    pub fn max_response_message_bytes(&self) -> Option<usize> {
+        self.max_response_message_bytes
+    }
+
+    /// The policy used to retry calls.
+    pub fn retry_policy(&self) -> Option<&retry::Policy> {
+        self.retry_policy.as_ref().map(|policy| &**policy)
+    }
+
+    /// The policy used to hedge calls.
+    pub fn hedging_policy(&self) -> Option<&hedge::Policy> {
+        self.hedging_policy.as_ref().map(|policy| &**policy)
+    }
+
+    pub(crate) fn shared_retry_policy(&self) -> Option<Arc<retry::Policy>> {
+        self.retry_policy.clone()
+    }
+
+    pub(crate) fn shared_hedging_policy(&self) -> Option<Arc<hedge::Policy>> {
+        self.hedging_policy.clone()
+    }
+
+    fn parse(value: &Value) -> Result<Self, ParseError> {
+        let object = as_object(value, "methodConfig entry")?;
+        let mut config = MethodConfig::default();
+
+        if let Some(names) = object.get("name") {
+            let names = names
+                .as_array()
+                .ok_or_else(|| ParseError::new("name must be a list"))?;
+
+            for name in names {
+                config.names.push(Name::parse(name)?);
+            }
+        }
+
+        if let Some(value) = object.get("waitForReady") {
+            let wait = value
+                .as_bool()
+                .ok_or_else(|| ParseError::new("waitForReady must be a boolean"))?;
+            config.wait_for_ready = Some(wait);
+        }
+
+        if let Some(value) = object.get("timeout") {
+            config.timeout = Some(duration(value, "timeout")?);
+        }
+
+        if let Some(value) = object.get("maxRequestMessageBytes") {
+            config.max_request_message_bytes = Some(integer(value, "maxRequestMessageBytes")?);
+        }
+
+        if let Some(value) = object.get("maxResponseMessageBytes") {
+            config.max_response_message_bytes = Some(integer(value, "maxResponseMessageBytes")?);
+        }
+
+        let retry = object.get("retryPolicy");
+        let hedging = object.get("hedgingPolicy");
+
+        if retry.is_some() && hedging.is_some() {
+            return Err(ParseError::new(
+                "retryPolicy and hedgingPolicy can't both be set",
+            ));
+        }
+
+        if let Some(value) = retry {
+            config.retry_policy = Some(Arc::new(parse_retry_policy(value)?));
+        }
+
+        if let Some(value) = hedging {
+            config.hedging_policy = Some(Arc::new(parse_hedging_policy(value)?));
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_retry_policy(value: &Value) -> Result<retry::Policy, ParseError> {
+    let object = as_object(value, "retryPolicy")?;
+
+    let max_attempts = max_attempts(object, "retryPolicy")?;
+
+    let initial_backoff = required(object, "initialBackoff", "retryPolicy")?;
+    let initial_backoff = positive_duration(initial_backoff, "initialBackoff")?;
+
+    let max_backoff = required(object, "maxBackoff", "retryPolicy")?;
+    let max_backoff = positive_duration(max_backoff, "maxBackoff")?;
+
+    let multiplier = required(object, "backoffMultiplier", "retryPolicy")?;
+    let multiplier = match multiplier.as_f64() {
+        Some(m) if m > 0.0 => m,
+        _ => {
+            return Err(ParseError::new(
+                "backoffMultiplier must be a positive number",
+            ))
+        }
+    };
+
+    let codes = required(object, "retryableStatusCodes", "retryPolicy")?;
+    let codes = status_codes(codes, "retryableStatusCodes")?;
+    if codes.is_empty() {
+        return Err(ParseError::new("retryableStatusCodes must not be empty"));
+    }
+
+    Ok(retry::Policy::new(max_attempts)
+        .initial_backoff(initial_backoff)
+        .max_backoff(max_backoff)
+        .backoff_multiplier(multiplier)
+        .retryable_codes(codes))
+}
+
+fn parse_hedging_policy(value: &Value) -> Result<hedge::Policy, ParseError> {
+    let object = as_object(value, "hedgingPolicy")?;
+
+    let max_attempts = max_attempts(object, "hedgingPolicy")?;
+
+    let delay = match object.get("hedgingDelay") {
+        Some(value) => duration(value, "hedgingDelay")?,
+        None => Duration::from_secs(0),
+    };
+
+    let codes = match object.get("nonFatalStatusCodes") {
+        Some(value) => status_codes(value, "nonFatalStatusCodes")?,
+        None => Vec::new(),
+    };
+
+    Ok(hedge::Policy::new(max_attempts, delay).non_fatal_codes(codes))
+}
+
+fn parse_throttle(value: &Value) -> Result<Throttle, ParseError> {
+    let object = as_object(value, "retryThrottling")?;
+
+    let max_tokens = required(object, "maxTokens", "retryThrottling")?;
+    let max_tokens = match integer(max_tokens, "maxTokens")? {
+        n if n == 0 || n > 1000 => {
+            return Err(ParseError::new("maxTokens must be between 1 and 1000"));
+        }
+        n => n as u32,
+    };
+
+    let token_ratio = required(object, "tokenRatio", "retryThrottling")?;
+    let token_ratio = match token_ratio.as_f64() {
+        Some(ratio) if ratio > 0.0 => ratio,
+        _ => return Err(ParseError::new("tokenRatio must be a positive number")),
+    };
+
+    Ok(Throttle::new(max_tokens, token_ratio))
+}
+
+// ===== impl Name =====
+
+impl Name {
+    fn parse(value: &Value) -> Result<Self, ParseError> {
+        let object = as_object(value, "name")?;
+
+        let string = |key: &str| match object.get(key) {
+            None => Ok(None),
+            Some(&Value::String(ref s)) if s.is_empty() => Ok(None),
+            Some(&Value::String(ref s)) => Ok(Some(s.clone())),
+            Some(_) => Err(ParseError::new(format!("name {} must be a string", key))),
+        };
+
+        let service = string("service")?;
+        let method = string("method")?;
+
+        if service.is_none() && method.is_some() {
+            return Err(ParseError::new("name with a method must have a service"));
+        }
+
+        Ok(Name { service, method })
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.service, &self.method) {
+            (&Some(ref service), &Some(ref method)) => write!(f, "{}/{}", service, method),
+            (&Some(ref service), &None) => write!(f, "{}/*", service),
+            _ => write!(f, "*"),
+        }
+    }
+}
+
+// ===== impl ParseError =====
+
+impl ParseError {
+    fn new(message: impl Into<String>) -> Self {
+        ParseError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid service config: {}", self.message)
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// Encode `timeout` as a `grpc-timeout` value, in the finest unit that fits
+/// in 8 digits.
+pub(crate) fn encode_timeout(timeout: Duration) -> HeaderValue {
+    const MAX: u64 = 99_999_999;
+
+    let nanos = timeout.as_secs() as u128 * 1_000_000_000 + timeout.subsec_nanos() as u128;
+    let units = [
+        (1, "n"),
+        (1_000, "u"),
+        (1_000_000, "m"),
+        (1_000_000_000, "S"),
+        (60 * 1_000_000_000, "M"),
+        (3600 * 1_000_000_000, "H"),
+    ];
+
+    for &(scale, unit) in &units {
+        // Round up, so the server doesn't give up early.
+        let value = (nanos + scale - 1) / scale;
+        if value <= MAX as u128 {
+            let value = format!("{}{}", value, unit);
+            return HeaderValue::from_str(&value).expect("timeout is a valid header value");
+        }
+    }
+
+    HeaderValue::from_str(&format!("{}H", MAX)).expect("timeout is a valid header value")
+}
+
+/// Decode a `grpc-timeout` value.
+pub(crate) fn decode_timeout(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?;
+    if value.is_empty() || value.len() > 9 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let n: u64 = digits.parse().ok()?;
+
+    let timeout = match unit {
+        "H" => Duration::from_secs(n * 3600),
+        "M" => Duration::from_secs(n * 60),
+        "S" => Duration::from_secs(n),
+        "m" => Duration::from_millis(n),
+        "u" => Duration::from_micros(n),
+        "n" => Duration::from_nanos(n),
+        _ => return None,
+    };
+    Some(timeout)
+}
+
+// ===== JSON helpers =====
+
+fn as_object<'a>(value: &'a Value, what: &str) -> Result<&'a Map<String, Value>, ParseError> {
+    value
+        .as_object()
+        .ok_or_else(|| ParseError::new(format!("{} must be an object", what)))
+}
+
+fn required<'a>(
+    object: &'a Map<String, Value>,
+    key: &str,
+    what: &str,
+) -> Result<&'a Value, ParseError> {
+    object
+        .get(key)
+        .ok_or_else(|| ParseError::new(format!("{} requires {}", what, key)))
+}
+
+fn max_attempts(object: &Map<String, Value>, what: &str) -> Result<usize, ParseError> {
+    let value = required(object, "maxAttempts", what)?;
+    match integer(value, "maxAttempts")? {
+        n if n < 2 => Err(ParseError::new("maxAttempts must be at least 2")),
+        n => Ok(n.min(MAX_ATTEMPTS)),
+    }
+}
+
+/// Parses a non-negative integer, which protobuf JSON allows as a string.
+fn integer(value: &Value, what: &str) -> Result<usize, ParseError> {
+    let n = match *value {
+        Value::Number(ref n) => n.as_u64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    };
+
+    n.map(|n| n as usize)
+        .ok_or_else(|| ParseError::new(format!("{} must be a non-negative integer", what)))
+}
+
+/// Parses a protobuf JSON duration, such as `"1.5s"`.
+fn duration(value: &Value, what: &str) -> Result<Duration, ParseError> {
+    let invalid = || ParseError::new(format!("{} must be a duration such as \"1.5s\"", what));
+
+    let s = value.as_str().ok_or_else(invalid)?;
+    let s = if s.ends_with('s') {
+        &s[..s.len() - 1]
+    } else {
+        return Err(invalid());
+    };
+
+    let mut parts = s.splitn(2, '.');
+    let secs = parts.next().unwrap_or("");
+    let secs: u64 = if secs.bytes().all(|b| b.is_ascii_digit()) {
+        secs.parse().map_err(|_| invalid())?
+    } else {
+        return Err(invalid());
+    };
+
+    let nanos = match parts.next() {
+        Some(frac) if frac.is_empty() || frac.len() > 9 => return Err(invalid()),
+        Some(frac) if !frac.bytes().all(|b| b.is_ascii_digit()) => return Err(invalid()),
+        Some(frac) => {
+            let padded = format!("{:0<9}", frac);
+            padded.parse().map_err(|_| invalid())?
+        }
+        None => 0,
+    };
+
+    Ok(Duration::new(secs, nanos))
+}
+
+fn positive_duration(value: &Value, what: &str) -> Result<Duration, ParseError> {
+    let duration = duration(value, what)?;
+    if duration == Duration::from_secs(0) {
+        return Err(ParseError::new(format!("{} must be positive", what)));
+    }
+    Ok(duration)
+}
+
+/// Parses a list of status codes, given by name or by number.
+fn status_codes(value: &Value, what: &str) -> Result<Vec<Code>, ParseError> {
+    let values = value
+        .as_array()
+        .ok_or_else(|| ParseError::new(format!("{} must be a list", what)))?;
+
+    values
+        .iter()
+        .map(|value| {
+            let code = match *value {
+                Value::String(ref s) => code_from_name(s),
+                Value::Number(ref n) => match n.as_u64() {
+                    Some(n) if n <= 16 => Some(Code::from_i32(n as i32)),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            code.ok_or_else(|| {
+                ParseError::new(format!("invalid status code {} in {}", value, what))
+            })
+        })
+        .collect()
+}
+
+fn code_from_name(name: &str) -> Option<Code> {
+    let code = match name {
+        "OK" => Code::Ok,
+        "CANCELLED" => Code::Cancelled,
+        "UNKNOWN" => Code::Unknown,
+        "INVALID_ARGUMENT" => Code::InvalidArgument,
+        "DEADLINE_EXCEEDED" => Code::DeadlineExceeded,
+        "NOT_FOUND" => Code::NotFound,
+        "ALREADY_EXISTS" => Code::AlreadyExists,
+        "PERMISSION_DENIED" => Code::PermissionDenied,
+        "RESOURCE_EXHAUSTED" => Code::ResourceExhausted,
+        "FAILED_PRECONDITION" => Code::FailedPrecondition,
+        "ABORTED" => Code::Aborted,
+        "OUT_OF_RANGE" => Code::OutOfRange,
+        "UNIMPLEMENTED" => Code::Unimplemented,
+        "INTERNAL" => Code::Internal,
+        "UNAVAILABLE" => Code::Unavailable,
+        "DATA_LOSS" => Code::DataLoss,
+        "UNAUTHENTICATED" => Code::Unauthenticated,
+        _ => return None,
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "methodConfig": [
+            {
+                "name": [{ "service": "helloworld.Greeter", "method": "SayHello" }],
+                "timeout": "0.250s",
+                "retryPolicy": {
+                    "maxAttempts": 10,
+                    "initialBackoff": "0.1s",
+                    "maxBackoff": "1s",
+                    "backoffMultiplier": 2,
+                    "retryableStatusCodes": ["UNAVAILABLE", 4]
+                }
+            },
+            {
+                "name": [{ "service": "helloworld.Greeter" }],
+                "waitForReady": true,
+                "maxResponseMessageBytes": "1024"
+            },
+            {
+                "name": [{}],
+                "hedgingPolicy": { "maxAttempts": 3, "hedgingDelay": "1s" }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn most_specific_name_matches() {
+        let config: ServiceConfig = CONFIG.parse().unwrap();
+
+        let hello = config
+            .method_config("/helloworld.Greeter/SayHello")
+            .unwrap();
+        assert_eq!(hello.timeout(), Some(Duration::from_millis(250)));
+        assert_eq!(hello.wait_for_ready(), None);
+
+        let policy = hello.retry_policy().unwrap();
+        assert_eq!(policy.max_attempts(), MAX_ATTEMPTS);
+        assert!(policy.is_retryable(Code::DeadlineExceeded));
+
+        let goodbye = config
+            .method_config("/helloworld.Greeter/SayGoodbye")
+            .unwrap();
+        assert_eq!(goodbye.wait_for_ready(), Some(true));
+        assert_eq!(goodbye.max_response_message_bytes(), Some(1024));
+
+        let other = config.method_config("/other.Service/Method").unwrap();
+        assert!(other.hedging_policy().is_some());
+    }
+
+    #[test]
+    fn parses_retry_throttling() {
+        let config = ServiceConfig::from_json(
+            r#"{ "retryThrottling": { "maxTokens": 10, "tokenRatio": 0.1 } }"#,
+        )
+        .unwrap();
+        assert_eq!(config.retry_throttling().unwrap().tokens(), 10.0);
+
+        let invalid = ServiceConfig::from_json(
+            r#"{ "retryThrottling": { "maxTokens": 0, "tokenRatio": 0.1 } }"#,
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn no_default_means_no_config() {
+        let config = ServiceConfig::from_json(
+            r#"{ "methodConfig": [{ "name": [{ "service": "a.B" }], "timeout": "1s" }] }"#,
+        )
+        .unwrap();
+
+        assert!(config.method_config("/a.B/C").is_some());
+        assert!(config.method_config("/a.D/C").is_none());
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let invalid = [
+            "[]",
+            r#"{ "methodConfig": {} }"#,
+            r#"{ "methodConfig": [{ "timeout": "1" }] }"#,
+            r#"{ "methodConfig": [{ "timeout": "-1s" }] }"#,
+            r#"{ "methodConfig": [{ "name": [{ "method": "A" }] }] }"#,
+            r#"{ "methodConfig": [{ "name": [{}] }, { "name": [{}] }] }"#,
+            r#"{ "methodConfig": [{ "retryPolicy": { "maxAttempts": 1 } }] }"#,
+            r#"{ "methodConfig": [{ "hedgingPolicy": { "maxAttempts": 2,
+                "nonFatalStatusCodes": ["NOPE"] } }] }"#,
+        ];
+
+        for json in &invalid {
+            assert!(ServiceConfig::from_json(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn encodes_timeouts() {
+        let encode = |d| encode_timeout(d).to_str().unwrap().to_string();
+
+        assert_eq!(encode(Duration::from_millis(250)), "250000u");
+        assert_eq!(encode(Duration::from_secs(100)), "100000m");
+        assert_eq!(encode(Duration::new(1, 1)), "1000001u");
+        assert_eq!(encode(Duration::from_secs(u64::max_value())), "99999999H");
+
+        let decode = |s| decode_timeout(&HeaderValue::from_static(s));
+        assert_eq!(decode("250000u"), Some(Duration::from_millis(250)));
+        assert_eq!(decode("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(decode("123456789S"), None);
+        assert_eq!(decode("10x"), None);
+    }
+
+    #[test]
+    fn parses_durations() {
+        let parse = |s: &str| duration(&Value::String(s.to_string()), "test").ok();
+
+        assert_eq!(parse("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse("0.000000001s"), Some(Duration::new(0, 1)));
+        assert_eq!(parse("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse("1.s"), None);
+        assert_eq!(parse("1.0000000001s"), None);
+        assert_eq!(parse("1m"), None);
+    }
+}
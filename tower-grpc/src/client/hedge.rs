@@ -0,0 +1,606 @@
+//! Hedging unary calls, sending them again while waiting for a response.
+
+use super::config::MethodConfig;
+use super::replay::{Head, Replay};
+use super::retry::{self, PREVIOUS_ATTEMPTS};
+use super::throttle::Throttle;
+use body::BoxBody;
+use error::Error;
+use {Code, Status};
+
+use futures::{Async, Future, Poll};
+use http::{self, header::HeaderValue};
+use tokio_timer::Delay;
+use tower_service::Service;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Request extension set by `Grpc::unary`, marking calls that may be
+/// hedged.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Unary;
+
+/// When and how often to hedge a call.
+///
+/// A new attempt is sent every `delay` while no attempt has completed, and
+/// immediately when an attempt fails with a non-fatal code, up to
+/// `max_attempts`. The first attempt to receive response headers, or to fail
+/// with any other code, wins and the others are cancelled.
+///
+/// See https://github.com/grpc/proposal/blob/master/A6-client-retries.md
+#[derive(Debug, Clone)]
+pub struct Policy {
+    max_attempts: usize,
+    delay: Duration,
+    non_fatal_codes: Vec<Code>,
+    buffer_limit: usize,
+}
+
+/// Hedges unary calls to the inner service according to a `Policy`.
+///
+/// Only calls made with `Grpc::unary` are hedged; other calls are passed
+/// through as is. The inner service is cloned for each hedged call.
+#[derive(Debug, Clone)]
+pub struct Hedge<S> {
+    inner: S,
+    policy: Arc<Policy>,
+    throttle: Option<Throttle>,
+}
+
+/// Response future returned by `Hedge`.
+pub struct ResponseFuture<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    kind: Kind<S>,
+}
+
+enum Kind<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    Single(S::Future),
+    Hedged(Hedging<S>),
+}
+
+struct Hedging<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    service: S,
+    policy: Arc<Policy>,
+    throttle: Option<Throttle>,
+    head: Head,
+    replay: Replay,
+    in_flight: Vec<S::Future>,
+    /// Attempts sent so far.
+    sent: usize,
+    next: Next,
+    /// Set when the server asked not to send more attempts.
+    stopped: bool,
+    /// The outcome of the last attempt that failed with a non-fatal code.
+    last: Option<Result<S::Response, Error>>,
+}
+
+/// When to send the next attempt.
+enum Next {
+    Now,
+    At(Delay),
+}
+
+// ===== impl Policy =====
+
+impl Policy {
+    /// Create a new `Policy` sending up to `max_attempts` attempts,
+    /// including the original call, `delay` apart.
+    ///
+    /// No codes are non-fatal by default.
+    pub fn new(max_attempts: usize, delay: Duration) -> Self {
+        Policy {
+            max_attempts,
+            delay,
+            non_fatal_codes: Vec::new(),
+            buffer_limit: 256 * 1024,
+        }
+    }
+
+    /// Set the codes that don't stop hedging.
+    pub fn non_fatal_codes(mut self, codes: Vec<Code>) -> Self {
+        self.non_fatal_codes = codes;
+        self
+    }
+
+    /// Set how many bytes of request messages are buffered for hedging.
+    /// Defaults to 256 KiB.
+    pub fn buffer_limit(mut self, limit: usize) -> Self {
+        self.buffer_limit = limit;
+        self
+    }
+
+    fn is_non_fatal(&self, code: Code) -> bool {
+        self.non_fatal_codes.contains(&code)
+    }
+}
+
+// ===== impl Hedge =====
+
+impl<S> Hedge<S> {
+    /// Hedge unary calls to `inner` according to `policy`.
+    pub fn new(inner: S, policy: Policy) -> Self {
+        Hedge {
+            inner,
+            policy: Arc::new(policy),
+            throttle: None,
+        }
+    }
+
+    /// Only send hedged attempts while `throttle` allows it.
+    ///
+    /// Without one, the throttle from the service config is used, if any.
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for Hedge<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>> + Clone,
+    S::Error: Into<Error>,
+{
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        // A policy from the service config takes precedence.
+        let policy = request
+            .extensions()
+            .get::<Arc<MethodConfig>>()
+            .and_then(|config| config.shared_hedging_policy())
+            .unwrap_or_else(|| self.policy.clone());
+
+        if request.extensions().get::<Unary>().is_none() || policy.max_attempts < 2 {
+            let kind = Kind::Single(self.inner.call(request));
+            return ResponseFuture { kind };
+        }
+
+        let throttle = self
+            .throttle
+            .clone()
+            .or_else(|| request.extensions().get::<Throttle>().cloned());
+
+        let (parts, body) = request.into_parts();
+        let head = Head::new(&parts);
+        let replay = Replay::new(body, policy.buffer_limit);
+
+        let body = replay.body().expect("new replay is replayable");
+        let request = http::Request::from_parts(parts, body);
+
+        let service = self.inner.clone();
+        let first = self.inner.call(request);
+
+        let hedging = Hedging {
+            service,
+            head,
+            replay,
+            in_flight: vec![first],
+            sent: 1,
+            next: Next::At(Delay::new(Instant::now() + policy.delay)),
+            stopped: false,
+            last: None,
+            policy,
+            throttle,
+        };
+
+        ResponseFuture {
+            kind: Kind::Hedged(hedging),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, B> Future for ResponseFuture<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+{
+    type Item = http::Response<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.kind {
+            Kind::Single(ref mut fut) => fut.poll().map_err(Into::into),
+            Kind::Hedged(ref mut hedging) => hedging.poll(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for ResponseFuture<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut dbg = f.debug_struct("hedge::ResponseFuture");
+
+        if let Kind::Hedged(ref hedging) = self.kind {
+            dbg.field("sent", &hedging.sent)
+                .field("in_flight", &hedging.in_flight.len());
+        }
+
+        dbg.finish()
+    }
+}
+
+// ===== impl Hedging =====
+
+impl<S, B> Hedging<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+{
+    fn poll(&mut self) -> Poll<http::Response<B>, Error> {
+        loop {
+            if let Some(result) = self.poll_in_flight() {
+                // Dropping the other attempts cancels them.
+                self.replay.commit();
+                self.in_flight.clear();
+                return result.map(Async::Ready);
+            }
+
+            let can_send = !self.stopped
+                && self.sent < self.policy.max_attempts
+                && self.replay.is_replayable()
+                && self.throttle.as_ref().map_or(true, Throttle::allows_retry);
+
+            if !can_send {
+                if self.in_flight.is_empty() {
+                    self.replay.commit();
+                    let last = self.last.take().expect("an attempt has failed");
+                    return last.map(Async::Ready);
+                }
+                return Ok(Async::NotReady);
+            }
+
+            let due = match self.next {
+                Next::Now => true,
+                Next::At(ref mut delay) => match delay.poll() {
+                    Ok(Async::Ready(())) => true,
+                    Ok(Async::NotReady) => false,
+                    Err(e) => return Err(e.into()),
+                },
+            };
+
+            if !due {
+                return Ok(Async::NotReady);
+            }
+
+            match self.service.poll_ready() {
+                Ok(Async::Ready(())) => {}
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    let e: Error = e.into();
+                    debug!("not hedging; service failed: {}", e);
+                    self.stopped = true;
+
+                    if self.in_flight.is_empty() {
+                        return Err(e);
+                    }
+                    continue;
+                }
+            }
+
+            let body = match self.replay.body() {
+                Some(body) => body,
+                None => continue,
+            };
+
+            let mut request = self.head.request(body);
+            request
+                .headers_mut()
+                .insert(PREVIOUS_ATTEMPTS, HeaderValue::from(self.sent));
+
+            trace!("sending hedged attempt {}", self.sent + 1);
+            self.sent += 1;
+            self.in_flight.push(self.service.call(request));
+            self.next = Next::At(Delay::new(Instant::now() + self.policy.delay));
+        }
+    }
+
+    /// Polls every attempt in flight, returning the outcome of the call
+    /// once one of them has won.
+    fn poll_in_flight(&mut self) -> Option<Result<http::Response<B>, Error>> {
+        let mut i = 0;
+
+        while i < self.in_flight.len() {
+            let result = match self.in_flight[i].poll() {
+                Ok(Async::NotReady) => {
+                    i += 1;
+                    continue;
+                }
+                Ok(Async::Ready(response)) => Ok(response),
+                Err(e) => Err(e.into()),
+            };
+
+            self.in_flight.swap_remove(i);
+
+            let code = match result {
+                Ok(ref response) => Status::from_header_map(response.headers()).map(|s| s.code()),
+                Err(ref e) => Some(Status::from_error(&**e).code()),
+            };
+
+            match code {
+                Some(code) if self.policy.is_non_fatal(code) => {
+                    debug!("hedged attempt failed with {:?}", code);
+                    if let Some(ref throttle) = self.throttle {
+                        throttle.on_failure();
+                    }
+                }
+                None | Some(Code::Ok) => {
+                    if let Some(ref throttle) = self.throttle {
+                        throttle.on_success();
+                    }
+                    return Some(result);
+                }
+                _ => return Some(result),
+            }
+
+            self.next = match result {
+                Ok(ref response) => match retry::pushback(response.headers()) {
+                    Ok(Some(delay)) => Next::At(Delay::new(Instant::now() + delay)),
+                    Ok(None) => Next::Now,
+                    Err(()) => {
+                        self.stopped = true;
+                        Next::Now
+                    }
+                },
+                Err(_) => Next::Now,
+            };
+            self.last = Some(result);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use body::{BytesBuf, HttpBody};
+    use bytes::{Buf, Bytes, IntoBuf};
+    use futures::future;
+    use futures::sync::oneshot;
+    use std::sync::Mutex;
+    use tokio::runtime::current_thread::Runtime;
+
+    type Attempt = oneshot::Sender<Result<http::Response<()>, Status>>;
+
+    /// Records every attempt, to be completed by the test.
+    #[derive(Clone, Default)]
+    struct Manual {
+        attempts: Arc<Mutex<Vec<(Option<String>, Attempt)>>>,
+        bodies: Arc<Mutex<Vec<BoxBody>>>,
+    }
+
+    struct Chunks(Vec<&'static str>);
+
+    impl HttpBody for Chunks {
+        type Item = BytesBuf;
+        type Error = Status;
+
+        fn is_end_stream(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            let chunk = if self.0.is_empty() {
+                None
+            } else {
+                Some(Bytes::from(self.0.remove(0)).into_buf())
+            };
+            Ok(Async::Ready(chunk))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    fn read_chunk(body: &mut BoxBody) -> Option<Vec<u8>> {
+        future::poll_fn(|| HttpBody::poll_buf(body))
+            .wait()
+            .unwrap()
+            .map(|buf| buf.bytes().to_vec())
+    }
+
+    impl Service<http::Request<BoxBody>> for Manual {
+        type Response = http::Response<()>;
+        type Error = Status;
+        type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            let previous = request
+                .headers()
+                .get(PREVIOUS_ATTEMPTS)
+                .map(|v| v.to_str().unwrap().to_string());
+
+            let (tx, rx) = oneshot::channel();
+            self.attempts.lock().unwrap().push((previous, tx));
+            self.bodies.lock().unwrap().push(request.into_body());
+
+            Box::new(
+                rx.map_err(|_| Status::new(Code::Cancelled, "dropped"))
+                    .and_then(|result| result),
+            )
+        }
+    }
+
+    fn unary() -> http::Request<BoxBody> {
+        let mut request = http::Request::new(BoxBody::empty());
+        request.extensions_mut().insert(Unary);
+        request
+    }
+
+    fn trailers_only(code: Code) -> http::Response<()> {
+        let mut response = http::Response::new(());
+        Status::new(code, "failed")
+            .add_header(response.headers_mut())
+            .unwrap();
+        response
+    }
+
+    #[test]
+    fn sends_hedges_after_delay_and_takes_first_response() {
+        let manual = Manual::default();
+        let policy = Policy::new(3, Duration::from_millis(1));
+        let mut hedge = Hedge::new(manual.clone(), policy);
+
+        let mut rt = Runtime::new().unwrap();
+        let mut fut = hedge.call(unary());
+
+        // Wait for all three attempts to be sent.
+        rt.block_on(future::poll_fn(|| {
+            let _ = fut.poll();
+            if manual.attempts.lock().unwrap().len() == 3 {
+                Ok::<_, ()>(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }))
+        .unwrap();
+
+        let mut attempts = manual
+            .attempts
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect::<Vec<_>>();
+        let previous: Vec<_> = attempts.iter().map(|a| a.0.clone()).collect();
+        assert_eq!(
+            previous,
+            [None, Some("1".to_string()), Some("2".to_string())]
+        );
+
+        // The second attempt wins; the others are cancelled.
+        let (_, second) = attempts.remove(1);
+        second.send(Ok(http::Response::new(()))).unwrap();
+        rt.block_on(future::lazy(|| fut)).unwrap();
+
+        for (_, tx) in attempts {
+            assert!(tx.is_canceled());
+        }
+    }
+
+    #[test]
+    fn winner_behind_the_leading_attempt_sends_whole_body() {
+        let manual = Manual::default();
+        let policy = Policy::new(2, Duration::from_millis(1));
+        let mut hedge = Hedge::new(manual.clone(), policy);
+
+        let mut request = unary();
+        *request.body_mut() = BoxBody::new(Box::new(Chunks(vec!["a", "b", "c"])));
+
+        let mut rt = Runtime::new().unwrap();
+        let mut fut = hedge.call(request);
+
+        rt.block_on(future::poll_fn(|| {
+            let _ = fut.poll();
+            if manual.attempts.lock().unwrap().len() == 2 {
+                Ok::<_, ()>(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }))
+        .unwrap();
+
+        let mut bodies = manual.bodies.lock().unwrap().drain(..).collect::<Vec<_>>();
+        let mut second = bodies.pop().unwrap();
+        let mut first = bodies.pop().unwrap();
+
+        assert_eq!(read_chunk(&mut first).unwrap(), b"a");
+        assert_eq!(read_chunk(&mut first).unwrap(), b"b");
+        assert_eq!(read_chunk(&mut second).unwrap(), b"a");
+
+        // The second attempt wins, while the first has read further.
+        let (_, tx) = manual.attempts.lock().unwrap().remove(1);
+        tx.send(Ok(http::Response::new(()))).unwrap();
+        rt.block_on(future::lazy(|| fut)).unwrap();
+        drop(first);
+
+        assert_eq!(read_chunk(&mut second).unwrap(), b"b");
+        assert_eq!(read_chunk(&mut second).unwrap(), b"c");
+        assert_eq!(read_chunk(&mut second), None);
+    }
+
+    #[test]
+    fn non_fatal_failure_sends_next_attempt_right_away() {
+        let manual = Manual::default();
+        let policy =
+            Policy::new(2, Duration::from_secs(60)).non_fatal_codes(vec![Code::Unavailable]);
+        let mut hedge = Hedge::new(manual.clone(), policy);
+
+        let mut rt = Runtime::new().unwrap();
+        let mut fut = hedge.call(unary());
+        rt.block_on(future::lazy(|| {
+            let _ = fut.poll();
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        let (_, first) = manual.attempts.lock().unwrap().remove(0);
+        first.send(Ok(trailers_only(Code::Unavailable))).unwrap();
+
+        rt.block_on(future::lazy(|| {
+            let _ = fut.poll();
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+        assert_eq!(manual.attempts.lock().unwrap().len(), 1);
+
+        // A fatal failure ends the call.
+        let (_, second) = manual.attempts.lock().unwrap().remove(0);
+        second
+            .send(Err(Status::new(Code::NotFound, "nope")))
+            .unwrap();
+
+        let err = rt.block_on(future::lazy(|| fut)).unwrap_err();
+        assert_eq!(Status::from_error(&*err).code(), Code::NotFound);
+    }
+
+    #[test]
+    fn only_unary_calls_are_hedged() {
+        let manual = Manual::default();
+        let mut hedge = Hedge::new(manual.clone(), Policy::new(3, Duration::from_millis(1)));
+
+        match hedge.call(http::Request::new(BoxBody::empty())).kind {
+            Kind::Single(_) => {}
+            Kind::Hedged(_) => panic!("streaming call was hedged"),
+        }
+
+        match hedge.call(unary()).kind {
+            Kind::Hedged(_) => {}
+            Kind::Single(_) => panic!("unary call was not hedged"),
+        }
+    }
+}
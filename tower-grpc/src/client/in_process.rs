@@ -0,0 +1,566 @@
+//! An in-memory transport to a server running in the same process.
+
+use body::{Body, BoxBody, BytesBuf, HttpBody};
+use error::Error;
+use {Code, Status};
+
+use bytes::{Buf, Bytes, IntoBuf};
+use futures::sync::{mpsc, oneshot};
+use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+use http::{self, HeaderMap};
+use tokio_executor::{DefaultExecutor, Executor, SpawnError};
+use tower_service::Service;
+
+use std::fmt;
+
+/// Frames buffered for each body before its sender waits.
+const BODY_BUFFER: usize = 4;
+
+type BoxPump = Box<dyn Future<Item = (), Error = ()> + Send>;
+
+/// A client service calling a server service directly, with no sockets.
+///
+/// Each call is handed to the `Connection` driving the server service. The
+/// bodies and trailers of the request and response are sent through
+/// channels as they are produced, like over HTTP/2, so that streaming calls
+/// behave as they would over the network. With a single-threaded runtime,
+/// the order in which the client, server and bodies make progress is
+/// deterministic.
+///
+/// ```rust,ignore
+/// let (transport, connection) = InProcess::pair(GreeterServer::new(Greet));
+/// rt.spawn(connection);
+///
+/// let mut client = Greeter::new(transport);
+/// ```
+///
+/// If the server service fails or the connection is dropped, calls fail
+/// with `Code::Unavailable`.
+#[derive(Clone)]
+pub struct InProcess {
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+/// Drives the server service behind an `InProcess` transport.
+///
+/// Completes once every `InProcess` handle is dropped and the calls in
+/// flight are done.
+pub struct Connection<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    service: S,
+    rx: mpsc::UnboundedReceiver<Message>,
+    /// A call taken from the queue, waiting for the service to be ready.
+    current: Option<Message>,
+    /// Whether every handle is gone.
+    closed: bool,
+    /// Set once the service failed, failing every later call.
+    failed: Option<Status>,
+    calls: Vec<Call<S::Future>>,
+    pumps: Vec<BoxPump>,
+}
+
+/// Response future returned by `InProcess`.
+pub struct ResponseFuture {
+    rx: oneshot::Receiver<Result<http::Response<RecvBody>, Status>>,
+}
+
+/// A body received from the other side of an `InProcess` transport.
+#[derive(Debug)]
+pub struct RecvBody {
+    rx: mpsc::Receiver<Frame>,
+    trailers: Option<HeaderMap>,
+}
+
+struct Message {
+    request: http::Request<BoxBody>,
+    /// Sends the client's request body to the server.
+    pump: Pump<BoxBody>,
+    tx: oneshot::Sender<Result<http::Response<RecvBody>, Status>>,
+}
+
+struct Call<F> {
+    future: F,
+    tx: Option<oneshot::Sender<Result<http::Response<RecvBody>, Status>>>,
+}
+
+/// Forwards a body to a channel, frame by frame.
+struct Pump<B> {
+    body: B,
+    tx: mpsc::Sender<Frame>,
+    /// A frame waiting for room in the channel.
+    pending: Option<Frame>,
+    state: PumpState,
+}
+
+#[derive(Debug)]
+enum PumpState {
+    Data,
+    Trailers,
+    Done,
+}
+
+#[derive(Debug)]
+enum Frame {
+    Data(Bytes),
+    Trailers(HeaderMap),
+    Error(Status),
+}
+
+// ===== impl InProcess =====
+
+impl InProcess {
+    /// Serve calls with `service`, spawning its connection on the default
+    /// executor.
+    pub fn new<S, B>(service: S) -> Result<Self, SpawnError>
+    where
+        S: Service<http::Request<BoxBody>, Response = http::Response<B>> + Send + 'static,
+        S::Future: Send,
+        S::Error: Into<Error>,
+        B: Body + Send + 'static,
+    {
+        let (transport, connection) = InProcess::pair(service);
+        DefaultExecutor::current().spawn(Box::new(connection))?;
+        Ok(transport)
+    }
+
+    /// Serve calls with `service`, returning the connection for the caller
+    /// to spawn.
+    pub fn pair<S>(service: S) -> (Self, Connection<S>)
+    where
+        S: Service<http::Request<BoxBody>>,
+    {
+        let (tx, rx) = mpsc::unbounded();
+
+        let connection = Connection {
+            service,
+            rx,
+            current: None,
+            closed: false,
+            failed: None,
+            calls: Vec::new(),
+            pumps: Vec::new(),
+        };
+
+        (InProcess { tx }, connection)
+    }
+}
+
+impl Service<http::Request<BoxBody>> for InProcess {
+    type Response = http::Response<RecvBody>;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let (mut parts, body) = request.into_parts();
+
+        // Extensions don't cross a real transport either.
+        parts.extensions = http::Extensions::new();
+
+        let (body_tx, body_rx) = mpsc::channel(BODY_BUFFER);
+        let recv = BoxBody::new(Box::new(RecvBody::new(body_rx)));
+        let request = http::Request::from_parts(parts, recv);
+
+        let (tx, rx) = oneshot::channel();
+        let message = Message {
+            request,
+            pump: Pump::new(body, body_tx),
+            tx,
+        };
+
+        // If the connection is gone, the message is dropped and the call
+        // fails as its sender is.
+        let _ = self.tx.unbounded_send(message);
+
+        ResponseFuture { rx }
+    }
+}
+
+impl fmt::Debug for InProcess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InProcess").finish()
+    }
+}
+
+// ===== impl Connection =====
+
+impl<S, B> Connection<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Body + Send + 'static,
+{
+    /// Call the service with the queued requests, for as long as it is ready.
+    fn poll_requests(&mut self) {
+        loop {
+            let message = match self.current.take() {
+                Some(message) => message,
+                None => match self.rx.poll() {
+                    Ok(Async::Ready(Some(message))) => message,
+                    Ok(Async::Ready(None)) => {
+                        trace!("every in-process handle is gone");
+                        self.closed = true;
+                        return;
+                    }
+                    Ok(Async::NotReady) => return,
+                    Err(()) => unreachable!("mpsc::Receiver never fails"),
+                },
+            };
+
+            if message.tx.is_canceled() {
+                continue;
+            }
+
+            if let Some(ref status) = self.failed {
+                let _ = message.tx.send(Err(status.clone()));
+                continue;
+            }
+
+            match self.service.poll_ready() {
+                Ok(Async::Ready(())) => {
+                    let Message { request, pump, tx } = message;
+                    self.pumps.push(Box::new(pump));
+                    self.calls.push(Call {
+                        future: self.service.call(request),
+                        tx: Some(tx),
+                    });
+                }
+                Ok(Async::NotReady) => {
+                    self.current = Some(message);
+                    return;
+                }
+                Err(e) => {
+                    let e: Error = e.into();
+                    debug!("in-process server service failed: {}", e);
+
+                    let status = Status::new(
+                        Code::Unavailable,
+                        format!("in-process server failed: {}", e),
+                    );
+                    let _ = message.tx.send(Err(status.clone()));
+                    self.failed = Some(status);
+                }
+            }
+        }
+    }
+
+    fn poll_calls(&mut self) {
+        let mut i = 0;
+        while i < self.calls.len() {
+            match self.calls[i].poll_respond(&mut self.pumps) {
+                Async::Ready(()) => {
+                    self.calls.swap_remove(i);
+                }
+                Async::NotReady => i += 1,
+            }
+        }
+    }
+
+    fn poll_pumps(&mut self) {
+        let mut i = 0;
+        while i < self.pumps.len() {
+            match self.pumps[i].poll() {
+                Ok(Async::NotReady) => i += 1,
+                _ => {
+                    self.pumps.swap_remove(i);
+                }
+            }
+        }
+    }
+}
+
+impl<S, B> Future for Connection<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Body + Send + 'static,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        if !self.closed {
+            self.poll_requests();
+        }
+        self.poll_calls();
+        self.poll_pumps();
+
+        if self.closed && self.calls.is_empty() && self.pumps.is_empty() {
+            return Ok(Async::Ready(()));
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+impl<S> fmt::Debug for Connection<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("in_process::Connection")
+            .field("calls", &self.calls.len())
+            .field("bodies", &self.pumps.len())
+            .finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl<F, B> Call<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    B: Body + Send + 'static,
+{
+    /// Polls the response future, sending the response once it's ready and
+    /// adding the pump of its body to `pumps`.
+    fn poll_respond(&mut self, pumps: &mut Vec<BoxPump>) -> Async<()> {
+        let tx = self.tx.as_mut().expect("polled after complete");
+
+        // The client gave up on the call.
+        if let Ok(Async::Ready(())) = tx.poll_cancel() {
+            return Async::Ready(());
+        }
+
+        let response = match self.future.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Async::NotReady,
+            Err(e) => {
+                let status = Status::from_error(&*e.into());
+                let _ = self.tx.take().unwrap().send(Err(status));
+                return Async::Ready(());
+            }
+        };
+
+        let (parts, body) = response.into_parts();
+        let (body_tx, body_rx) = mpsc::channel(BODY_BUFFER);
+        let response = http::Response::from_parts(parts, RecvBody::new(body_rx));
+
+        if self.tx.take().unwrap().send(Ok(response)).is_ok() {
+            pumps.push(Box::new(Pump::new(body, body_tx)));
+        }
+
+        Async::Ready(())
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl Future for ResponseFuture {
+    type Item = http::Response<RecvBody>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Ok(response))) => Ok(Async::Ready(response)),
+            Ok(Async::Ready(Err(status))) => Err(status.into()),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(Status::new(Code::Unavailable, "in-process connection is gone").into()),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("in_process::ResponseFuture").finish()
+    }
+}
+
+// ===== impl RecvBody =====
+
+impl RecvBody {
+    fn new(rx: mpsc::Receiver<Frame>) -> Self {
+        RecvBody { rx, trailers: None }
+    }
+
+    fn poll_frame(&mut self) -> Poll<Option<Frame>, Status> {
+        match self.rx.poll() {
+            Ok(ready) => Ok(ready),
+            Err(()) => unreachable!("mpsc::Receiver never fails"),
+        }
+    }
+}
+
+impl HttpBody for RecvBody {
+    type Item = BytesBuf;
+    type Error = Status;
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.trailers.is_some() {
+            return Ok(Async::Ready(None));
+        }
+
+        match try_ready!(self.poll_frame()) {
+            Some(Frame::Data(bytes)) => Ok(Async::Ready(Some(bytes.into_buf()))),
+            Some(Frame::Trailers(trailers)) => {
+                self.trailers = Some(trailers);
+                Ok(Async::Ready(None))
+            }
+            Some(Frame::Error(status)) => Err(status),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        loop {
+            if let Some(trailers) = self.trailers.take() {
+                return Ok(Async::Ready(Some(trailers)));
+            }
+
+            match try_ready!(self.poll_frame()) {
+                // Data that wasn't read is skipped.
+                Some(Frame::Data(_)) => continue,
+                Some(Frame::Trailers(trailers)) => return Ok(Async::Ready(Some(trailers))),
+                Some(Frame::Error(status)) => return Err(status),
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+// ===== impl Pump =====
+
+impl<B> Pump<B> {
+    fn new(body: B, tx: mpsc::Sender<Frame>) -> Self {
+        Pump {
+            body,
+            tx,
+            pending: None,
+            state: PumpState::Data,
+        }
+    }
+}
+
+impl<B> Future for Pump<B>
+where
+    B: Body,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            if let Some(frame) = self.pending.take() {
+                match self.tx.start_send(frame) {
+                    Ok(AsyncSink::Ready) => {}
+                    Ok(AsyncSink::NotReady(frame)) => {
+                        self.pending = Some(frame);
+                        return Ok(Async::NotReady);
+                    }
+                    // The receiving side is gone, so is the rest of the body.
+                    Err(_) => return Ok(Async::Ready(())),
+                }
+            }
+
+            let frame = match self.state {
+                PumpState::Data => match self.body.poll_buf() {
+                    Ok(Async::Ready(Some(buf))) => Frame::Data(buf.collect()),
+                    Ok(Async::Ready(None)) => {
+                        self.state = PumpState::Trailers;
+                        continue;
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        self.state = PumpState::Done;
+                        Frame::Error(Status::from_error(&*e.into()))
+                    }
+                },
+                PumpState::Trailers => match self.body.poll_trailers() {
+                    Ok(Async::Ready(Some(trailers))) => {
+                        self.state = PumpState::Done;
+                        Frame::Trailers(trailers)
+                    }
+                    Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => {
+                        self.state = PumpState::Done;
+                        Frame::Error(Status::from_error(&*e.into()))
+                    }
+                },
+                PumpState::Done => return Ok(Async::Ready(())),
+            };
+
+            self.pending = Some(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client::Grpc;
+    use codec::Encoder;
+    use generic::Encode;
+    use test_util::Server;
+    use Request;
+
+    use futures::future;
+    use futures::stream;
+    use tokio::runtime::current_thread::Runtime;
+
+    fn path() -> http::uri::PathAndQuery {
+        http::uri::PathAndQuery::from_static("/test.Svc/Double")
+    }
+
+    #[test]
+    fn calls_server_without_sockets() {
+        let (transport, connection) = InProcess::pair(Server);
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(connection);
+
+        let mut client = Grpc::new(transport);
+        let response = rt
+            .block_on(client.unary::<_, u32, _>(Request::new(21u32), path()))
+            .unwrap();
+        assert_eq!(response.into_inner(), 42);
+
+        // The connection completes once the client is gone.
+        drop(client);
+        rt.run().unwrap();
+    }
+
+    #[test]
+    fn sends_bodies_and_trailers_through() {
+        let (mut transport, connection) = InProcess::pair(Server);
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(connection);
+
+        let body = Encode::request(Encoder::new(), stream::once(Ok::<_, Status>(21u32)));
+        let request = http::Request::builder()
+            .uri("/test.Svc/Double")
+            .body(BoxBody::new(Box::new(body)))
+            .unwrap();
+        let mut body = rt.block_on(transport.call(request)).unwrap().into_body();
+
+        let data = rt
+            .block_on(future::poll_fn(|| HttpBody::poll_buf(&mut body)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.bytes(), &[0, 0, 0, 0, 2, 0x08, 42][..]);
+
+        let trailers = rt
+            .block_on(future::poll_fn(|| HttpBody::poll_trailers(&mut body)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+    }
+
+    #[test]
+    fn fails_when_connection_is_gone() {
+        let (transport, connection) = InProcess::pair(Server);
+        drop(connection);
+
+        let mut client = Grpc::new(transport);
+        let status = client
+            .unary::<_, u32, _>(Request::new(21u32), path())
+            .wait()
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+}
@@ -1,26 +1,95 @@
+pub mod balance;
+pub mod channel;
 pub mod client_streaming;
+pub mod config;
+pub mod hedge;
+pub mod in_process;
+pub mod pool;
+pub mod resolve;
+pub mod retry;
 pub mod server_streaming;
+pub mod shared;
 pub mod streaming;
+pub mod throttle;
 pub mod unary;
 
+mod backoff;
+mod replay;
+
+pub use self::balance::Balance;
+pub use self::channel::Channel;
+pub use self::hedge::Hedge;
+pub use self::in_process::InProcess;
+pub use self::pool::Pool;
+pub use self::retry::Retry;
+pub use self::shared::Shared;
+pub use self::throttle::Throttle;
+
 use futures::{stream, Future, Poll, Stream};
 use http::{uri, Uri};
 use prost::Message;
+use tokio_timer::Delay;
 
 use body::BoxBody;
 use generic::client::{GrpcService, IntoService};
+use stats::{Handler, Recorder, SharedHandler};
+
+use self::config::ServiceConfig;
+
+use std::cmp;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Debug, Clone)]
 pub struct Grpc<T> {
     /// The inner HTTP/2.0 service.
     inner: T,
+
+    /// Per-method settings applied to each call.
+    config: Option<Arc<ServiceConfig>>,
+
+    /// Receives the events of each call.
+    stats: Option<SharedHandler>,
 }
 
+/// Per-call option making a call wait for a connection, rather than fail
+/// fast with `Code::Unavailable` while the channel is reconnecting.
+///
+/// Set it in the extensions of a `Request`. It takes precedence over the
+/// `waitForReady` setting of the service config. A waiting call fails with
+/// `Code::DeadlineExceeded` once its `grpc-timeout` expires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitForReady(pub bool);
+
 /// Convert a stream of protobuf messages to an HTTP body payload.
 ///
 /// TODO: Rename to `IntoEncode` or something...
 pub trait Encodable<T> {
     fn into_encode(self) -> T;
+
+    /// Like `into_encode`, but failing with `Code::ResourceExhausted` when a
+    /// message is larger than `limit` bytes.
+    ///
+    /// By default the limit is ignored.
+    fn into_limited_encode(self, limit: Option<usize>) -> T
+    where
+        Self: Sized,
+    {
+        let _ = limit;
+        self.into_encode()
+    }
+
+    /// Like `into_limited_encode`, also reporting each message sent to
+    /// `stats`.
+    ///
+    /// By default nothing is reported.
+    fn into_recorded_encode(self, limit: Option<usize>, stats: Recorder) -> T
+    where
+        Self: Sized,
+    {
+        let _ = stats;
+        self.into_limited_encode(limit)
+    }
 }
 
 // ===== impl Grpc =====
@@ -28,7 +97,37 @@ pub trait Encodable<T> {
 impl<T> Grpc<T> {
     /// Create a new `Grpc` instance backed by the given HTTP service.
     pub fn new(inner: T) -> Self {
-        Grpc { inner }
+        Grpc {
+            inner,
+            config: None,
+            stats: None,
+        }
+    }
+
+    /// Create a new `Grpc` instance applying `config` to each call.
+    ///
+    /// The method config matching a call's path sets its message size limits
+    /// and timeout, unless the call was made with a shorter `grpc-timeout`,
+    /// and is passed on as a request extension for `Retry`, `Hedge` and other
+    /// services to use, along with the config's retry throttle.
+    ///
+    /// Calls with a timeout fail with `Code::DeadlineExceeded` once it
+    /// expires, whether or not they were given a config.
+    pub fn with_service_config(inner: T, config: ServiceConfig) -> Self {
+        Grpc {
+            inner,
+            config: Some(Arc::new(config)),
+            stats: None,
+        }
+    }
+
+    /// Report the events of each call to `handler`.
+    pub fn stats_handler<H>(mut self, handler: H) -> Self
+    where
+        H: Handler,
+    {
+        self.stats = Some(SharedHandler::new(handler));
+        self
     }
 
     pub fn poll_ready<R>(&mut self) -> Poll<(), ::Status>
@@ -45,8 +144,14 @@ impl<T> Grpc<T> {
         T: GrpcService<R>,
     {
         use tower_util::Ready;
+        let config = self.config;
+        let stats = self.stats;
         Ready::new(self.inner.into_service())
-            .map(|IntoService(inner)| Grpc { inner })
+            .map(|IntoService(inner)| Grpc {
+                inner,
+                config,
+                stats,
+            })
             .map_err(|err| ::Status::from_error(&*(err.into())))
     }
 
@@ -59,7 +164,8 @@ impl<T> Grpc<T> {
         T: GrpcService<R>,
         unary::Once<M1>: Encodable<R>,
     {
-        let request = request.map(|v| stream::once(Ok(v)));
+        let mut request = request.map(|v| stream::once(Ok(v)));
+        request.extensions_mut().insert(hedge::Unary);
         let response = self.client_streaming(request, path);
 
         unary::ResponseFuture::new(response)
@@ -113,6 +219,21 @@ impl<T> Grpc<T> {
 
         // TODO: validate the path
 
+        let stats = Recorder::client(self.stats.as_ref(), path.path());
+
+        let method = self
+            .config
+            .as_ref()
+            .and_then(|config| config.matching(path.path()))
+            .cloned();
+
+        let max_request = method
+            .as_ref()
+            .and_then(|method| method.max_request_message_bytes());
+        let max_response = method
+            .as_ref()
+            .and_then(|method| method.max_response_message_bytes());
+
         // Get the gRPC's method URI
         let mut parts = uri::Parts::default();
         parts.path_and_query = Some(path);
@@ -121,7 +242,7 @@ impl<T> Grpc<T> {
         let uri = Uri::from_parts(parts).expect("path_and_query only is valid Uri");
 
         // Convert the request body
-        let request = request.map(Encodable::into_encode);
+        let request = request.map(|body| body.into_recorded_encode(max_request, stats.clone()));
 
         // Convert to an HTTP request
         let mut request = request.into_http(uri);
@@ -138,10 +259,45 @@ impl<T> Grpc<T> {
             .headers_mut()
             .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
 
+        let throttle = self
+            .config
+            .as_ref()
+            .and_then(|config| config.retry_throttling());
+        if let Some(throttle) = throttle {
+            request.extensions_mut().insert(throttle.clone());
+        }
+
+        // The call's deadline is the earlier of its own `grpc-timeout` and
+        // the method config's timeout.
+        let timeout = request
+            .headers()
+            .get(config::TIMEOUT)
+            .and_then(config::decode_timeout);
+        let config_timeout = method.as_ref().and_then(|method| method.timeout());
+        let timeout = match (timeout, config_timeout) {
+            (Some(timeout), Some(config_timeout)) => Some(cmp::min(timeout, config_timeout)),
+            (timeout, config_timeout) => timeout.or(config_timeout),
+        };
+        if let Some(timeout) = timeout {
+            request
+                .headers_mut()
+                .insert(config::TIMEOUT, config::encode_timeout(timeout));
+        }
+        let deadline = timeout.map(|timeout| Delay::new(Instant::now() + timeout));
+
+        if let Some(method) = method {
+            request.extensions_mut().insert(method);
+        }
+
+        stats.out_header(request.headers());
+
         // Call the inner HTTP service
         let response = self.inner.call(request);
 
         streaming::ResponseFuture::new(response)
+            .max_message_size(max_response)
+            .stats(stats)
+            .deadline(deadline)
     }
 }
 
@@ -153,10 +309,131 @@ where
     U: Message + 'static,
 {
     fn into_encode(self) -> BoxBody {
+        self.into_limited_encode(None)
+    }
+
+    fn into_limited_encode(self, limit: Option<usize>) -> BoxBody {
+        self.into_recorded_encode(limit, Recorder::default())
+    }
+
+    fn into_recorded_encode(self, limit: Option<usize>, stats: Recorder) -> BoxBody {
         use codec::Encoder;
         use generic::Encode;
 
-        let encode = Encode::request(Encoder::new(), self);
+        let encode = Encode::request(Encoder::new(), self)
+            .max_message_size(limit)
+            .stats(stats);
         BoxBody::new(Box::new(encode))
     }
 }
+
+#[cfg(all(test, feature = "protobuf"))]
+mod tests {
+    use super::*;
+    use body::{BytesBuf, HttpBody};
+    use {Code, Request, Status};
+
+    use futures::future::{self, Either, Empty, FutureResult};
+    use futures::Async;
+    use http;
+    use tokio::runtime::current_thread::Runtime;
+    use tower_service::Service;
+
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Never completes a call: it either never responds, or responds with a
+    /// body that never ends. Records the `grpc-timeout` of each call.
+    #[derive(Clone, Default)]
+    struct Stalled {
+        respond: bool,
+        timeouts: Arc<Mutex<Vec<http::HeaderValue>>>,
+    }
+
+    struct Pending;
+
+    impl Service<http::Request<BoxBody>> for Stalled {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = Either<Empty<Self::Response, Status>, FutureResult<Self::Response, Status>>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            if let Some(timeout) = request.headers().get(config::TIMEOUT) {
+                self.timeouts.lock().unwrap().push(timeout.clone());
+            }
+
+            if self.respond {
+                let body = BoxBody::new(Box::new(Pending));
+                Either::B(future::ok(http::Response::new(body)))
+            } else {
+                Either::A(future::empty())
+            }
+        }
+    }
+
+    impl HttpBody for Pending {
+        type Item = BytesBuf;
+        type Error = Status;
+
+        fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            Ok(Async::NotReady)
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+            Ok(Async::NotReady)
+        }
+    }
+
+    fn config() -> ServiceConfig {
+        let json =
+            r#"{ "methodConfig": [{ "name": [{ "service": "test.Svc" }], "timeout": "0.01s" }] }"#;
+        ServiceConfig::from_json(json).unwrap()
+    }
+
+    fn path() -> uri::PathAndQuery {
+        uri::PathAndQuery::from_static("/test.Svc/Method")
+    }
+
+    #[test]
+    fn fails_calls_without_a_response_at_the_deadline() {
+        let transport = Stalled::default();
+        let mut client = Grpc::with_service_config(transport.clone(), config());
+        let mut rt = Runtime::new().unwrap();
+
+        // The call's own timeout is later than the config's.
+        let mut request = Request::new(1u32);
+        request
+            .metadata_mut()
+            .insert("grpc-timeout", "1S".parse().unwrap());
+
+        let status = rt
+            .block_on(client.unary::<_, u32, _>(request, path()))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+
+        let timeouts = transport.timeouts.lock().unwrap();
+        assert_eq!(
+            *timeouts,
+            [config::encode_timeout(Duration::from_millis(10))]
+        );
+    }
+
+    #[test]
+    fn fails_calls_with_an_unfinished_body_at_the_deadline() {
+        let transport = Stalled {
+            respond: true,
+            ..Stalled::default()
+        };
+        let mut client = Grpc::with_service_config(transport, config());
+        let mut rt = Runtime::new().unwrap();
+
+        let status = rt
+            .block_on(client.unary::<_, u32, _>(Request::new(1u32), path()))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+    }
+}
@@ -0,0 +1,485 @@
+//! Spreading calls to one endpoint over several connections.
+
+use super::channel::{self, Channel, Connectivity};
+use body::{BoxBody, HttpBody};
+use error::Error;
+use {Code, Status};
+
+use futures::task::AtomicTask;
+use futures::{Async, Future, Poll};
+use http::{self, HeaderMap, Uri};
+use tower_service::Service;
+use tower_util::MakeService;
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A `GrpcService` keeping a pool of HTTP/2 connections to one endpoint.
+///
+/// Each call is sent on the ready connection with the fewest calls in
+/// flight. A call is in flight until its response body is dropped. When
+/// every connection is saturated, either because it already has the
+/// maximum number of calls in flight or because it isn't ready for another
+/// stream, a new connection is opened, up to the maximum number of
+/// connections.
+///
+/// Each connection is a `Channel`, and fails fast the same way while
+/// reconnecting.
+pub struct Pool<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    origin: Uri,
+    target: T,
+    make: M,
+    config: Builder,
+    connections: Vec<Connection<M, T>>,
+    /// The connection picked by `poll_ready`, used by the following `call`.
+    ready: Option<usize>,
+    /// Notified when a call completes, as a saturated connection may now
+    /// be used.
+    task: Arc<AtomicTask>,
+}
+
+/// Configures a `Pool`.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    max_connections: usize,
+    max_streams: usize,
+    channel: channel::Builder,
+}
+
+/// Response future returned by `Pool`.
+pub struct ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    inner: channel::ResponseFuture<M, T>,
+    guard: Option<Guard>,
+}
+
+/// Response body returned by `Pool`, keeping its call in flight.
+#[derive(Debug)]
+pub struct ResponseBody<B> {
+    inner: B,
+    _guard: Guard,
+}
+
+struct Connection<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    channel: Channel<M, T>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Counts a call as in flight on a connection until dropped.
+#[derive(Debug)]
+struct Guard {
+    in_flight: Arc<AtomicUsize>,
+    task: Arc<AtomicTask>,
+}
+
+// ===== impl Builder =====
+
+impl Builder {
+    /// Create a new `Builder` with the default limits.
+    pub fn new() -> Self {
+        Builder {
+            max_connections: 4,
+            max_streams: 100,
+            channel: channel::Builder::new(),
+        }
+    }
+
+    /// Set the maximum number of connections. Defaults to 4.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Set the maximum number of calls in flight on each connection.
+    /// Defaults to 100, the smallest limit HTTP/2 peers should allow.
+    pub fn max_streams_per_connection(mut self, max: usize) -> Self {
+        self.max_streams = max;
+        self
+    }
+
+    /// Configure each connection with `builder`.
+    pub fn channel(mut self, builder: channel::Builder) -> Self {
+        self.channel = builder;
+        self
+    }
+
+    /// Build a `Pool` to `origin`, connecting to `target` with `make`.
+    ///
+    /// `origin` must have a scheme and an authority, such as
+    /// `http://example.com:50051`.
+    pub fn build<M, T>(self, origin: Uri, target: T, make: M) -> Result<Pool<M, T>, Error>
+    where
+        M: MakeService<T, http::Request<BoxBody>> + Clone,
+        T: Clone,
+    {
+        if self.max_connections == 0 || self.max_streams == 0 {
+            return Err("pool limits must be positive".into());
+        }
+
+        let first = self
+            .channel
+            .clone()
+            .build(origin.clone(), target.clone(), make.clone())?;
+
+        Ok(Pool {
+            origin,
+            target,
+            make,
+            config: self,
+            connections: vec![Connection::new(first)],
+            ready: None,
+            task: Arc::new(AtomicTask::new()),
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+// ===== impl Pool =====
+
+impl<M, T> Pool<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>> + Clone,
+    T: Clone,
+{
+    /// Create a `Pool` to `origin` with the default configuration.
+    ///
+    /// See `Builder::build`.
+    pub fn new(origin: Uri, target: T, make: M) -> Result<Self, Error> {
+        Builder::new().build(origin, target, make)
+    }
+
+    /// Returns the number of calls in flight on each connection.
+    pub fn loads(&self) -> Vec<usize> {
+        self.connections.iter().map(Connection::load).collect()
+    }
+
+    fn connect(&mut self) -> Result<(), Error> {
+        debug!(
+            "opening connection {} to {}",
+            self.connections.len() + 1,
+            self.origin
+        );
+
+        let channel = self.config.channel.clone().build(
+            self.origin.clone(),
+            self.target.clone(),
+            self.make.clone(),
+        )?;
+
+        self.connections.push(Connection::new(channel));
+        Ok(())
+    }
+}
+
+impl<M, T, B> Service<http::Request<BoxBody>> for Pool<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>, Response = http::Response<B>> + Clone,
+    M::MakeError: Into<Error>,
+    M::Error: Into<Error>,
+    T: Clone,
+{
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<M, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.ready = None;
+
+        // Completed calls notify this task, in case they free up a
+        // saturated connection.
+        self.task.register();
+
+        loop {
+            let mut best: Option<(usize, usize)> = None;
+            let mut connecting = false;
+            let mut failing = false;
+
+            for (idx, connection) in self.connections.iter_mut().enumerate() {
+                let load = connection.load();
+                if load >= self.config.max_streams {
+                    continue;
+                }
+
+                let ready = connection.channel.poll_ready()?;
+                match (ready, connection.channel.connectivity()) {
+                    (Async::Ready(()), Connectivity::Ready) => {
+                        if best.map_or(true, |(_, least)| load < least) {
+                            best = Some((idx, load));
+                        }
+                    }
+                    (Async::Ready(()), _) => failing = true,
+                    // Out of streams.
+                    (Async::NotReady, Connectivity::Ready) => {}
+                    (Async::NotReady, _) => connecting = true,
+                }
+            }
+
+            if let Some((idx, _)) = best {
+                self.ready = Some(idx);
+                return Ok(Async::Ready(()));
+            }
+
+            // Only open another connection if the existing ones are all
+            // saturated, not while they are connecting or failing.
+            if !connecting && !failing && self.connections.len() < self.config.max_connections {
+                self.connect()?;
+                continue;
+            }
+
+            if failing && !connecting {
+                // Fail fast, like a single channel.
+                return Ok(Async::Ready(()));
+            }
+
+            return Ok(Async::NotReady);
+        }
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let idx = match self.ready.take() {
+            Some(idx) => idx,
+            // Let the first connection wait for the endpoint.
+            None if channel::wait_for_ready(&request) => 0,
+            None => {
+                let status = Status::new(Code::Unavailable, "no connection is ready");
+                return ResponseFuture {
+                    inner: channel::ResponseFuture::failed(status),
+                    guard: None,
+                };
+            }
+        };
+
+        let connection = &mut self.connections[idx];
+        let guard = Guard::new(connection.in_flight.clone(), self.task.clone());
+
+        ResponseFuture {
+            inner: connection.channel.call(request),
+            guard: Some(guard),
+        }
+    }
+}
+
+impl<M, T> fmt::Debug for Pool<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let loads: Vec<_> = self.connections.iter().map(Connection::load).collect();
+
+        f.debug_struct("Pool")
+            .field("origin", &self.origin)
+            .field("loads", &loads)
+            .finish()
+    }
+}
+
+// ===== impl Connection =====
+
+impl<M, T> Connection<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn new(channel: Channel<M, T>) -> Self {
+        Connection {
+            channel,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn load(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+}
+
+// ===== impl Guard =====
+
+impl Guard {
+    fn new(in_flight: Arc<AtomicUsize>, task: Arc<AtomicTask>) -> Self {
+        in_flight.fetch_add(1, Ordering::AcqRel);
+        Guard { in_flight, task }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.task.notify();
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<M, T, B> Future for ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>, Response = http::Response<B>>,
+    M::MakeError: Into<Error>,
+    M::Error: Into<Error>,
+    T: Clone,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+        let guard = self.guard.take().expect("polled after complete");
+
+        Ok(Async::Ready(response.map(|inner| ResponseBody {
+            inner,
+            _guard: guard,
+        })))
+    }
+}
+
+impl<M, T> fmt::Debug for ResponseFuture<M, T>
+where
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("pool::ResponseFuture")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> HttpBody for ResponseBody<B>
+where
+    B: HttpBody,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll_buf()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Never;
+    use futures::future::{self, FutureResult};
+
+    #[derive(Clone, Default)]
+    struct MockConnect {
+        connects: Arc<AtomicUsize>,
+    }
+
+    struct MockConnection;
+
+    impl Service<()> for MockConnect {
+        type Response = MockConnection;
+        type Error = Never;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            future::ok(MockConnection)
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for MockConnection {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            future::ok(http::Response::new(BoxBody::empty()))
+        }
+    }
+
+    fn call(pool: &mut Pool<MockConnect, ()>) -> Option<http::Response<ResponseBody<BoxBody>>> {
+        future::lazy(|| match pool.poll_ready().unwrap() {
+            Async::Ready(()) => pool
+                .call(http::Request::new(BoxBody::empty()))
+                .wait()
+                .map(Some),
+            Async::NotReady => Ok(None),
+        })
+        .wait()
+        .unwrap()
+    }
+
+    #[test]
+    fn opens_connections_when_saturated() {
+        let connect = MockConnect::default();
+        let mut pool = Builder::new()
+            .max_connections(2)
+            .max_streams_per_connection(1)
+            .build("http://example.com".parse().unwrap(), (), connect.clone())
+            .unwrap();
+
+        let first = call(&mut pool).unwrap();
+        let second = call(&mut pool).unwrap();
+        assert_eq!(connect.connects.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.loads(), [1, 1]);
+
+        // Both connections are saturated.
+        assert!(call(&mut pool).is_none());
+
+        drop(first);
+        assert_eq!(pool.loads(), [0, 1]);
+        let _third = call(&mut pool).unwrap();
+        assert_eq!(pool.loads(), [1, 1]);
+
+        drop(second);
+        assert_eq!(connect.connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn routes_to_least_loaded_connection() {
+        let mut pool = Builder::new()
+            .max_connections(2)
+            .max_streams_per_connection(2)
+            .build(
+                "http://example.com".parse().unwrap(),
+                (),
+                MockConnect::default(),
+            )
+            .unwrap();
+
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            responses.push(call(&mut pool).unwrap());
+        }
+        assert_eq!(pool.loads(), [2, 1]);
+
+        responses.remove(0);
+        responses.remove(0);
+        assert_eq!(pool.loads(), [0, 1]);
+
+        responses.push(call(&mut pool).unwrap());
+        assert_eq!(pool.loads(), [1, 1]);
+    }
+}
@@ -0,0 +1,382 @@
+use body::{BoxBody, BytesBuf, HttpBody};
+use {Code, Status};
+
+use bytes::{Bytes, IntoBuf};
+use futures::task::{self, Task};
+use futures::{Async, Poll};
+use http::{self, HeaderMap};
+
+use std::sync::{Arc, Mutex};
+
+/// A request body that can be sent more than once.
+///
+/// Chunks read from the original body are buffered, so that every attempt
+/// created with `body` sees the whole request. Once the buffer limit is
+/// reached, no more chunks are buffered and no more attempts can be made.
+/// After `commit`, no more attempts can be made either, but the buffer is
+/// kept until the remaining attempt has replayed it.
+///
+/// Attempts may read concurrently; the one furthest ahead drives the
+/// original body.
+#[derive(Debug, Clone)]
+pub(crate) struct Replay {
+    shared: Arc<Mutex<Shared>>,
+}
+
+/// The parts of a request needed to send it again.
+///
+/// Extensions can't be cloned, so only the original request carries them.
+#[derive(Debug)]
+pub(crate) struct Head {
+    method: http::Method,
+    uri: http::Uri,
+    version: http::Version,
+    headers: HeaderMap,
+}
+
+#[derive(Debug)]
+struct ReplayBody {
+    shared: Arc<Mutex<Shared>>,
+    /// Index of the next chunk this attempt reads.
+    pos: usize,
+}
+
+#[derive(Debug)]
+struct Shared {
+    source: BoxBody,
+    chunks: Vec<Bytes>,
+    /// Number of chunks read from `source`, buffered or not.
+    produced: usize,
+    buffered: usize,
+    limit: usize,
+    /// Whether more attempts can be made.
+    replayable: bool,
+    /// Whether the buffer limit was exceeded.
+    overflowed: bool,
+    /// Number of live attempt bodies.
+    bodies: usize,
+    eos: bool,
+    trailers: Option<Option<HeaderMap>>,
+    error: Option<Status>,
+    /// Attempts waiting on `source`, which only notifies the last poller.
+    waiters: Vec<Task>,
+}
+
+// ===== impl Replay =====
+
+impl Replay {
+    /// Buffer up to `limit` bytes of `source`.
+    pub fn new(source: BoxBody, limit: usize) -> Self {
+        let shared = Shared {
+            source,
+            chunks: Vec::new(),
+            produced: 0,
+            buffered: 0,
+            limit,
+            replayable: true,
+            overflowed: false,
+            bodies: 0,
+            eos: false,
+            trailers: None,
+            error: None,
+            waiters: Vec::new(),
+        };
+
+        Replay {
+            shared: Arc::new(Mutex::new(shared)),
+        }
+    }
+
+    /// Returns a body replaying the request from the start, or `None` if
+    /// the request can no longer be replayed.
+    pub fn body(&self) -> Option<BoxBody> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if !shared.replayable {
+            return None;
+        }
+
+        shared.bodies += 1;
+
+        let body = ReplayBody {
+            shared: self.shared.clone(),
+            pos: 0,
+        };
+        Some(BoxBody::new(Box::new(body)))
+    }
+
+    /// Returns whether another attempt can be made.
+    pub fn is_replayable(&self) -> bool {
+        self.shared.lock().unwrap().replayable
+    }
+
+    /// Stop making attempts, since the response of one was accepted.
+    ///
+    /// That attempt may still be replaying the buffer, so it is only freed
+    /// once the attempt has caught up.
+    pub fn commit(&self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.replayable = false;
+
+        if shared.bodies == 0 {
+            shared.chunks = Vec::new();
+        }
+    }
+}
+
+// ===== impl Head =====
+
+impl Head {
+    pub fn new(parts: &http::request::Parts) -> Self {
+        Head {
+            method: parts.method.clone(),
+            uri: parts.uri.clone(),
+            version: parts.version,
+            headers: parts.headers.clone(),
+        }
+    }
+
+    /// Build another request with this head.
+    pub fn request(&self, body: BoxBody) -> http::Request<BoxBody> {
+        let mut request = http::Request::new(body);
+        *request.method_mut() = self.method.clone();
+        *request.uri_mut() = self.uri.clone();
+        *request.version_mut() = self.version;
+        *request.headers_mut() = self.headers.clone();
+        request
+    }
+}
+
+// ===== impl ReplayBody =====
+
+impl HttpBody for ReplayBody {
+    type Item = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared.eos && self.pos == shared.produced && shared.trailers.is_some()
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut shared = self.shared.lock().unwrap();
+
+        // The last attempt of a committed request frees the buffer once it
+        // has replayed it.
+        if !shared.replayable && shared.bodies == 1 && self.pos >= shared.chunks.len() {
+            shared.chunks = Vec::new();
+        }
+
+        if self.pos < shared.chunks.len() {
+            let chunk = shared.chunks[self.pos].clone();
+            self.pos += 1;
+            return Ok(Async::Ready(Some(chunk.into_buf())));
+        }
+
+        if self.pos != shared.produced {
+            // Another attempt read past the end of the buffer.
+            return Err(Status::new(
+                Code::Cancelled,
+                "request body is no longer buffered",
+            ));
+        }
+
+        if let Some(ref error) = shared.error {
+            return Err(error.clone());
+        }
+
+        if shared.eos {
+            return Ok(Async::Ready(None));
+        }
+
+        let polled = shared.source.poll_buf();
+        let chunk = match polled {
+            Ok(Async::NotReady) => {
+                shared.waiters.push(task::current());
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(Some(buf))) => {
+                let pos = buf.position() as usize;
+                Some(buf.into_inner().slice_from(pos))
+            }
+            Ok(Async::Ready(None)) => None,
+            Err(e) => {
+                shared.error = Some(e.clone());
+                shared.notify();
+                return Err(e);
+            }
+        };
+
+        match chunk {
+            Some(chunk) => {
+                shared.produced += 1;
+                self.pos += 1;
+
+                // Other attempts still need the chunk, even once committed.
+                if !shared.overflowed && (shared.replayable || shared.bodies > 1) {
+                    shared.buffered += chunk.len();
+
+                    if shared.buffered > shared.limit {
+                        trace!("request body exceeds replay buffer");
+                        shared.replayable = false;
+                        shared.overflowed = true;
+                        shared.chunks = Vec::new();
+                    } else {
+                        shared.chunks.push(chunk.clone());
+                    }
+                }
+
+                shared.notify();
+                Ok(Async::Ready(Some(chunk.into_buf())))
+            }
+            None => {
+                shared.eos = true;
+                shared.notify();
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if let Some(ref trailers) = shared.trailers {
+            return Ok(Async::Ready(trailers.clone()));
+        }
+
+        match shared.source.poll_trailers() {
+            Ok(Async::Ready(trailers)) => {
+                shared.trailers = Some(trailers.clone());
+                shared.notify();
+                Ok(Async::Ready(trailers))
+            }
+            Ok(Async::NotReady) => {
+                shared.waiters.push(task::current());
+                Ok(Async::NotReady)
+            }
+            Err(e) => {
+                shared.error = Some(e.clone());
+                shared.notify();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Drop for ReplayBody {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.bodies -= 1;
+
+        if !shared.replayable && shared.bodies == 0 {
+            shared.chunks = Vec::new();
+        }
+    }
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    fn notify(&mut self) {
+        for waiter in self.waiters.drain(..) {
+            waiter.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Buf;
+    use futures::{future, Future};
+    use std::collections::VecDeque;
+
+    struct Chunks(VecDeque<&'static str>);
+
+    impl HttpBody for Chunks {
+        type Item = BytesBuf;
+        type Error = Status;
+
+        fn is_end_stream(&self) -> bool {
+            self.0.is_empty()
+        }
+
+        fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            let chunk = self.0.pop_front().map(|s| Bytes::from(s).into_buf());
+            Ok(Async::Ready(chunk))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    fn body(chunks: &[&'static str]) -> BoxBody {
+        BoxBody::new(Box::new(Chunks(chunks.iter().cloned().collect())))
+    }
+
+    fn read_all(mut body: BoxBody) -> Result<Vec<u8>, Status> {
+        future::poll_fn(|| {
+            let mut data = Vec::new();
+            while let Some(buf) = try_ready!(body.poll_buf()) {
+                data.extend_from_slice(buf.bytes());
+            }
+            Ok(Async::Ready(data))
+        })
+        .wait()
+    }
+
+    fn read_chunk(body: &mut BoxBody) -> Vec<u8> {
+        let buf = future::poll_fn(|| body.poll_buf()).wait().unwrap().unwrap();
+        buf.bytes().to_vec()
+    }
+
+    #[test]
+    fn attempts_see_the_whole_body() {
+        let replay = Replay::new(body(&["a", "b", "c"]), 1024);
+
+        let first = read_all(replay.body().unwrap()).unwrap();
+        let second = read_all(replay.body().unwrap()).unwrap();
+
+        assert_eq!(first, b"abc");
+        assert_eq!(second, b"abc");
+    }
+
+    #[test]
+    fn commit_stops_replaying() {
+        let replay = Replay::new(body(&["a"]), 1024);
+        let first = replay.body().unwrap();
+
+        replay.commit();
+        assert!(replay.body().is_none());
+        assert_eq!(read_all(first).unwrap(), b"a");
+    }
+
+    #[test]
+    fn commit_keeps_buffer_until_replayed() {
+        let replay = Replay::new(body(&["a", "b", "c"]), 1024);
+        let mut leader = replay.body().unwrap();
+        let mut behind = replay.body().unwrap();
+
+        assert_eq!(read_chunk(&mut leader), b"a");
+        assert_eq!(read_chunk(&mut leader), b"b");
+        assert_eq!(read_chunk(&mut behind), b"a");
+
+        // The attempt behind is accepted partway through its replay, while
+        // the other one still reads ahead before going away.
+        replay.commit();
+        assert_eq!(read_chunk(&mut leader), b"c");
+        drop(leader);
+
+        assert_eq!(read_all(behind).unwrap(), b"bc");
+        assert!(replay.shared.lock().unwrap().chunks.is_empty());
+    }
+
+    #[test]
+    fn buffer_limit_stops_replaying() {
+        let replay = Replay::new(body(&["aaaa", "bbbb", "cccc"]), 6);
+
+        assert_eq!(read_all(replay.body().unwrap()).unwrap(), b"aaaabbbbcccc");
+        assert!(!replay.is_replayable());
+    }
+}
@@ -0,0 +1,644 @@
+//! Resolving a target name into the endpoints to balance over.
+//!
+//! Targets use the gRPC naming syntax:
+//!
+//! - `dns:///svc.local:443`, or just `svc.local:443`, is resolved with DNS,
+//!   and re-resolved periodically.
+//! - `static:///10.0.0.1:50051,10.0.0.2:50051` is a fixed set of addresses.
+
+use super::balance::{Balance, Policy};
+use super::channel::{self, Connectivity, ResponseFuture};
+use body::BoxBody;
+use error::Error;
+
+use futures::sync::oneshot;
+use futures::{future, Async, Future, Poll};
+use http::uri::{Authority, Scheme};
+use http::{self, Uri};
+use tokio_timer::Delay;
+use tower_service::Service;
+use tower_util::MakeService;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The port used when a `dns` target doesn't have one.
+const DEFAULT_PORT: u16 = 443;
+
+/// Resolves a host name into a set of addresses.
+pub trait Resolve {
+    /// The future of the resolved addresses.
+    type Future: Future<Item = Vec<SocketAddr>, Error = Error>;
+
+    /// Resolve `host`, returning addresses with `port`.
+    fn resolve(&mut self, host: &str, port: u16) -> Self::Future;
+}
+
+/// A parsed target name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A host name to resolve.
+    Dns { host: String, port: u16 },
+    /// A fixed set of addresses.
+    Static(Vec<SocketAddr>),
+}
+
+/// Resolves names with the system resolver.
+///
+/// Each resolution runs `getaddrinfo` on a new thread, so as not to block
+/// the event loop.
+#[derive(Debug, Clone, Default)]
+pub struct Dns {
+    _p: (),
+}
+
+/// Future returned by `Dns`.
+#[derive(Debug)]
+pub struct DnsFuture {
+    rx: Result<oneshot::Receiver<io::Result<Vec<SocketAddr>>>, Option<io::Error>>,
+}
+
+/// An in-process resolver, for tests.
+///
+/// Names resolve to the addresses last set with `Stub::set`; clones share
+/// the same names.
+#[derive(Debug, Clone, Default)]
+pub struct Stub {
+    hosts: Arc<Mutex<HashMap<String, Vec<IpAddr>>>>,
+}
+
+/// Configures a `Resolved` balancer.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    policy: Policy,
+    interval: Duration,
+    scheme: Scheme,
+    channel: channel::Builder,
+}
+
+/// A `GrpcService` balancing over the endpoints a target resolves to.
+///
+/// The target is resolved the first time the service is polled for
+/// readiness, and again every re-resolution interval. Endpoints that
+/// disappear from the resolved set are removed from the balancer. If a
+/// resolution fails, the previous endpoints are kept.
+///
+/// Requests are sent with the host and port of a `dns` target as their
+/// authority, whichever address they are sent to.
+pub struct Resolved<R, M, T, F>
+where
+    R: Resolve,
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    balance: Balance<M, T>,
+    resolver: R,
+    target: Target,
+    scheme: Scheme,
+    to_target: F,
+    interval: Duration,
+    state: State<R::Future>,
+    /// Whether a resolution has completed, successfully or not.
+    resolved: bool,
+}
+
+enum State<F> {
+    Idle,
+    Resolving(F),
+    Waiting(Delay),
+    Static,
+}
+
+// ===== impl Target =====
+
+impl Target {
+    /// Parse a target name.
+    pub fn parse(target: &str) -> Result<Self, Error> {
+        if let Some(rest) = strip_scheme(target, "static") {
+            let addrs = rest
+                .split(',')
+                .map(|addr| {
+                    addr.parse::<SocketAddr>()
+                        .map_err(|_| format!("invalid static address: {:?}", addr))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            return Ok(Target::Static(addrs));
+        }
+
+        let name = strip_scheme(target, "dns").unwrap_or(target);
+        let authority = name
+            .parse::<Authority>()
+            .map_err(|_| format!("invalid target name: {:?}", name))?;
+
+        let host = authority.host();
+        // IPv6 literals are bracketed in the name, but not for resolving.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        Ok(Target::Dns {
+            host: host.to_string(),
+            port: authority.port_part().map_or(DEFAULT_PORT, |p| p.as_u16()),
+        })
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Target::parse(s)
+    }
+}
+
+/// Strips `scheme:///` or `scheme:` from `target`.
+fn strip_scheme<'a>(target: &'a str, scheme: &str) -> Option<&'a str> {
+    if !target.starts_with(scheme) || !target[scheme.len()..].starts_with(':') {
+        return None;
+    }
+
+    let rest = &target[scheme.len() + 1..];
+    if rest.starts_with("///") {
+        Some(&rest[3..])
+    } else {
+        Some(rest)
+    }
+}
+
+// ===== impl Dns =====
+
+impl Dns {
+    /// Create a new `Dns` resolver.
+    pub fn new() -> Self {
+        Dns::default()
+    }
+}
+
+impl Resolve for Dns {
+    type Future = DnsFuture;
+
+    fn resolve(&mut self, host: &str, port: u16) -> Self::Future {
+        let (tx, rx) = oneshot::channel();
+        let host = host.to_string();
+
+        let spawn = thread::Builder::new()
+            .name("grpc-dns".into())
+            .spawn(move || {
+                trace!("resolving {}", host);
+                let addrs = (&host[..], port)
+                    .to_socket_addrs()
+                    .map(|addrs| addrs.collect());
+                let _ = tx.send(addrs);
+            });
+
+        DnsFuture {
+            rx: spawn.map(|_| rx).map_err(Some),
+        }
+    }
+}
+
+impl Future for DnsFuture {
+    type Item = Vec<SocketAddr>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx {
+            Ok(ref mut rx) => match rx.poll() {
+                Ok(Async::Ready(addrs)) => Ok(Async::Ready(addrs?)),
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                Err(_) => Err("resolver thread panicked".into()),
+            },
+            Err(ref mut err) => Err(err.take().expect("polled after complete").into()),
+        }
+    }
+}
+
+// ===== impl Stub =====
+
+impl Stub {
+    /// Create a new `Stub` resolver, without any names.
+    pub fn new() -> Self {
+        Stub::default()
+    }
+
+    /// Set the addresses `host` resolves to.
+    pub fn set(&self, host: &str, addrs: Vec<IpAddr>) {
+        self.hosts.lock().unwrap().insert(host.to_string(), addrs);
+    }
+
+    /// Make `host` fail to resolve.
+    pub fn remove(&self, host: &str) {
+        self.hosts.lock().unwrap().remove(host);
+    }
+}
+
+impl Resolve for Stub {
+    type Future = future::FutureResult<Vec<SocketAddr>, Error>;
+
+    fn resolve(&mut self, host: &str, port: u16) -> Self::Future {
+        let result = match self.hosts.lock().unwrap().get(host) {
+            Some(addrs) => Ok(addrs.iter().map(|ip| SocketAddr::new(*ip, port)).collect()),
+            None => Err(format!("unknown host: {:?}", host).into()),
+        };
+
+        future::result(result)
+    }
+}
+
+// ===== impl Builder =====
+
+impl Builder {
+    /// Create a new `Builder`, using round-robin balancing and re-resolving
+    /// every 30 seconds.
+    pub fn new() -> Self {
+        Builder {
+            policy: Policy::RoundRobin,
+            interval: Duration::from_secs(30),
+            scheme: Scheme::HTTP,
+            channel: channel::Builder::new(),
+        }
+    }
+
+    /// Set the balancing policy.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set how often the target is re-resolved.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the scheme of the requests, such as `https` for endpoints
+    /// connected to with TLS. Defaults to `http`.
+    pub fn scheme(mut self, scheme: Scheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Configure the channel to each endpoint with `builder`.
+    pub fn channel(mut self, builder: channel::Builder) -> Self {
+        self.channel = builder;
+        self
+    }
+
+    /// Build a balancer over the endpoints `target` resolves to with
+    /// `resolver`.
+    ///
+    /// Each resolved address is converted to the target of `make` with
+    /// `to_target`.
+    pub fn build<R, M, T, F>(
+        self,
+        target: &str,
+        resolver: R,
+        make: M,
+        to_target: F,
+    ) -> Result<Resolved<R, M, T, F>, Error>
+    where
+        R: Resolve,
+        M: MakeService<T, http::Request<BoxBody>> + Clone,
+        F: Fn(SocketAddr) -> T,
+    {
+        let target = Target::parse(target)?;
+
+        // Endpoints are connected to at their addresses, but requests keep
+        // the name they were resolved from.
+        let channel = match target {
+            Target::Dns { ref host, port } => {
+                let origin = if host.contains(':') {
+                    format!("{}://[{}]:{}", self.scheme, host, port)
+                } else {
+                    format!("{}://{}:{}", self.scheme, host, port)
+                };
+                self.channel.request_origin(origin.parse()?)
+            }
+            Target::Static(_) => self.channel,
+        };
+        let balance = Balance::with_channel_builder(self.policy, channel, make);
+
+        let mut resolved = Resolved {
+            balance,
+            resolver,
+            target,
+            scheme: self.scheme,
+            to_target,
+            interval: self.interval,
+            state: State::Idle,
+            resolved: false,
+        };
+
+        if let Target::Static(ref addrs) = resolved.target.clone() {
+            resolved.update(addrs)?;
+            resolved.state = State::Static;
+            resolved.resolved = true;
+        }
+
+        Ok(resolved)
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+// ===== impl Resolved =====
+
+impl<R, M, T, F> Resolved<R, M, T, F>
+where
+    R: Resolve,
+    M: MakeService<T, http::Request<BoxBody>> + Clone,
+    F: Fn(SocketAddr) -> T,
+{
+    /// Returns the origin and connectivity state of every endpoint.
+    pub fn endpoints(&self) -> Vec<(Uri, Connectivity)> {
+        self.balance.endpoints()
+    }
+
+    fn update(&mut self, addrs: &[SocketAddr]) -> Result<(), Error> {
+        trace!("resolved {:?}", addrs);
+
+        let stale = self
+            .balance
+            .endpoints()
+            .into_iter()
+            .map(|(origin, _)| origin)
+            .filter(|origin| !addrs.iter().any(|addr| is_origin_of(origin, addr)));
+
+        for origin in stale.collect::<Vec<_>>() {
+            self.balance.remove_endpoint(&origin);
+        }
+
+        for addr in addrs {
+            let origin = format!("{}://{}", self.scheme, addr).parse()?;
+            self.balance.add_endpoint(origin, (self.to_target)(*addr))?;
+        }
+
+        Ok(())
+    }
+
+    fn poll_resolve(&mut self) -> Poll<(), Error> {
+        loop {
+            let (next, addrs) = match self.state {
+                State::Static => return Ok(Async::Ready(())),
+                State::Idle => match self.target {
+                    Target::Dns { ref host, port } => {
+                        (State::Resolving(self.resolver.resolve(host, port)), None)
+                    }
+                    Target::Static(_) => (State::Static, None),
+                },
+                State::Resolving(ref mut fut) => {
+                    let addrs = match fut.poll() {
+                        Ok(Async::Ready(addrs)) => Some(addrs),
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            // Keep the endpoints from the last resolution.
+                            warn!("failed to resolve {:?}: {}", self.target, e);
+                            None
+                        }
+                    };
+
+                    self.resolved = true;
+                    let delay = Delay::new(Instant::now() + self.interval);
+                    (State::Waiting(delay), addrs)
+                }
+                State::Waiting(ref mut delay) => {
+                    try_ready!(delay.poll());
+                    (State::Idle, None)
+                }
+            };
+
+            self.state = next;
+
+            if let Some(addrs) = addrs {
+                self.update(&addrs)?;
+            }
+        }
+    }
+}
+
+impl<R, M, T, F, B> Service<http::Request<BoxBody>> for Resolved<R, M, T, F>
+where
+    R: Resolve,
+    M: MakeService<T, http::Request<BoxBody>, Response = http::Response<B>> + Clone,
+    M::MakeError: Into<Error>,
+    M::Error: Into<Error>,
+    T: Clone,
+    F: Fn(SocketAddr) -> T,
+{
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<M, T>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if let Async::NotReady = self.poll_resolve()? {
+            // Until the first resolution is done, there is nothing to
+            // balance over yet.
+            if !self.resolved {
+                return Ok(Async::NotReady);
+            }
+        }
+
+        self.balance.poll_ready()
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        self.balance.call(request)
+    }
+}
+
+impl<R, M, T, F> fmt::Debug for Resolved<R, M, T, F>
+where
+    R: Resolve,
+    M: MakeService<T, http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Resolved")
+            .field("target", &self.target)
+            .field("balance", &self.balance)
+            .finish()
+    }
+}
+
+fn is_origin_of(origin: &Uri, addr: &SocketAddr) -> bool {
+    origin
+        .authority_part()
+        .map_or(false, |authority| authority.as_str() == addr.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::FutureResult;
+    use tokio::runtime::current_thread::Runtime;
+    use Status;
+
+    #[derive(Clone)]
+    struct MockConnect;
+
+    /// Replies with the URI of the request.
+    struct MockConnection;
+
+    impl Service<SocketAddr> for MockConnect {
+        type Response = MockConnection;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: SocketAddr) -> Self::Future {
+            future::ok(MockConnection)
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for MockConnection {
+        type Response = http::Response<Uri>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            future::ok(http::Response::new(request.uri().clone()))
+        }
+    }
+
+    fn ips(ips: &[&str]) -> Vec<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_targets() {
+        assert_eq!(
+            Target::parse("dns:///svc.local:8080").unwrap(),
+            Target::Dns {
+                host: "svc.local".into(),
+                port: 8080,
+            }
+        );
+        assert_eq!(
+            Target::parse("svc.local").unwrap(),
+            Target::Dns {
+                host: "svc.local".into(),
+                port: 443,
+            }
+        );
+        assert_eq!(
+            Target::parse("dns:[::1]:50051").unwrap(),
+            Target::Dns {
+                host: "::1".into(),
+                port: 50051,
+            }
+        );
+        assert_eq!(
+            Target::parse("static:///127.0.0.1:1,127.0.0.2:2").unwrap(),
+            Target::Static(vec![
+                "127.0.0.1:1".parse().unwrap(),
+                "127.0.0.2:2".parse().unwrap(),
+            ])
+        );
+        assert!(Target::parse("static:///a:1").is_err());
+    }
+
+    #[test]
+    fn static_target_needs_no_resolution() {
+        let resolved = Builder::new()
+            .build(
+                "static:///127.0.0.1:1,127.0.0.2:2",
+                Stub::new(),
+                MockConnect,
+                |addr| addr,
+            )
+            .unwrap();
+
+        assert_eq!(resolved.endpoints().len(), 2);
+    }
+
+    #[test]
+    fn re_resolution_updates_endpoints() {
+        let stub = Stub::new();
+        stub.set("svc.local", ips(&["10.0.0.1", "10.0.0.2"]));
+
+        let mut resolved = Builder::new()
+            .interval(Duration::from_millis(1))
+            .build(
+                "dns:///svc.local:50051",
+                stub.clone(),
+                MockConnect,
+                |addr| addr,
+            )
+            .unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let mut wait_for = |resolved: &mut Resolved<_, _, _, _>, n: usize| {
+            rt.block_on(future::poll_fn(|| {
+                resolved.poll_ready()?;
+                if resolved.endpoints().len() == n {
+                    Ok(Async::Ready(()))
+                } else {
+                    Ok(Async::NotReady)
+                }
+            }))
+            .unwrap();
+        };
+
+        wait_for(&mut resolved, 2);
+
+        stub.set("svc.local", ips(&["10.0.0.2", "10.0.0.3", "10.0.0.4"]));
+        wait_for(&mut resolved, 3);
+
+        let origins: Vec<_> = resolved
+            .endpoints()
+            .into_iter()
+            .map(|(origin, _)| origin.to_string())
+            .collect();
+        assert!(!origins.contains(&"http://10.0.0.1:50051/".to_string()));
+
+        // Failed resolutions keep the last endpoints.
+        stub.remove("svc.local");
+        rt.block_on(Delay::new(Instant::now() + Duration::from_millis(5)))
+            .unwrap();
+        wait_for(&mut resolved, 3);
+    }
+
+    #[test]
+    fn requests_keep_the_target_authority() {
+        let stub = Stub::new();
+        stub.set("svc.local", ips(&["10.0.0.1"]));
+
+        let mut resolved = Builder::new()
+            .scheme(Scheme::HTTPS)
+            .build("dns:///svc.local:50051", stub, MockConnect, |addr| addr)
+            .unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let uri = rt
+            .block_on(future::lazy(|| {
+                try_ready!(resolved.poll_ready());
+                let request = http::Request::builder()
+                    .uri("/pkg.Service/Method")
+                    .body(BoxBody::empty())
+                    .unwrap();
+                resolved.call(request).poll()
+            }))
+            .unwrap()
+            .into_body();
+
+        assert_eq!(uri, "https://svc.local:50051/pkg.Service/Method");
+        assert_eq!(
+            resolved.endpoints()[0].0.to_string(),
+            "https://10.0.0.1:50051/"
+        );
+    }
+}
@@ -0,0 +1,482 @@
+//! Retrying calls that fail with a transient status.
+
+use super::backoff;
+use super::config::MethodConfig;
+use super::replay::{Head, Replay};
+use super::throttle::Throttle;
+use body::BoxBody;
+use error::Error;
+use {Code, Status};
+
+use futures::{Async, Future, Poll};
+use http::header::HeaderValue;
+use http::{self, HeaderMap};
+use rand::{self, Rng};
+use tokio_timer::Delay;
+use tower_service::Service;
+
+use std::cmp;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub(crate) const PREVIOUS_ATTEMPTS: &str = "grpc-previous-rpc-attempts";
+const PUSHBACK: &str = "grpc-retry-pushback-ms";
+
+/// When and how often to retry a call.
+///
+/// A call is retried when it fails with one of the retryable codes before
+/// any response headers were received, either with a trailers-only response
+/// or with an error from the inner service. Request messages are buffered
+/// up to a limit so they can be sent again; calls with larger requests are
+/// not retried.
+///
+/// See https://github.com/grpc/proposal/blob/master/A6-client-retries.md
+#[derive(Debug, Clone)]
+pub struct Policy {
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    backoff_multiplier: f64,
+    retryable_codes: Vec<Code>,
+    buffer_limit: usize,
+}
+
+/// Retries calls to the inner service according to a `Policy`.
+///
+/// The inner service is cloned for each call, so that retries can be made
+/// after the call has returned.
+#[derive(Debug, Clone)]
+pub struct Retry<S> {
+    inner: S,
+    policy: Arc<Policy>,
+    throttle: Option<Throttle>,
+}
+
+/// Response future returned by `Retry`.
+pub struct ResponseFuture<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    service: S,
+    policy: Arc<Policy>,
+    throttle: Option<Throttle>,
+    head: Head,
+    replay: Replay,
+    /// Attempts made so far, including the one in flight.
+    attempts: usize,
+    state: State<S::Future>,
+}
+
+enum State<F> {
+    Called(F),
+    Backoff(Delay),
+    Ready,
+}
+
+// ===== impl Policy =====
+
+impl Policy {
+    /// Create a new `Policy` making at most `max_attempts` attempts,
+    /// including the original call, and retrying `Code::Unavailable`.
+    pub fn new(max_attempts: usize) -> Self {
+        Policy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            backoff_multiplier: 2.0,
+            retryable_codes: vec![Code::Unavailable],
+            buffer_limit: 256 * 1024,
+        }
+    }
+
+    /// Set the backoff before the first retry. Defaults to 100 milliseconds.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the maximum backoff between retries. Defaults to 1 second.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Set how much the backoff grows after each retry. Defaults to 2.
+    pub fn backoff_multiplier(mut self, multiplier: f64) -> Self {
+        self.backoff_multiplier = multiplier;
+        self
+    }
+
+    /// Set the codes calls are retried on.
+    pub fn retryable_codes(mut self, codes: Vec<Code>) -> Self {
+        self.retryable_codes = codes;
+        self
+    }
+
+    /// Set how many bytes of request messages are buffered for retries.
+    /// Defaults to 256 KiB.
+    pub fn buffer_limit(mut self, limit: usize) -> Self {
+        self.buffer_limit = limit;
+        self
+    }
+
+    pub(crate) fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    pub(crate) fn is_retryable(&self, code: Code) -> bool {
+        self.retryable_codes.contains(&code)
+    }
+
+    /// The delay before retry number `retry`, counting from 1.
+    ///
+    /// This is a random duration up to the exponential backoff.
+    pub(crate) fn backoff(&self, retry: usize) -> Duration {
+        let exp = self.backoff_multiplier.powi(retry as i32 - 1);
+        let ceiling = cmp::min(backoff::scale(self.initial_backoff, exp), self.max_backoff);
+
+        backoff::scale(ceiling, rand::thread_rng().gen_range(0.0, 1.0))
+    }
+}
+
+// ===== impl Retry =====
+
+impl<S> Retry<S> {
+    /// Retry calls to `inner` according to `policy`.
+    pub fn new(inner: S, policy: Policy) -> Self {
+        Retry {
+            inner,
+            policy: Arc::new(policy),
+            throttle: None,
+        }
+    }
+
+    /// Only retry while `throttle` allows it.
+    ///
+    /// Without one, the throttle from the service config is used, if any.
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Returns a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for Retry<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>> + Clone,
+    S::Error: Into<Error>,
+{
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<S>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        // A policy from the service config takes precedence.
+        let policy = request
+            .extensions()
+            .get::<Arc<MethodConfig>>()
+            .and_then(|config| config.shared_retry_policy())
+            .unwrap_or_else(|| self.policy.clone());
+        let throttle = self
+            .throttle
+            .clone()
+            .or_else(|| request.extensions().get::<Throttle>().cloned());
+
+        let (parts, body) = request.into_parts();
+        let head = Head::new(&parts);
+        let replay = Replay::new(body, policy.buffer_limit);
+
+        let body = replay.body().expect("new replay is replayable");
+        let request = http::Request::from_parts(parts, body);
+
+        // Retries are made on a clone, since the original is only ready for
+        // this one call.
+        let service = self.inner.clone();
+        let future = self.inner.call(request);
+
+        ResponseFuture {
+            service,
+            policy,
+            throttle,
+            head,
+            replay,
+            attempts: 1,
+            state: State::Called(future),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<S, B> Future for ResponseFuture<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+{
+    type Item = http::Response<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Called(ref mut fut) => {
+                    let (code, result) = match fut.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(response)) => {
+                            let status = Status::from_header_map(response.headers());
+                            (status.map(|s| s.code()), Ok(response))
+                        }
+                        Err(e) => {
+                            let e: Error = e.into();
+                            (Some(Status::from_error(&*e).code()), Err(e))
+                        }
+                    };
+
+                    let failed = code.map_or(false, |code| self.policy.is_retryable(code));
+                    if let Some(ref throttle) = self.throttle {
+                        match code {
+                            _ if failed => throttle.on_failure(),
+                            None | Some(Code::Ok) => throttle.on_success(),
+                            _ => {}
+                        }
+                    }
+
+                    let retry = failed
+                        && self.attempts < self.policy.max_attempts()
+                        && self.replay.is_replayable()
+                        && self.throttle.as_ref().map_or(true, Throttle::allows_retry);
+
+                    let pushback = match result {
+                        Ok(ref response) if retry => pushback(response.headers()),
+                        _ => Ok(None),
+                    };
+
+                    let code = match (code, pushback) {
+                        (Some(code), Ok(_)) if retry => code,
+                        // Either not retryable, or the server asked not to
+                        // retry.
+                        _ => {
+                            self.replay.commit();
+                            return result.map(Async::Ready);
+                        }
+                    };
+                    let pushback = pushback.unwrap_or(None);
+
+                    let delay = pushback.unwrap_or_else(|| self.policy.backoff(self.attempts));
+                    debug!(
+                        "attempt {} failed with {:?}; retrying in {:?}",
+                        self.attempts, code, delay
+                    );
+
+                    State::Backoff(Delay::new(Instant::now() + delay))
+                }
+                State::Backoff(ref mut delay) => {
+                    try_ready!(delay.poll());
+                    State::Ready
+                }
+                State::Ready => {
+                    try_ready!(self.service.poll_ready().map_err(Into::into));
+
+                    let body = match self.replay.body() {
+                        Some(body) => body,
+                        None => {
+                            let status =
+                                Status::new(Code::Internal, "request could not be replayed");
+                            return Err(status.into());
+                        }
+                    };
+
+                    let mut request = self.head.request(body);
+                    request
+                        .headers_mut()
+                        .insert(PREVIOUS_ATTEMPTS, HeaderValue::from(self.attempts));
+
+                    self.attempts += 1;
+                    State::Called(self.service.call(request))
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<S> fmt::Debug for ResponseFuture<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("retry::ResponseFuture")
+            .field("attempts", &self.attempts)
+            .finish()
+    }
+}
+
+/// Parses the server's retry pushback, if any.
+///
+/// Returns `Err` if the server asked not to retry.
+pub(crate) fn pushback(headers: &HeaderMap) -> Result<Option<Duration>, ()> {
+    match headers.get(PUSHBACK) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(|ms| Some(Duration::from_millis(ms)))
+            .ok_or(()),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, FutureResult};
+    use std::sync::Mutex;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Fails with the given codes, in order, then succeeds.
+    #[derive(Clone)]
+    struct Flaky {
+        failures: Arc<Mutex<Vec<Code>>>,
+        attempts: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Flaky {
+        fn new(failures: Vec<Code>) -> Self {
+            Flaky {
+                failures: Arc::new(Mutex::new(failures)),
+                attempts: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    impl Service<http::Request<BoxBody>> for Flaky {
+        type Response = http::Response<()>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            let previous = request
+                .headers()
+                .get(PREVIOUS_ATTEMPTS)
+                .map(|v| v.to_str().unwrap().to_string());
+            self.attempts.lock().unwrap().push(previous);
+
+            let mut failures = self.failures.lock().unwrap();
+            if failures.is_empty() {
+                return future::ok(http::Response::new(()));
+            }
+
+            match failures.remove(0) {
+                // Trailers-only response.
+                Code::Unavailable => {
+                    let mut response = http::Response::new(());
+                    Status::new(Code::Unavailable, "try again")
+                        .add_header(response.headers_mut())
+                        .unwrap();
+                    future::ok(response)
+                }
+                code => future::err(Status::new(code, "failed")),
+            }
+        }
+    }
+
+    fn call(service: &mut Retry<Flaky>) -> Result<http::Response<()>, Error> {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| {
+            service.call(http::Request::new(BoxBody::empty()))
+        }))
+    }
+
+    fn policy(max_attempts: usize) -> Policy {
+        Policy::new(max_attempts)
+            .initial_backoff(Duration::from_millis(1))
+            .max_backoff(Duration::from_millis(1))
+            .retryable_codes(vec![Code::Unavailable, Code::Aborted])
+    }
+
+    #[test]
+    fn retries_retryable_codes() {
+        let flaky = Flaky::new(vec![Code::Unavailable, Code::Aborted]);
+        let mut service = Retry::new(flaky.clone(), policy(3));
+
+        let response = call(&mut service).unwrap();
+        assert!(Status::from_header_map(response.headers()).is_none());
+
+        let attempts = flaky.attempts.lock().unwrap().clone();
+        assert_eq!(
+            attempts,
+            [None, Some("1".to_string()), Some("2".to_string())]
+        );
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let flaky = Flaky::new(vec![Code::Unavailable, Code::Unavailable]);
+        let mut service = Retry::new(flaky.clone(), policy(2));
+
+        let response = call(&mut service).unwrap();
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(flaky.attempts.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn does_not_retry_other_codes() {
+        let flaky = Flaky::new(vec![Code::InvalidArgument]);
+        let mut service = Retry::new(flaky.clone(), policy(3));
+
+        let err = call(&mut service).unwrap_err();
+        assert_eq!(Status::from_error(&*err).code(), Code::InvalidArgument);
+        assert_eq!(flaky.attempts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn throttle_suppresses_retries() {
+        let throttle = Throttle::new(4, 1.0);
+        let flaky = Flaky::new(vec![Code::Unavailable; 4]);
+        let mut service = Retry::new(flaky.clone(), policy(5)).throttle(throttle.clone());
+
+        // The second failure leaves the bucket half full.
+        let response = call(&mut service).unwrap();
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(flaky.attempts.lock().unwrap().len(), 2);
+        assert_eq!(throttle.tokens(), 2.0);
+
+        // Successes refill it.
+        flaky.failures.lock().unwrap().clear();
+        call(&mut service).unwrap();
+        assert_eq!(throttle.tokens(), 3.0);
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let policy = Policy::new(5)
+            .initial_backoff(Duration::from_millis(10))
+            .max_backoff(Duration::from_millis(50));
+
+        for retry in 1..5 {
+            assert!(policy.backoff(retry) <= Duration::from_millis(50));
+        }
+        assert!(policy.backoff(1) <= Duration::from_millis(10));
+    }
+}
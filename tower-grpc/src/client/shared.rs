@@ -0,0 +1,351 @@
+//! Sharing one client service between many tasks.
+
+use body::BoxBody;
+use error::Error;
+use {Code, Status};
+
+use futures::sync::{mpsc, oneshot};
+use futures::{Async, Future, Poll, Stream};
+use http;
+use tokio_executor::{DefaultExecutor, Executor, SpawnError};
+use tower_service::Service;
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A cheaply cloneable handle to a service driven by a background worker.
+///
+/// Calls made through any clone are queued to the worker, which sends them
+/// on the inner service once it is ready. The response futures are then
+/// polled by the callers themselves, so many calls can be in flight at once
+/// over the same connection.
+///
+/// The queue holds up to `capacity` calls, plus one per handle; once it is
+/// full, handles are not ready. If the inner service fails or the worker is
+/// dropped, calls fail with `Code::Unavailable`.
+pub struct Shared<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    tx: mpsc::Sender<Message<S::Future>>,
+    closed: Arc<Closed>,
+}
+
+/// Drives the service behind a `Shared` handle.
+///
+/// Completes once every handle is dropped, or when the service fails.
+pub struct Worker<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    service: S,
+    rx: mpsc::Receiver<Message<S::Future>>,
+    /// A call taken from the queue, waiting for the service to be ready.
+    current: Option<Message<S::Future>>,
+    closed: Arc<Closed>,
+}
+
+/// Response future returned by `Shared`.
+pub struct ResponseFuture<F> {
+    state: State<F>,
+}
+
+struct Message<F> {
+    request: http::Request<BoxBody>,
+    tx: oneshot::Sender<F>,
+}
+
+enum State<F> {
+    Queued(oneshot::Receiver<F>, Arc<Closed>),
+    Called(F),
+    Failed(Option<Status>),
+}
+
+/// Why the worker is gone, once it is.
+#[derive(Debug, Default)]
+struct Closed {
+    error: Mutex<Option<String>>,
+}
+
+// ===== impl Shared =====
+
+impl<S> Shared<S>
+where
+    S: Service<http::Request<BoxBody>>,
+    S::Error: Into<Error>,
+{
+    /// Share `service`, spawning its worker on the default executor.
+    pub fn new(service: S, capacity: usize) -> Result<Self, SpawnError>
+    where
+        S: Send + 'static,
+        S::Future: Send,
+    {
+        let (shared, worker) = Shared::pair(service, capacity);
+        DefaultExecutor::current().spawn(Box::new(worker))?;
+        Ok(shared)
+    }
+
+    /// Share `service`, returning the worker for the caller to spawn.
+    pub fn pair(service: S, capacity: usize) -> (Self, Worker<S>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let closed = Arc::new(Closed::default());
+
+        let worker = Worker {
+            service,
+            rx,
+            current: None,
+            closed: closed.clone(),
+        };
+
+        (Shared { tx, closed }, worker)
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for Shared<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let closed = &self.closed;
+        self.tx.poll_ready().map_err(|_| closed.status().into())
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let (tx, rx) = oneshot::channel();
+
+        let state = match self.tx.try_send(Message { request, tx }) {
+            Ok(()) => State::Queued(rx, self.closed.clone()),
+            Err(ref e) if e.is_full() => State::Failed(Some(Status::new(
+                Code::ResourceExhausted,
+                "shared client queue is full",
+            ))),
+            Err(_) => State::Failed(Some(self.closed.status())),
+        };
+
+        ResponseFuture { state }
+    }
+}
+
+impl<S> Clone for Shared<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    fn clone(&self) -> Self {
+        Shared {
+            tx: self.tx.clone(),
+            closed: self.closed.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for Shared<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shared").finish()
+    }
+}
+
+// ===== impl Worker =====
+
+impl<S> Future for Worker<S>
+where
+    S: Service<http::Request<BoxBody>>,
+    S::Error: Into<Error>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            let message = match self.current.take() {
+                Some(message) => message,
+                None => match self.rx.poll() {
+                    Ok(Async::Ready(Some(message))) => message,
+                    Ok(Async::Ready(None)) => {
+                        trace!("every shared client handle is gone");
+                        return Ok(Async::Ready(()));
+                    }
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(()) => unreachable!("mpsc::Receiver never fails"),
+                },
+            };
+
+            if message.tx.is_canceled() {
+                continue;
+            }
+
+            match self.service.poll_ready() {
+                Ok(Async::Ready(())) => {
+                    let future = self.service.call(message.request);
+                    let _ = message.tx.send(future);
+                }
+                Ok(Async::NotReady) => {
+                    self.current = Some(message);
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => {
+                    let e: Error = e.into();
+                    debug!("shared client service failed: {}", e);
+                    *self.closed.error.lock().unwrap() = Some(e.to_string());
+
+                    // Queued calls fail as their senders are dropped.
+                    self.rx.close();
+                    return Ok(Async::Ready(()));
+                }
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for Worker<S>
+where
+    S: Service<http::Request<BoxBody>>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("shared::Worker")
+            .field("waiting", &self.current.is_some())
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future,
+    F::Error: Into<Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Queued(ref mut rx, ref closed) => match rx.poll() {
+                    Ok(Async::Ready(future)) => State::Called(future),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(_) => return Err(closed.status().into()),
+                },
+                State::Called(ref mut future) => return future.poll().map_err(Into::into),
+                State::Failed(ref mut status) => {
+                    return Err(status.take().expect("polled after complete").into());
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("shared::ResponseFuture").finish()
+    }
+}
+
+// ===== impl Closed =====
+
+impl Closed {
+    fn status(&self) -> Status {
+        let message = match *self.error.lock().unwrap() {
+            Some(ref error) => format!("shared client worker is gone: {}", error),
+            None => "shared client worker is gone".to_string(),
+        };
+        Status::new(Code::Unavailable, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{self, FutureResult};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tokio::runtime::current_thread::Runtime;
+
+    #[derive(Default)]
+    struct Echo {
+        broken: Arc<AtomicBool>,
+    }
+
+    impl Service<http::Request<BoxBody>> for Echo {
+        type Response = http::Response<String>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            if self.broken.load(Ordering::SeqCst) {
+                return Err(Status::new(Code::Internal, "broken"));
+            }
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            future::ok(http::Response::new(request.uri().path().to_string()))
+        }
+    }
+
+    fn request(path: &str) -> http::Request<BoxBody> {
+        http::Request::builder()
+            .uri(path)
+            .body(BoxBody::empty())
+            .unwrap()
+    }
+
+    fn call(
+        shared: &Shared<Echo>,
+        path: &str,
+    ) -> impl Future<Item = http::Response<String>, Error = Error> {
+        let mut shared = shared.clone();
+        let mut request = Some(request(path));
+        let mut response = None;
+
+        future::poll_fn(move || loop {
+            if let Some(ref mut response) = response {
+                return response.poll();
+            }
+
+            try_ready!(shared.poll_ready());
+            response = Some(shared.call(request.take().unwrap()));
+        })
+    }
+
+    #[test]
+    fn clones_share_one_service() {
+        let (shared, worker) = Shared::pair(Echo::default(), 4);
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(worker);
+
+        let calls = future::join_all(vec![call(&shared, "/a"), call(&shared.clone(), "/b")]);
+        let responses = rt.block_on(calls).unwrap();
+
+        let paths: Vec<_> = responses.into_iter().map(|r| r.into_body()).collect();
+        assert_eq!(paths, ["/a", "/b"]);
+    }
+
+    #[test]
+    fn fails_clearly_when_worker_is_gone() {
+        let echo = Echo::default();
+        echo.broken.store(true, Ordering::SeqCst);
+
+        let (shared, worker) = Shared::pair(echo, 4);
+        let mut rt = Runtime::new().unwrap();
+        rt.spawn(worker);
+
+        let err = rt.block_on(call(&shared, "/a")).unwrap_err();
+        let status = Status::from_error(&*err);
+        assert_eq!(status.code(), Code::Unavailable);
+        assert!(status.message().starts_with("shared client worker is gone"));
+        assert!(status.message().contains("broken"));
+
+        // Later calls fail right away.
+        let err = rt.block_on(call(&shared, "/b")).unwrap_err();
+        assert_eq!(Status::from_error(&*err).code(), Code::Unavailable);
+    }
+}
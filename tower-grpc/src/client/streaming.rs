@@ -1,10 +1,12 @@
 use codec::{Direction, Streaming};
 use error::Error;
+use stats::Recorder;
 use Body;
 
-use futures::{Future, Poll};
+use futures::{Async, Future, Poll};
 use http::Response;
 use prost::Message;
+use tokio_timer::Delay;
 
 use Code;
 
@@ -13,6 +15,11 @@ use std::marker::PhantomData;
 #[derive(Debug)]
 pub struct ResponseFuture<T, U> {
     inner: U,
+    max_message_size: Option<usize>,
+    stats: Recorder,
+    /// Fails the call with `Code::DeadlineExceeded` once it expires, carried
+    /// on to the response body.
+    deadline: Option<Delay>,
     _m: PhantomData<T>,
 }
 
@@ -21,9 +28,30 @@ impl<T, U> ResponseFuture<T, U> {
     pub(super) fn new(inner: U) -> Self {
         ResponseFuture {
             inner,
+            max_message_size: None,
+            stats: Recorder::default(),
+            deadline: None,
             _m: PhantomData,
         }
     }
+
+    /// Fail the call once `deadline` expires.
+    pub(super) fn deadline(mut self, deadline: Option<Delay>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Limit the size of response messages.
+    pub(super) fn max_message_size(mut self, limit: Option<usize>) -> Self {
+        self.max_message_size = limit;
+        self
+    }
+
+    /// Report the events of the call to `stats`.
+    pub(super) fn stats(mut self, stats: Recorder) -> Self {
+        self.stats = stats;
+        self
+    }
 }
 
 impl<T, U, B> Future for ResponseFuture<T, U>
@@ -40,11 +68,28 @@ where
         use codec::Decoder;
         use generic::Streaming;
 
+        if let Some(ref mut deadline) = self.deadline {
+            let expired = match deadline.poll() {
+                Ok(Async::Ready(())) => Some(::Status::new(
+                    Code::DeadlineExceeded,
+                    "deadline expired before the response",
+                )),
+                Ok(Async::NotReady) => None,
+                Err(e) => Some(::Status::from_error(&e)),
+            };
+            if let Some(status) = expired {
+                self.stats.end(&status);
+                return Err(status);
+            }
+        }
+
         // Get the response
-        let response = try_ready!(self
-            .inner
-            .poll()
-            .map_err(|err| ::Status::from_error(&*(err.into()))));
+        let stats = &self.stats;
+        let response = try_ready!(self.inner.poll().map_err(|err| {
+            let status = ::Status::from_error(&*(err.into()));
+            stats.end(&status);
+            status
+        }));
 
         let status_code = response.status();
 
@@ -52,9 +97,13 @@ where
         let trailers_only_status = ::Status::from_header_map(response.headers());
         let expect_additional_trailers = trailers_only_status.is_none();
         if let Some(status) = trailers_only_status {
+            self.stats.in_trailer(response.headers());
             if status.code() != Code::Ok {
+                self.stats.end(&status);
                 return Err(status);
             }
+        } else {
+            self.stats.in_header(response.headers());
         }
 
         let streaming_direction = if expect_additional_trailers {
@@ -63,8 +112,15 @@ where
             Direction::EmptyResponse
         };
 
-        let response =
-            response.map(move |body| Streaming::new(Decoder::new(), body, streaming_direction));
+        let max_message_size = self.max_message_size;
+        let stats = self.stats.clone();
+        let deadline = self.deadline.take();
+        let response = response.map(move |body| {
+            Streaming::new(Decoder::new(), body, streaming_direction)
+                .max_message_size(max_message_size)
+                .stats(stats)
+                .deadline(deadline)
+        });
 
         Ok(::Response::from_http(response).into())
     }
@@ -0,0 +1,122 @@
+//! Limiting retries and hedged attempts when calls keep failing.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A token bucket shared by the calls on a channel, suppressing retries and
+/// hedged attempts while too many calls are failing.
+///
+/// The bucket starts full with `max_tokens` tokens. Each failed attempt
+/// takes one token and each successful call adds `token_ratio` tokens. No
+/// more retries or hedged attempts are made while the bucket is at or below
+/// half full.
+///
+/// Clones share the same bucket.
+///
+/// See https://github.com/grpc/proposal/blob/master/A6-client-retries.md
+#[derive(Clone)]
+pub struct Throttle {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+/// Token counts are kept in thousandths of a token, the precision of the
+/// token ratio.
+#[derive(Debug)]
+struct Bucket {
+    tokens: u64,
+    max_tokens: u64,
+    token_ratio: u64,
+}
+
+const SCALE: f64 = 1000.0;
+
+// ===== impl Throttle =====
+
+impl Throttle {
+    /// Create a new `Throttle` holding up to `max_tokens` tokens, with
+    /// successful calls adding `token_ratio` tokens.
+    ///
+    /// `token_ratio` is rounded to 3 decimal places.
+    ///
+    /// # Panics
+    ///
+    /// If `max_tokens` is 0 or `token_ratio` isn't positive.
+    pub fn new(max_tokens: u32, token_ratio: f64) -> Self {
+        assert!(max_tokens > 0, "max_tokens must be positive");
+        assert!(token_ratio > 0.0, "token_ratio must be positive");
+
+        let max_tokens = u64::from(max_tokens) * SCALE as u64;
+        let bucket = Bucket {
+            tokens: max_tokens,
+            max_tokens,
+            token_ratio: (token_ratio * SCALE).round().max(1.0) as u64,
+        };
+
+        Throttle {
+            bucket: Arc::new(Mutex::new(bucket)),
+        }
+    }
+
+    /// Returns the number of tokens in the bucket.
+    pub fn tokens(&self) -> f64 {
+        self.bucket.lock().unwrap().tokens as f64 / SCALE
+    }
+
+    /// Returns whether retries and hedged attempts may be made.
+    pub(crate) fn allows_retry(&self) -> bool {
+        let bucket = self.bucket.lock().unwrap();
+        bucket.tokens > bucket.max_tokens / 2
+    }
+
+    pub(crate) fn on_failure(&self) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.tokens = bucket.tokens.saturating_sub(SCALE as u64);
+    }
+
+    pub(crate) fn on_success(&self) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.tokens = (bucket.tokens + bucket.token_ratio).min(bucket.max_tokens);
+    }
+}
+
+impl fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Throttle")
+            .field("tokens", &self.tokens())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_retries_below_half() {
+        let throttle = Throttle::new(4, 0.5);
+        assert!(throttle.allows_retry());
+
+        throttle.on_failure();
+        assert!(throttle.allows_retry());
+
+        throttle.on_failure();
+        assert!(!throttle.allows_retry());
+        assert_eq!(throttle.tokens(), 2.0);
+
+        throttle.on_success();
+        assert_eq!(throttle.tokens(), 2.5);
+        assert!(throttle.allows_retry());
+    }
+
+    #[test]
+    fn tokens_stay_in_bounds() {
+        let throttle = Throttle::new(1, 0.1);
+
+        throttle.on_success();
+        assert_eq!(throttle.tokens(), 1.0);
+
+        throttle.on_failure();
+        throttle.on_failure();
+        assert_eq!(throttle.tokens(), 0.0);
+    }
+}
@@ -6,13 +6,16 @@ pub mod server {
         pub use generic::server::{
             ClientStreamingService, ServerStreamingService, StreamingService, UnaryService,
         };
-        pub use server::{client_streaming, server_streaming, streaming, unary, unimplemented};
+        pub use server::{
+            client_streaming, server_streaming, streaming, unary, unimplemented, BoxFuture,
+            BoxStream, NamedService,
+        };
         pub use {error::Never, Body, BoxBody, Code, Request, Response, Status};
     }
 
     /// Re-export types from the `future` crate.
     pub mod futures {
-        pub use futures::future::{ok, FutureResult};
+        pub use futures::future::{err, ok, FutureResult};
         pub use futures::{Async, Future, Poll, Stream};
     }
 
@@ -41,11 +44,23 @@ pub mod server {
     }
 }
 
+/// Type re-exports used by generated mock code
+pub mod mock {
+    /// Re-export types from this crate
+    pub mod grpc {
+        pub use mock::Method;
+        pub use server::{BoxFuture, BoxStream};
+        pub use {Request, Response, Streaming};
+    }
+}
+
 pub mod client {
     /// Re-export types from this crate
     pub mod grpc {
+        pub use client::config::ServiceConfig;
         pub use client::{client_streaming, server_streaming, streaming, unary, Encodable, Grpc};
         pub use generic::client::GrpcService;
+        pub use stats::Handler as StatsHandler;
         pub use {Body, Code, Request, Response, Status};
     }
 
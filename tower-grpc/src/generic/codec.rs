@@ -1,10 +1,12 @@
 use body::{Body, HttpBody};
 use error::Error;
-use Status;
+use stats::Recorder;
+use {Code, Status};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
-use futures::{Async, Poll, Stream};
+use futures::{Async, Future, Poll, Stream};
 use http::{HeaderMap, StatusCode};
+use tokio_timer::Delay;
 
 use std::collections::VecDeque;
 use std::fmt;
@@ -71,6 +73,12 @@ pub struct Encode<T, U> {
     buf: BytesMut,
 
     role: Role,
+
+    /// Largest message that may be encoded
+    max_message_size: Option<usize>,
+
+    /// Reports the messages sent
+    stats: Recorder,
 }
 
 #[derive(Debug)]
@@ -107,6 +115,15 @@ pub struct Streaming<T, B: Body> {
     state: State,
 
     direction: Direction,
+
+    /// Largest message that may be decoded
+    max_message_size: Option<usize>,
+
+    /// Reports the messages and trailers received
+    stats: Recorder,
+
+    /// Fails the stream with `Code::DeadlineExceeded` once it expires
+    deadline: Option<Delay>,
 }
 
 /// Whether this is a request or a response stream value.
@@ -160,6 +177,8 @@ where
             inner: EncodeInner::Ok { encoder, inner },
             buf: BytesMut::new(),
             role,
+            max_message_size: None,
+            stats: Recorder::default(),
         }
     }
 
@@ -176,8 +195,24 @@ where
             inner: EncodeInner::Err(status),
             buf: BytesMut::new(),
             role: Role::Server,
+            max_message_size: None,
+            stats: Recorder::default(),
         }
     }
+
+    /// Fail with `Code::ResourceExhausted` when a message is larger than
+    /// `limit` bytes.
+    pub(crate) fn max_message_size(mut self, limit: Option<usize>) -> Self {
+        self.max_message_size = limit;
+        self
+    }
+
+    /// Report the messages sent, the end of the request on the client, and
+    /// the trailers and end of the call on the server, to `stats`.
+    pub(crate) fn stats(mut self, stats: Recorder) -> Self {
+        self.stats = stats;
+        self
+    }
 }
 
 impl<T, U> HttpBody for Encode<T, U>
@@ -194,14 +229,28 @@ where
     }
 
     fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Status> {
-        match self.inner.poll_encode(&mut self.buf) {
-            Ok(ok) => Ok(ok),
+        match self.inner.poll_encode(&mut self.buf, self.max_message_size) {
+            Ok(Async::Ready(Some(buf))) => {
+                // Skip the gRPC frame header.
+                self.stats.out_payload(&buf.bytes()[5..]);
+                Ok(Async::Ready(Some(buf)))
+            }
+            Ok(Async::Ready(None)) => {
+                if let Role::Client = self.role {
+                    self.stats.half_close();
+                }
+                Ok(Async::Ready(None))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
             Err(status) => {
                 match self.role {
                     // clients don't send statuses as trailers, so just return
                     // this error directly to allow an HTTP2 rst_stream to be
                     // sent.
-                    Role::Client => Err(status),
+                    Role::Client => {
+                        self.stats.end(&status);
+                        Err(status)
+                    }
                     // otherwise, its better to send this status in the
                     // trailers, instead of a RST_STREAM as the server...
                     Role::Server => {
@@ -218,10 +267,15 @@ where
             return Ok(Async::Ready(None));
         }
 
-        let map = match self.inner {
-            EncodeInner::Ok { .. } => Status::new(::Code::Ok, "").to_header_map(),
-            EncodeInner::Err(ref status) => status.to_header_map(),
+        let status = match self.inner {
+            EncodeInner::Ok { .. } => Status::new(Code::Ok, ""),
+            EncodeInner::Err(ref status) => status.clone(),
         };
+        let map = status.to_header_map();
+        if let Ok(ref map) = map {
+            self.stats.out_trailer(map);
+        }
+        self.stats.end(&status);
         Ok(Some(map?).into())
     }
 }
@@ -232,7 +286,11 @@ where
     U: Stream,
     U::Error: Into<Error>,
 {
-    fn poll_encode(&mut self, buf: &mut BytesMut) -> Poll<Option<BytesBuf>, Status> {
+    fn poll_encode(
+        &mut self,
+        buf: &mut BytesMut,
+        max_message_size: Option<usize>,
+    ) -> Poll<Option<BytesBuf>, Status> {
         match self {
             EncodeInner::Ok {
                 ref mut inner,
@@ -254,6 +312,16 @@ where
                     // now that we know length, we can write the header
                     let len = buf.len() - 5;
                     assert!(len <= ::std::u32::MAX as usize);
+
+                    if let Some(max) = max_message_size {
+                        if len > max {
+                            buf.clear();
+                            return Err(Status::new(
+                                ::Code::ResourceExhausted,
+                                format!("message of {} bytes is larger than {}", len, max),
+                            ));
+                        }
+                    }
                     {
                         let mut cursor = ::std::io::Cursor::new(&mut buf[..5]);
                         cursor.put_u8(0); // byte must be 0, reserve doesn't auto-zero
@@ -288,9 +356,37 @@ where
             },
             state: State::ReadHeader,
             direction,
+            max_message_size: None,
+            stats: Recorder::default(),
+            deadline: None,
         }
     }
 
+    /// Fail with `Code::ResourceExhausted` when a message is larger than
+    /// `limit` bytes.
+    pub(crate) fn max_message_size(mut self, limit: Option<usize>) -> Self {
+        self.max_message_size = limit;
+        self
+    }
+
+    /// Fail with `Code::DeadlineExceeded` once `deadline` expires.
+    pub(crate) fn deadline(mut self, deadline: Option<Delay>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Report the messages received, the end of the request on the server,
+    /// and the trailers and end of the call on the client, to `stats`.
+    pub(crate) fn stats(mut self, stats: Recorder) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Returns the `Recorder` reporting the events of this stream's call.
+    pub(crate) fn recorder(&self) -> &Recorder {
+        &self.stats
+    }
+
     fn decode(&mut self) -> Result<Option<T::Item>, ::Status> {
         if let State::ReadHeader = self.state {
             if self.bufs.remaining() < 5 {
@@ -316,6 +412,16 @@ where
             };
             let len = self.bufs.get_u32_be() as usize;
 
+            if let Some(max) = self.max_message_size {
+                if len > max {
+                    trace!("message of {} bytes is larger than {}", len, max);
+                    return Err(::Status::new(
+                        ::Code::ResourceExhausted,
+                        format!("message of {} bytes is larger than {}", len, max),
+                    ));
+                }
+            }
+
             self.state = State::ReadBody {
                 compression: is_compressed,
                 len,
@@ -327,12 +433,30 @@ where
                 return Ok(None);
             }
 
-            match self.decoder.decode(&mut DecodeBuf {
-                bufs: &mut self.bufs,
-                len,
-            }) {
+            // Handlers are given the whole message, so gather it first.
+            let message = if self.stats.is_recording() {
+                Some(self.bufs.by_ref().take(len).collect::<Bytes>())
+            } else {
+                None
+            };
+
+            let decoded = match message {
+                Some(ref message) => self.decoder.decode(&mut DecodeBuf {
+                    bufs: &mut message.clone().into_buf(),
+                    len,
+                }),
+                None => self.decoder.decode(&mut DecodeBuf {
+                    bufs: &mut self.bufs,
+                    len,
+                }),
+            };
+
+            match decoded {
                 Ok(msg) => {
                     self.state = State::ReadHeader;
+                    if let Some(ref message) = message {
+                        self.stats.in_payload(message);
+                    }
                     return Ok(Some(msg));
                 }
                 Err(e) => {
@@ -343,17 +467,24 @@ where
 
         Ok(None)
     }
-}
 
-impl<T, U> Stream for Streaming<T, U>
-where
-    T: Decoder,
-    U: Body,
-{
-    type Item = T::Item;
-    type Error = Status;
+    /// Polls for the next message like `poll`, but leaves ending the call
+    /// to the caller.
+    pub(crate) fn poll_stream(&mut self) -> Poll<Option<T::Item>, Status> {
+        // Polled first, so that the task is notified when it expires.
+        if let Some(ref mut deadline) = self.deadline {
+            match deadline.poll() {
+                Ok(Async::Ready(())) => {
+                    return Err(Status::new(
+                        Code::DeadlineExceeded,
+                        "deadline expired while receiving the response",
+                    ));
+                }
+                Ok(Async::NotReady) => {}
+                Err(e) => return Err(Status::from_error(&e)),
+            }
+        }
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         loop {
             if let State::Done = self.state {
                 break;
@@ -381,6 +512,9 @@ where
                     ));
                 } else {
                     self.state = State::Done;
+                    if let Direction::Request = self.direction {
+                        self.stats.half_close();
+                    }
                     break;
                 }
             }
@@ -392,6 +526,9 @@ where
                 debug!("decoder inner trailers error: {:?}", err);
                 Status::from_error(&*err)
             }));
+            if let Some(ref trailers) = trailers {
+                self.stats.in_trailer(trailers);
+            }
             match infer_grpc_status(trailers, status_code) {
                 Ok(_) => Ok(Async::Ready(None)),
                 Err(err) => Err(err),
@@ -402,6 +539,30 @@ where
     }
 }
 
+impl<T, U> Stream for Streaming<T, U>
+where
+    T: Decoder,
+    U: Body,
+{
+    type Item = T::Item;
+    type Error = Status;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let result = self.poll_stream();
+
+        // Responses end with the status of the call, while servers end their
+        // calls once they send it.
+        match (self.direction, &result) {
+            (Direction::Request, _) => {}
+            (_, &Ok(Async::Ready(None))) => self.stats.end(&Status::new(Code::Ok, "")),
+            (_, &Err(ref status)) => self.stats.end(status),
+            _ => {}
+        }
+
+        result
+    }
+}
+
 impl<T, B> fmt::Debug for Streaming<T, B>
 where
     T: fmt::Debug,
@@ -1,6 +1,7 @@
 use super::streaming;
 use super::unary::Once;
 use generic::{Encode, Encoder};
+use stats::Recorder;
 use Response;
 
 use futures::{Future, Poll};
@@ -28,6 +29,12 @@ where
         let inner = streaming::ResponseFuture::new(inner, encoder);
         ResponseFuture { inner }
     }
+
+    /// Report the events of the call to `stats`.
+    pub(crate) fn stats(self, stats: Recorder) -> Self {
+        let inner = self.inner.stats(stats);
+        ResponseFuture { inner }
+    }
 }
 
 impl<T, E> Future for ResponseFuture<T, E>
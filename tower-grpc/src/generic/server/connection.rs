@@ -0,0 +1,145 @@
+use error::Never;
+
+use futures::future::{self, FutureResult};
+use futures::Poll;
+use http;
+use tower_service::Service;
+
+use std::net::SocketAddr;
+
+/// The addresses of the connection a request was received on.
+///
+/// When present in the request extensions, these are exposed through
+/// `Request::remote_addr` and `Request::local_addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    remote: SocketAddr,
+    local: SocketAddr,
+}
+
+/// Records the `ConnectionInfo` of a single connection on every request
+/// dispatched to the inner service.
+///
+/// A new `WithConnectionInfo` should be created for each accepted
+/// connection, wrapping the generated `XServer` value. It implements
+/// `Service<()>` in the same way as the generated servers, so it can be
+/// handed directly to either the `tower-h2` or `tower-hyper` server.
+#[derive(Debug, Clone)]
+pub struct WithConnectionInfo<T> {
+    inner: T,
+    info: ConnectionInfo,
+}
+
+// ===== impl ConnectionInfo =====
+
+impl ConnectionInfo {
+    /// Create a new `ConnectionInfo` from the remote and local addresses of a
+    /// connection.
+    pub fn new(remote: SocketAddr, local: SocketAddr) -> Self {
+        ConnectionInfo { remote, local }
+    }
+
+    /// Returns the address of the remote peer.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote
+    }
+
+    /// Returns the local address the connection was accepted on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local
+    }
+}
+
+// ===== impl WithConnectionInfo =====
+
+impl<T> WithConnectionInfo<T> {
+    /// Wrap `inner`, recording `info` on every request it receives.
+    pub fn new(inner: T, info: ConnectionInfo) -> Self {
+        WithConnectionInfo { inner, info }
+    }
+
+    /// Returns the connection info recorded by this service.
+    pub fn connection_info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, B> Service<http::Request<B>> for WithConnectionInfo<T>
+where
+    T: Service<http::Request<B>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        request.extensions_mut().insert(self.info);
+        self.inner.call(request)
+    }
+}
+
+impl<T> Service<()> for WithConnectionInfo<T>
+where
+    T: Clone,
+{
+    type Response = Self;
+    type Error = Never;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _target: ()) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+
+    /// Responds with the connection info found in the request extensions.
+    struct Extract;
+
+    impl Service<http::Request<()>> for Extract {
+        type Response = Option<ConnectionInfo>;
+        type Error = Never;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<()>) -> Self::Future {
+            future::ok(request.extensions().get::<ConnectionInfo>().cloned())
+        }
+    }
+
+    #[test]
+    fn inserts_connection_info() {
+        let info = ConnectionInfo::new(
+            "10.0.0.1:5000".parse().unwrap(),
+            "127.0.0.1:50051".parse().unwrap(),
+        );
+        let mut service = WithConnectionInfo::new(Extract, info);
+
+        let found = service.call(http::Request::new(())).wait().unwrap();
+        assert_eq!(found, Some(info));
+    }
+}
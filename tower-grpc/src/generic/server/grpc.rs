@@ -3,6 +3,7 @@ use generic::server::{
     ClientStreamingService, ServerStreamingService, StreamingService, UnaryService,
 };
 use generic::{Codec, Direction, Streaming};
+use stats::{Recorder, SharedHandler};
 use {Body, Request};
 
 use http;
@@ -31,8 +32,8 @@ where
         S: UnaryService<T::Decode, Response = T::Encode>,
         B: Body,
     {
-        let request = self.map_request(request);
-        unary::ResponseFuture::new(service, request, self.codec.encoder())
+        let (request, stats) = self.map_request(request);
+        unary::ResponseFuture::new(service, request, self.codec.encoder()).stats(stats)
     }
 
     pub(crate) fn client_streaming<S, B>(
@@ -44,8 +45,9 @@ where
         S: ClientStreamingService<Streaming<T::Decoder, B>, Response = T::Encode>,
         B: Body,
     {
-        let response = service.call(self.map_request(request));
-        client_streaming::ResponseFuture::new(response, self.codec.encoder())
+        let (request, stats) = self.map_request(request);
+        let response = service.call(request);
+        client_streaming::ResponseFuture::new(response, self.codec.encoder()).stats(stats)
     }
 
     pub(crate) fn server_streaming<S, B>(
@@ -57,8 +59,8 @@ where
         S: ServerStreamingService<T::Decode, Response = T::Encode>,
         B: Body,
     {
-        let request = self.map_request(request);
-        server_streaming::ResponseFuture::new(service, request, self.codec.encoder())
+        let (request, stats) = self.map_request(request);
+        server_streaming::ResponseFuture::new(service, request, self.codec.encoder()).stats(stats)
     }
 
     pub(crate) fn streaming<S, B>(
@@ -70,17 +72,28 @@ where
         S: StreamingService<Streaming<T::Decoder, B>, Response = T::Encode>,
         B: Body,
     {
-        let response = service.call(self.map_request(request));
-        streaming::ResponseFuture::new(response, self.codec.encoder())
+        let (request, stats) = self.map_request(request);
+        let response = service.call(request);
+        streaming::ResponseFuture::new(response, self.codec.encoder()).stats(stats)
     }
 
-    /// Map an inbound HTTP request to a streaming decoded request
-    fn map_request<B>(&mut self, request: http::Request<B>) -> Request<Streaming<T::Decoder, B>>
+    /// Map an inbound HTTP request to a streaming decoded request, along
+    /// with the `Recorder` reporting the events of its call.
+    fn map_request<B>(
+        &mut self,
+        request: http::Request<B>,
+    ) -> (Request<Streaming<T::Decoder, B>>, Recorder)
     where
         B: Body,
     {
-        Request::from_http(
-            request.map(|body| Streaming::new(self.codec.decoder(), body, Direction::Request)),
-        )
+        let handler = request.extensions().get::<SharedHandler>();
+        let stats = Recorder::server(handler, &request);
+        stats.in_header(request.headers());
+
+        let decoder = self.codec.decoder();
+        let request = request
+            .map(|body| Streaming::new(decoder, body, Direction::Request).stats(stats.clone()));
+
+        (Request::from_http(request), stats)
     }
 }
@@ -1,3 +1,4 @@
+mod connection;
 mod grpc;
 
 pub(crate) mod client_streaming;
@@ -5,6 +6,7 @@ pub(crate) mod server_streaming;
 pub(crate) mod streaming;
 pub(crate) mod unary;
 
+pub use self::connection::{ConnectionInfo, WithConnectionInfo};
 pub(crate) use self::grpc::Grpc;
 
 use {Request, Response};
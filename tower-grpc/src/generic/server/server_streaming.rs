@@ -1,6 +1,7 @@
 use super::streaming;
 use generic::server::ServerStreamingService;
 use generic::{Encode, Encoder};
+use stats::Recorder;
 use {Request, Response};
 
 use futures::{Future, Poll, Stream};
@@ -52,6 +53,12 @@ where
         let inner = streaming::ResponseFuture::new(inner, encoder);
         ResponseFuture { inner }
     }
+
+    /// Report the events of the call to `stats`.
+    pub(crate) fn stats(self, stats: Recorder) -> Self {
+        let inner = self.inner.stats(stats);
+        ResponseFuture { inner }
+    }
 }
 
 impl<T, E, S> Future for ResponseFuture<T, E, S>
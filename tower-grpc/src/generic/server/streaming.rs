@@ -1,5 +1,6 @@
 use error::{Error, Never};
 use generic::{Encode, Encoder};
+use stats::Recorder;
 use Response;
 
 use futures::{Async, Future, Poll, Stream};
@@ -10,6 +11,7 @@ use http::header;
 pub struct ResponseFuture<T, E> {
     inner: T,
     encoder: Option<E>,
+    stats: Recorder,
 }
 
 // ===== impl ResponseFuture =====
@@ -24,8 +26,15 @@ where
         ResponseFuture {
             inner,
             encoder: Some(encoder),
+            stats: Recorder::default(),
         }
     }
+
+    /// Report the events of the call to `stats`.
+    pub(crate) fn stats(mut self, stats: Recorder) -> Self {
+        self.stats = stats;
+        self
+    }
 }
 
 impl<T, E, S> Future for ResponseFuture<T, E>
@@ -44,8 +53,10 @@ where
             Ok(Async::Ready(response)) => response,
             Ok(Async::NotReady) => return Ok(Async::NotReady),
             Err(status) => {
-                let response = Response::new(Encode::error(status));
-                return Ok(response.into_http().into());
+                let response = Response::new(Encode::error(status).stats(self.stats.clone()));
+                let response = response.into_http();
+                self.stats.out_header(response.headers());
+                return Ok(response.into());
             }
         };
 
@@ -60,8 +71,11 @@ where
         // Get the encoder
         let encoder = self.encoder.take().expect("encoder consumed");
 
+        self.stats.out_header(response.headers());
+
         // Map the response body
-        let response = response.map(move |body| Encode::response(encoder, body));
+        let stats = self.stats.clone();
+        let response = response.map(move |body| Encode::response(encoder, body).stats(stats));
 
         Ok(response.into())
     }
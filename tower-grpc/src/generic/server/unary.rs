@@ -1,6 +1,7 @@
 use super::server_streaming;
 use generic::server::UnaryService;
 use generic::{Encode, Encoder};
+use stats::Recorder;
 use {Request, Response};
 
 use futures::{Future, Poll, Stream};
@@ -42,6 +43,12 @@ where
         let inner = server_streaming::ResponseFuture::new(Inner(inner), request, encoder);
         ResponseFuture { inner }
     }
+
+    /// Report the events of the call to `stats`.
+    pub(crate) fn stats(self, stats: Recorder) -> Self {
+        let inner = self.inner.stats(stats);
+        ResponseFuture { inner }
+    }
 }
 
 impl<T, E, S> Future for ResponseFuture<T, E, S>
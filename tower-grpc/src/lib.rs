@@ -10,6 +10,12 @@ extern crate http;
 #[macro_use]
 extern crate log;
 extern crate percent_encoding;
+extern crate rand;
+extern crate serde_json;
+extern crate tokio_executor;
+#[cfg(feature = "tower-h2")]
+extern crate tokio_tcp;
+extern crate tokio_timer;
 extern crate tower_http;
 extern crate tower_service;
 extern crate tower_util;
@@ -21,12 +27,19 @@ extern crate tower_h2;
 #[cfg(feature = "tower-hyper")]
 extern crate tower_hyper;
 
+#[cfg(test)]
+extern crate tokio;
+
 pub mod client;
 pub mod generic;
 pub mod metadata;
+pub mod metrics;
+pub mod stats;
+pub mod trace;
 
 mod body;
 mod error;
+mod observe;
 mod request;
 mod response;
 mod status;
@@ -36,6 +49,9 @@ pub use request::Request;
 pub use response::Response;
 pub use status::{Code, Status};
 
+#[cfg(feature = "protobuf")]
+pub mod mock;
+
 #[cfg(feature = "protobuf")]
 pub mod server;
 
@@ -46,5 +62,8 @@ pub mod codegen;
 #[cfg(feature = "protobuf")]
 mod codec;
 
+#[cfg(all(test, feature = "protobuf"))]
+mod test_util;
+
 #[cfg(feature = "protobuf")]
 pub use codec::{Encode, Streaming};
@@ -0,0 +1,791 @@
+//! Per-method call metrics, rendered in the Prometheus text format.
+
+use body::{Body, BoxBody, BytesBuf, HttpBody};
+use error::Error;
+use observe::{self, Observe};
+use {Code, Status};
+
+use bytes::Buf;
+use futures::{Future, Poll};
+use http;
+use tower_service::Service;
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Records metrics for the calls made to, or handled by, a service.
+///
+/// For every method, labeled by the service and method names from the
+/// request path, the registry keeps:
+///
+/// - the number of calls started, and of calls still in flight,
+/// - the number of calls completed, by final status code,
+/// - a histogram of call latencies, from the call until the status is known,
+/// - the number of messages sent and received.
+///
+/// So that callers can't add an unbounded number of labels, a server only
+/// labels the methods of the service it wraps, and a `Registry` at most
+/// `max_methods` methods. Other calls are labeled `unknown`.
+///
+/// ```rust,ignore
+/// let registry = Registry::new();
+///
+/// let greeter = Metrics::server(GreeterServer::new(Greet), &registry);
+/// let router = Router::new().add_service(greeter);
+///
+/// // Later, when scraped:
+/// let text = registry.render();
+/// ```
+#[derive(Clone)]
+pub struct Metrics<S> {
+    inner: S,
+    side: Side,
+    /// The only service whose methods are labeled, if any.
+    service: Option<&'static str>,
+    registry: Registry,
+}
+
+/// The metrics recorded by any number of `Metrics` services.
+///
+/// Clones share the same metrics.
+#[derive(Clone)]
+pub struct Registry {
+    methods: Arc<Mutex<BTreeMap<Key, Arc<MethodStats>>>>,
+    max_methods: usize,
+}
+
+/// Response future returned by `Metrics`.
+pub struct ResponseFuture<F> {
+    inner: observe::ResponseFuture<F, Call>,
+}
+
+/// Response body returned by `Metrics`, recording the call once its status is
+/// known.
+pub struct ResponseBody<B> {
+    inner: observe::ResponseBody<B, Call>,
+}
+
+/// A request body counting the messages it carries.
+struct RequestBody {
+    inner: BoxBody,
+    frames: Frames,
+    stats: Arc<MethodStats>,
+    side: Side,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Side {
+    Client,
+    Server,
+}
+
+/// The side, service and method of a call.
+type Key = (Side, String, String);
+
+#[derive(Debug, Default)]
+struct MethodStats {
+    started: AtomicUsize,
+    in_flight: AtomicUsize,
+    sent: AtomicUsize,
+    received: AtomicUsize,
+    handled: Mutex<Handled>,
+}
+
+#[derive(Debug, Default)]
+struct Handled {
+    codes: BTreeMap<i32, u64>,
+    /// Non-cumulative counts for each of `BUCKETS`, plus one for `+Inf`.
+    buckets: [u64; 12],
+    sum: f64,
+    count: u64,
+}
+
+/// A call in progress, recorded once finished.
+struct Call {
+    stats: Arc<MethodStats>,
+    side: Side,
+    frames: Frames,
+    start: Instant,
+}
+
+/// Counts gRPC messages in a stream of bytes by following their
+/// length-prefixed framing.
+#[derive(Debug, Default)]
+pub(crate) struct Frames {
+    /// Header bytes read so far of the next message.
+    header: usize,
+    len: usize,
+    /// Bytes left in the current message.
+    remaining: usize,
+}
+
+/// The labels of calls to methods that aren't recorded by name.
+const UNKNOWN: (&str, &str) = ("unknown", "unknown");
+
+/// How many methods a `Registry` records by default.
+const MAX_METHODS: usize = 1000;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// ===== impl Metrics =====
+
+impl<S> Metrics<S> {
+    /// Record the calls made by the client service `inner` in `registry`.
+    pub fn client(inner: S, registry: &Registry) -> Self {
+        Metrics::new(inner, Side::Client, None, registry)
+    }
+
+    fn new(inner: S, side: Side, service: Option<&'static str>, registry: &Registry) -> Self {
+        Metrics {
+            inner,
+            side,
+            service,
+            registry: registry.clone(),
+        }
+    }
+
+    /// Returns the registry recording the calls.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<S> Metrics<S>
+where
+    S: ::server::NamedService,
+{
+    /// Record the calls handled by the generated server `inner` in
+    /// `registry`.
+    ///
+    /// Calls to paths outside of the `S::NAME` service are labeled
+    /// `unknown`.
+    pub fn server(inner: S, registry: &Registry) -> Self {
+        Metrics::new(inner, Side::Server, Some(S::NAME), registry)
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for Metrics<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Body,
+{
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let stats = self
+            .registry
+            .method(self.side, self.service, request.uri().path());
+        stats.started.fetch_add(1, Ordering::SeqCst);
+        stats.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        let side = self.side;
+        let request_stats = stats.clone();
+        let request = request.map(move |inner| {
+            BoxBody::new(Box::new(RequestBody {
+                inner,
+                frames: Frames::default(),
+                stats: request_stats,
+                side,
+            }))
+        });
+
+        let call = Call {
+            stats,
+            side,
+            frames: Frames::default(),
+            start: Instant::now(),
+        };
+
+        ResponseFuture {
+            inner: observe::ResponseFuture::new(self.inner.call(request), call),
+        }
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<S> ::server::NamedService for Metrics<S>
+where
+    S: ::server::NamedService,
+{
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> fmt::Debug for Metrics<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("inner", &self.inner)
+            .field("side", &self.side)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+// ===== impl Registry =====
+
+impl Registry {
+    /// Create a new, empty `Registry`.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Record at most `max` methods, across clients and servers, labeling
+    /// the calls to any other method `unknown`.
+    ///
+    /// Defaults to 1000.
+    pub fn max_methods(mut self, max: usize) -> Self {
+        self.max_methods = max;
+        self
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let methods = self.methods.lock().unwrap();
+        let mut out = String::new();
+
+        for &side in &[Side::Client, Side::Server] {
+            let methods: Vec<_> = methods
+                .iter()
+                .filter(|&(key, _)| key.0 == side)
+                .map(|(&(_, ref service, ref method), stats)| {
+                    let labels = format!(
+                        "grpc_service=\"{}\",grpc_method=\"{}\"",
+                        escape(service),
+                        escape(method)
+                    );
+                    (labels, stats)
+                })
+                .collect();
+
+            if !methods.is_empty() {
+                render_side(&mut out, side, &methods).expect("writing to a String");
+            }
+        }
+
+        out
+    }
+
+    /// Returns the metrics of the method at `path`, a method of `service`
+    /// if given.
+    fn method(&self, side: Side, service: Option<&str>, path: &str) -> Arc<MethodStats> {
+        let (name, method) = match split_path(path) {
+            Some((name, method)) if service.map_or(true, |service| service == name) => {
+                (name, method)
+            }
+            _ => UNKNOWN,
+        };
+
+        let mut methods = self.methods.lock().unwrap();
+
+        let mut key = (side, name.to_string(), method.to_string());
+        if !methods.contains_key(&key) && methods.len() >= self.max_methods {
+            key = (side, UNKNOWN.0.to_string(), UNKNOWN.1.to_string());
+        }
+
+        methods.entry(key).or_insert_with(Default::default).clone()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            methods: Arc::new(Mutex::new(BTreeMap::new())),
+            max_methods: MAX_METHODS,
+        }
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("methods", &self.methods.lock().unwrap().len())
+            .field("max_methods", &self.max_methods)
+            .finish()
+    }
+}
+
+fn render_side(
+    out: &mut String,
+    side: Side,
+    methods: &[(String, &Arc<MethodStats>)],
+) -> fmt::Result {
+    let prefix = match side {
+        Side::Client => "grpc_client",
+        Side::Server => "grpc_server",
+    };
+
+    let counters: [(&str, &str, fn(&MethodStats) -> usize); 3] = [
+        ("started_total", "Total number of RPCs started.", |s| {
+            s.started.load(Ordering::SeqCst)
+        }),
+        ("msg_sent_total", "Total number of messages sent.", |s| {
+            s.sent.load(Ordering::SeqCst)
+        }),
+        (
+            "msg_received_total",
+            "Total number of messages received.",
+            |s| s.received.load(Ordering::SeqCst),
+        ),
+    ];
+
+    for &(name, help, value) in &counters {
+        writeln!(out, "# HELP {}_{} {}", prefix, name, help)?;
+        writeln!(out, "# TYPE {}_{} counter", prefix, name)?;
+        for &(ref labels, stats) in methods {
+            writeln!(out, "{}_{}{{{}}} {}", prefix, name, labels, value(stats))?;
+        }
+    }
+
+    writeln!(out, "# HELP {}_in_flight Number of RPCs in flight.", prefix)?;
+    writeln!(out, "# TYPE {}_in_flight gauge", prefix)?;
+    for &(ref labels, stats) in methods {
+        let value = stats.in_flight.load(Ordering::SeqCst);
+        writeln!(out, "{}_in_flight{{{}}} {}", prefix, labels, value)?;
+    }
+
+    writeln!(
+        out,
+        "# HELP {}_handled_total Total number of RPCs completed, by status code.",
+        prefix
+    )?;
+    writeln!(out, "# TYPE {}_handled_total counter", prefix)?;
+    for &(ref labels, stats) in methods {
+        let handled = stats.handled.lock().unwrap();
+        for (&code, count) in &handled.codes {
+            let code = code_name(Code::from_i32(code));
+            writeln!(
+                out,
+                "{}_handled_total{{{},grpc_code=\"{}\"}} {}",
+                prefix, labels, code, count
+            )?;
+        }
+    }
+
+    writeln!(
+        out,
+        "# HELP {}_handling_seconds Latency of completed RPCs.",
+        prefix
+    )?;
+    writeln!(out, "# TYPE {}_handling_seconds histogram", prefix)?;
+    for &(ref labels, stats) in methods {
+        let handled = stats.handled.lock().unwrap();
+        let mut cumulative = 0;
+
+        for (i, count) in handled.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = match BUCKETS.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            writeln!(
+                out,
+                "{}_handling_seconds_bucket{{{},le=\"{}\"}} {}",
+                prefix, labels, le, cumulative
+            )?;
+        }
+
+        writeln!(
+            out,
+            "{}_handling_seconds_sum{{{}}} {}",
+            prefix, labels, handled.sum
+        )?;
+        writeln!(
+            out,
+            "{}_handling_seconds_count{{{}}} {}",
+            prefix, labels, handled.count
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns the service and method names of a `/package.Service/Method`
+/// path, if it is one.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    let mut parts = path.splitn(3, '/');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(""), Some(service), Some(method)) if !method.contains('/') => Some((service, method)),
+        _ => None,
+    }
+}
+
+/// Returns the canonical name of `code`, as other gRPC implementations
+/// label it.
+fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "OK",
+        Code::Cancelled => "CANCELLED",
+        Code::Unknown => "UNKNOWN",
+        Code::InvalidArgument => "INVALID_ARGUMENT",
+        Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        Code::NotFound => "NOT_FOUND",
+        Code::AlreadyExists => "ALREADY_EXISTS",
+        Code::PermissionDenied => "PERMISSION_DENIED",
+        Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        Code::FailedPrecondition => "FAILED_PRECONDITION",
+        Code::Aborted => "ABORTED",
+        Code::OutOfRange => "OUT_OF_RANGE",
+        Code::Unimplemented => "UNIMPLEMENTED",
+        Code::Internal => "INTERNAL",
+        Code::Unavailable => "UNAVAILABLE",
+        Code::DataLoss => "DATA_LOSS",
+        Code::Unauthenticated => "UNAUTHENTICATED",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Escapes a Prometheus label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+        Ok(response.map(|inner| ResponseBody { inner }).into())
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("metrics::ResponseFuture").finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> HttpBody for ResponseBody<B>
+where
+    B: Body,
+{
+    type Item = B::Item;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll_buf()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where
+    B: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("metrics::ResponseBody")
+            .field("inner", self.inner.get_ref())
+            .finish()
+    }
+}
+
+// ===== impl RequestBody =====
+
+impl HttpBody for RequestBody {
+    type Item = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        HttpBody::is_end_stream(&self.inner)
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let buf = try_ready!(HttpBody::poll_buf(&mut self.inner));
+
+        if let Some(ref buf) = buf {
+            let count = self.frames.count(buf.bytes());
+            self.stats.count_messages(self.side == Side::Client, count);
+        }
+
+        Ok(buf.into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        HttpBody::poll_trailers(&mut self.inner)
+    }
+}
+
+// ===== impl MethodStats =====
+
+impl MethodStats {
+    fn count_messages(&self, sent: bool, count: usize) {
+        let counter = if sent { &self.sent } else { &self.received };
+        counter.fetch_add(count, Ordering::SeqCst);
+    }
+}
+
+// ===== impl Call =====
+
+impl Observe for Call {
+    fn data<T: Buf>(&mut self, buf: &T) {
+        let count = self.frames.count(buf.bytes());
+        self.stats.count_messages(self.side == Side::Server, count);
+    }
+
+    fn finish(self, status: Status) {
+        let code = status.code();
+        let elapsed = self.start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        self.stats.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        let mut handled = self.stats.handled.lock().unwrap();
+        *handled.codes.entry(code as i32).or_insert(0) += 1;
+
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(BUCKETS.len());
+        handled.buckets[bucket] += 1;
+        handled.sum += seconds;
+        handled.count += 1;
+    }
+}
+
+// ===== impl Frames =====
+
+impl Frames {
+    /// Returns the number of messages starting in `bytes`.
+    ///
+    /// The bodies of this crate always yield contiguous buffers, so only
+    /// the first chunk of a buffer is looked at.
+    pub(crate) fn count(&mut self, mut bytes: &[u8]) -> usize {
+        let mut count = 0;
+
+        while !bytes.is_empty() {
+            if self.remaining > 0 {
+                let skip = self.remaining.min(bytes.len());
+                self.remaining -= skip;
+                bytes = &bytes[skip..];
+                continue;
+            }
+
+            // The first header byte is the compression flag, and the next
+            // four the big-endian message length.
+            if self.header > 0 {
+                self.len = (self.len << 8) | usize::from(bytes[0]);
+            }
+            self.header += 1;
+            bytes = &bytes[1..];
+
+            if self.header == 5 {
+                count += 1;
+                self.remaining = self.len;
+                self.header = 0;
+                self.len = 0;
+            }
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{Bytes, IntoBuf};
+    use futures::future::{self, FutureResult};
+    use futures::Async;
+
+    /// Replies with two messages, split across three chunks.
+    struct Reply;
+
+    struct Chunks {
+        chunks: Vec<Bytes>,
+        trailers: Option<http::HeaderMap>,
+    }
+
+    impl Service<http::Request<BoxBody>> for Reply {
+        type Response = http::Response<Chunks>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::header::HeaderValue::from_static("0"));
+
+            future::ok(http::Response::new(Chunks {
+                chunks: vec![
+                    Bytes::from_static(&[0, 0, 0]),
+                    Bytes::from_static(&[0, 2, 1, 2, 0, 0]),
+                    Bytes::from_static(&[0, 0, 1, 3]),
+                ],
+                trailers: Some(trailers),
+            }))
+        }
+    }
+
+    #[cfg(feature = "protobuf")]
+    impl ::server::NamedService for Reply {
+        const NAME: &'static str = "test.Svc";
+    }
+
+    impl HttpBody for Chunks {
+        type Item = BytesBuf;
+        type Error = Status;
+
+        fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            if self.chunks.is_empty() {
+                return Ok(None.into());
+            }
+            Ok(Some(self.chunks.remove(0).into_buf()).into())
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+            Ok(self.trailers.take().into())
+        }
+    }
+
+    fn call(metrics: &mut Metrics<Reply>, path: &str) -> http::Response<ResponseBody<Chunks>> {
+        let request = http::Request::builder()
+            .uri(path)
+            .body(BoxBody::empty())
+            .unwrap();
+
+        metrics.call(request).wait().unwrap()
+    }
+
+    #[test]
+    fn counts_messages_split_across_chunks() {
+        let mut frames = Frames::default();
+
+        assert_eq!(frames.count(&[0, 0, 0]), 0);
+        assert_eq!(frames.count(&[0, 2, 1, 2, 0, 0]), 1);
+        assert_eq!(frames.count(&[0, 0, 1, 3]), 1);
+        assert_eq!(frames.count(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "protobuf")]
+    fn records_completed_calls() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::server(Reply, &registry);
+
+        let mut body = call(&mut metrics, "/test.Svc/Method").into_body();
+        while let Async::Ready(Some(_)) = HttpBody::poll_buf(&mut body).unwrap() {}
+        HttpBody::poll_trailers(&mut body).unwrap();
+
+        let text = registry.render();
+        let labels = "grpc_service=\"test.Svc\",grpc_method=\"Method\"";
+        for line in &[
+            format!("grpc_server_started_total{{{}}} 1", labels),
+            format!("grpc_server_msg_sent_total{{{}}} 2", labels),
+            format!("grpc_server_msg_received_total{{{}}} 0", labels),
+            format!("grpc_server_in_flight{{{}}} 0", labels),
+            format!("grpc_server_handled_total{{{},grpc_code=\"OK\"}} 1", labels),
+            format!(
+                "grpc_server_handling_seconds_bucket{{{},le=\"+Inf\"}} 1",
+                labels
+            ),
+            format!("grpc_server_handling_seconds_count{{{}}} 1", labels),
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {:?} in:\n{}",
+                line,
+                text
+            );
+        }
+        assert!(!text.contains("grpc_client_"));
+    }
+
+    #[test]
+    fn records_dropped_calls_as_cancelled() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::client(Reply, &registry);
+
+        let response = call(&mut metrics, "/test.Svc/Method");
+        assert!(registry
+            .render()
+            .contains("grpc_client_in_flight{grpc_service=\"test.Svc\",grpc_method=\"Method\"} 1"));
+
+        drop(response);
+        let text = registry.render();
+        assert!(text
+            .contains("grpc_client_in_flight{grpc_service=\"test.Svc\",grpc_method=\"Method\"} 0"));
+        assert!(text.contains("grpc_code=\"CANCELLED\"} 1"));
+    }
+
+    #[test]
+    fn labels_unknown_methods() {
+        let registry = Registry::new().max_methods(1);
+        let mut metrics = Metrics::client(Reply, &registry);
+
+        drop(call(&mut metrics, "/test.Svc/Method"));
+        drop(call(&mut metrics, "/test.Svc/Other"));
+        drop(call(&mut metrics, "/not/a/method"));
+
+        let text = registry.render();
+        assert!(text.contains(
+            "grpc_client_started_total{grpc_service=\"test.Svc\",grpc_method=\"Method\"} 1"
+        ));
+        assert!(text.contains(
+            "grpc_client_started_total{grpc_service=\"unknown\",grpc_method=\"unknown\"} 2"
+        ));
+        assert!(!text.contains("Other"));
+    }
+
+    #[test]
+    #[cfg(feature = "protobuf")]
+    fn labels_only_methods_of_the_server() {
+        let registry = Registry::new();
+        let mut metrics = Metrics::server(Reply, &registry);
+
+        drop(call(&mut metrics, "/other.Svc/Method"));
+
+        let text = registry.render();
+        assert!(text.contains(
+            "grpc_server_started_total{grpc_service=\"unknown\",grpc_method=\"unknown\"} 1"
+        ));
+        assert!(!text.contains("other.Svc"));
+    }
+}
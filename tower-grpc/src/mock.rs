@@ -0,0 +1,324 @@
+//! Support for the mock services generated by `tower-grpc-build`.
+//!
+//! With mock generation enabled, each service trait `Foo` gets a `MockFoo`
+//! implementation, holding a `Method` for each of its RPCs. A `Method` is
+//! told how to answer calls, and records the calls it answers so that tests
+//! can make assertions about them.
+//!
+//! Streamed messages are collected into a `Vec`: a client streaming method
+//! is answered once the whole request stream was received, and a server
+//! streaming method answers with all of its messages at once.
+
+use metadata::MetadataMap;
+use server::{BoxFuture, BoxStream};
+use {Code, Request, Response, Status};
+
+use futures::{future, stream, Future, Stream};
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+type Respond<T, U> = Arc<Mutex<dyn FnMut(Request<T>) -> Result<Response<U>, Status> + Send>>;
+
+/// A mocked RPC method, taking `T` and answering with `U`.
+///
+/// Clones share their configuration and recorded calls, so a `Method` can
+/// be configured and inspected while a clone of the mock is being served.
+/// Until it is configured, a method fails calls with `Unimplemented`.
+pub struct Method<T, U> {
+    inner: Arc<Mutex<Inner<T, U>>>,
+}
+
+/// A call recorded by a `Method`.
+#[derive(Debug, Clone)]
+pub struct Call<T> {
+    metadata: MetadataMap,
+    message: T,
+}
+
+struct Inner<T, U> {
+    respond: Option<Respond<T, U>>,
+    calls: Vec<Call<T>>,
+}
+
+// ===== impl Method =====
+
+impl<T, U> Method<T, U> {
+    /// Create a new, unconfigured `Method`.
+    pub fn new() -> Self {
+        Method {
+            inner: Arc::new(Mutex::new(Inner {
+                respond: None,
+                calls: Vec::new(),
+            })),
+        }
+    }
+
+    /// Answer every call with `message`.
+    pub fn respond(&self, message: U)
+    where
+        T: 'static,
+        U: Clone + Send + 'static,
+    {
+        self.respond_with(move |_| Ok(Response::new(message.clone())));
+    }
+
+    /// Fail every call with `status`.
+    pub fn fail(&self, status: Status)
+    where
+        T: 'static,
+        U: 'static,
+    {
+        self.respond_with(move |_| Err(status.clone()));
+    }
+
+    /// Answer every call with the result of `f`.
+    pub fn respond_with<F>(&self, f: F)
+    where
+        F: FnMut(Request<T>) -> Result<Response<U>, Status> + Send + 'static,
+    {
+        self.inner.lock().unwrap().respond = Some(Arc::new(Mutex::new(f)));
+    }
+
+    /// Returns the calls made to this method so far, in order.
+    pub fn calls(&self) -> Vec<Call<T>>
+    where
+        T: Clone,
+    {
+        self.inner.lock().unwrap().calls.clone()
+    }
+
+    /// Returns the number of calls made to this method so far.
+    pub fn call_count(&self) -> usize {
+        self.inner.lock().unwrap().calls.len()
+    }
+
+    /// Record a call of this method and answer it.
+    pub fn call(&self, request: Request<T>) -> Result<Response<U>, Status>
+    where
+        T: Clone,
+    {
+        // The responder has its own lock, and runs outside of this one, so
+        // that it may use this method itself, e.g. to inspect the calls.
+        let respond = {
+            let mut inner = self.inner.lock().unwrap();
+
+            inner.calls.push(Call {
+                metadata: request.metadata().clone(),
+                message: request.get_ref().clone(),
+            });
+
+            inner.respond.clone()
+        };
+
+        let respond = match respond {
+            Some(respond) => respond,
+            None => {
+                return Err(Status::new(
+                    Code::Unimplemented,
+                    "no response configured for mock method",
+                ));
+            }
+        };
+
+        let mut respond = respond.lock().unwrap();
+        (&mut *respond)(request)
+    }
+
+    /// Answer a unary call.
+    pub fn unary(&self, request: Request<T>) -> BoxFuture<Response<U>>
+    where
+        T: Clone,
+        U: Send + 'static,
+    {
+        Box::new(future::result(self.call(request)))
+    }
+}
+
+impl<T, I> Method<T, Vec<I>> {
+    /// Answer a server streaming call.
+    pub fn server_streaming(&self, request: Request<T>) -> BoxFuture<Response<BoxStream<I>>>
+    where
+        T: Clone,
+        I: Send + 'static,
+    {
+        Box::new(self.unary(request).map(into_stream))
+    }
+}
+
+impl<T, U> Method<Vec<T>, U> {
+    /// Answer a client streaming call.
+    pub fn client_streaming<S>(&self, request: Request<S>) -> BoxFuture<Response<U>>
+    where
+        S: Stream<Item = T, Error = Status> + Send + 'static,
+        T: Clone + Send + 'static,
+        U: Send + 'static,
+    {
+        let mut request = request.map(Some);
+        let messages = request.get_mut().take().expect("request stream");
+        let method = self.clone();
+
+        let response = messages
+            .collect()
+            .and_then(move |messages| method.call(request.map(|_| messages)));
+
+        Box::new(response)
+    }
+}
+
+impl<T, I> Method<Vec<T>, Vec<I>> {
+    /// Answer a bidirectional streaming call.
+    pub fn streaming<S>(&self, request: Request<S>) -> BoxFuture<Response<BoxStream<I>>>
+    where
+        S: Stream<Item = T, Error = Status> + Send + 'static,
+        T: Clone + Send + 'static,
+        I: Send + 'static,
+    {
+        Box::new(self.client_streaming(request).map(into_stream))
+    }
+}
+
+impl<T, U> Clone for Method<T, U> {
+    fn clone(&self) -> Self {
+        Method {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T, U> Default for Method<T, U> {
+    fn default() -> Self {
+        Method::new()
+    }
+}
+
+impl<T, U> fmt::Debug for Method<T, U> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+
+        fmt.debug_struct("Method")
+            .field("configured", &inner.respond.is_some())
+            .field("calls", &inner.calls.len())
+            .finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl<T> Call<T> {
+    /// Get a reference to the metadata the call was made with.
+    pub fn metadata(&self) -> &MetadataMap {
+        &self.metadata
+    }
+
+    /// Get a reference to the request message.
+    ///
+    /// For client streaming methods, these are all of the messages sent.
+    pub fn get_ref(&self) -> &T {
+        &self.message
+    }
+
+    /// Consumes `self`, returning the request message.
+    pub fn into_inner(self) -> T {
+        self.message
+    }
+}
+
+fn into_stream<I>(response: Response<Vec<I>>) -> Response<BoxStream<I>>
+where
+    I: Send + 'static,
+{
+    response.map(|messages| Box::new(stream::iter_ok(messages)) as BoxStream<I>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metadata::AsciiMetadataValue;
+
+    #[test]
+    fn records_calls_and_responds() {
+        let method = Method::<u32, u32>::new();
+
+        let status = method.call(Request::new(1)).unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+
+        method.respond(10);
+
+        let mut request = Request::new(2);
+        request
+            .metadata_mut()
+            .insert("x-test", AsciiMetadataValue::from_static("yes"));
+
+        let response = method.clone().unary(request).wait().unwrap();
+        assert_eq!(*response.get_ref(), 10);
+
+        method.respond_with(|request| Ok(Response::new(request.into_inner() * 2)));
+        assert_eq!(*method.call(Request::new(3)).unwrap().get_ref(), 6);
+
+        method.fail(Status::new(Code::NotFound, "gone"));
+        let status = method.call(Request::new(4)).unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let calls = method.calls();
+        let messages: Vec<_> = calls.iter().map(|call| *call.get_ref()).collect();
+        assert_eq!(messages, vec![1, 2, 3, 4]);
+        assert_eq!(calls[1].metadata().get("x-test").unwrap(), "yes");
+    }
+
+    #[test]
+    fn answers_concurrent_calls() {
+        use std::sync::mpsc;
+        use std::thread;
+
+        let method = Method::<u32, u32>::new();
+        let (entered_tx, entered_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+
+        // The first call blocks in the responder until released.
+        method.respond_with(move |request| {
+            let n = request.into_inner();
+            if n == 1 {
+                entered_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            }
+            Ok(Response::new(n * 10))
+        });
+
+        let call = |n| {
+            let method = method.clone();
+            thread::spawn(move || method.call(Request::new(n)))
+        };
+
+        let first = call(1);
+        entered_rx.recv().unwrap();
+
+        // The second call is recorded, and has its responder, before the
+        // first one is done.
+        let second = call(2);
+        while method.call_count() < 2 {
+            thread::yield_now();
+        }
+        release_tx.send(()).unwrap();
+
+        assert_eq!(*first.join().unwrap().unwrap().get_ref(), 10);
+        assert_eq!(*second.join().unwrap().unwrap().get_ref(), 20);
+    }
+
+    #[test]
+    fn collects_streams() {
+        let method = Method::<Vec<u32>, Vec<u32>>::new();
+        method.respond_with(|request| {
+            let messages = request.into_inner();
+            Ok(Response::new(messages.iter().map(|n| n + 1).collect()))
+        });
+
+        let request = Request::new(stream::iter_ok::<_, Status>(vec![1, 2, 3]));
+        let response = method.streaming(request).wait().unwrap();
+        let messages = response.into_inner().collect().wait().unwrap();
+
+        assert_eq!(messages, vec![2, 3, 4]);
+        assert_eq!(method.call_count(), 1);
+        assert_eq!(*method.calls()[0].get_ref(), vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Following a call until its final status is known.
+//!
+//! `ResponseFuture` and `ResponseBody` wrap a response, handing every chunk
+//! of its body to an `Observe`, and finishing it exactly once: with the
+//! status in the trailers, the status of a trailers-only response, an
+//! error, or `Code::Cancelled` if the call is dropped before completing.
+
+use body::Body;
+use error::Error;
+use {Code, Status};
+
+use bytes::Buf;
+use futures::{Async, Future, Poll};
+use http;
+
+/// A call in progress, told about the response it receives.
+pub(crate) trait Observe {
+    /// Called with every chunk of the response body.
+    fn data<T: Buf>(&mut self, _buf: &T) {}
+
+    /// Called once the final status of the call is known.
+    fn finish(self, status: Status);
+}
+
+/// Wraps a response future, finishing the call if it fails or is dropped.
+pub(crate) struct ResponseFuture<F, O>
+where
+    O: Observe,
+{
+    inner: F,
+    call: Option<O>,
+}
+
+/// Wraps a response body, finishing the call once its status is known.
+pub(crate) struct ResponseBody<B, O>
+where
+    O: Observe,
+{
+    inner: B,
+    call: Option<O>,
+    /// The status of a trailers-only response.
+    status: Option<Status>,
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, O> ResponseFuture<F, O>
+where
+    O: Observe,
+{
+    pub(crate) fn new(inner: F, call: O) -> Self {
+        ResponseFuture {
+            inner,
+            call: Some(call),
+        }
+    }
+}
+
+impl<F, O, B> Future for ResponseFuture<F, O>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    O: Observe,
+{
+    type Item = http::Response<ResponseBody<B, O>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = match self.inner.poll() {
+            Ok(Async::Ready(response)) => response,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(e) => {
+                let e: Error = e.into();
+                if let Some(call) = self.call.take() {
+                    call.finish(Status::from_error(&*e));
+                }
+                return Err(e);
+            }
+        };
+
+        let status = Status::from_header_map(response.headers());
+        let call = self.call.take();
+
+        Ok(response
+            .map(|inner| ResponseBody {
+                inner,
+                call,
+                status,
+            })
+            .into())
+    }
+}
+
+impl<F, O> Drop for ResponseFuture<F, O>
+where
+    O: Observe,
+{
+    fn drop(&mut self) {
+        if let Some(call) = self.call.take() {
+            call.finish(Status::new(
+                Code::Cancelled,
+                "call dropped before responding",
+            ));
+        }
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B, O> ResponseBody<B, O>
+where
+    O: Observe,
+{
+    pub(crate) fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    fn finish(&mut self, status: Status) {
+        if let Some(call) = self.call.take() {
+            call.finish(status);
+        }
+    }
+}
+
+impl<B, O> ResponseBody<B, O>
+where
+    B: Body,
+    O: Observe,
+{
+    pub(crate) fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    pub(crate) fn poll_buf(&mut self) -> Poll<Option<B::Item>, Status> {
+        match self.inner.poll_buf().map_err(Status::map_error) {
+            Ok(ready) => {
+                if let Async::Ready(Some(ref buf)) = ready {
+                    if let Some(ref mut call) = self.call {
+                        call.data(buf);
+                    }
+                }
+                Ok(ready)
+            }
+            Err(status) => {
+                self.finish(status.clone());
+                Err(status)
+            }
+        }
+    }
+
+    pub(crate) fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Status> {
+        match self.inner.poll_trailers().map_err(Status::map_error) {
+            Ok(ready) => {
+                if let Async::Ready(ref trailers) = ready {
+                    let status = trailers
+                        .as_ref()
+                        .and_then(Status::from_header_map)
+                        .or_else(|| self.status.take())
+                        .unwrap_or_else(|| Status::new(Code::Unknown, "missing grpc-status"));
+                    self.finish(status);
+                }
+                Ok(ready)
+            }
+            Err(status) => {
+                self.finish(status.clone());
+                Err(status)
+            }
+        }
+    }
+}
+
+impl<B, O> Drop for ResponseBody<B, O>
+where
+    O: Observe,
+{
+    fn drop(&mut self) {
+        // Trailers-only responses may end without their trailers being
+        // polled; anything else ending early was cancelled.
+        let status = self
+            .status
+            .take()
+            .unwrap_or_else(|| Status::new(Code::Cancelled, "response dropped before completing"));
+        self.finish(status);
+    }
+}
@@ -1,10 +1,14 @@
+use generic::server::ConnectionInfo;
 use http;
 use metadata::MetadataMap;
 
+use std::net::SocketAddr;
+
 #[derive(Debug)]
 pub struct Request<T> {
     metadata: MetadataMap,
     message: T,
+    extensions: http::Extensions,
 }
 
 impl<T> Request<T> {
@@ -13,6 +17,7 @@ impl<T> Request<T> {
         Request {
             metadata: MetadataMap::new(),
             message,
+            extensions: http::Extensions::new(),
         }
     }
 
@@ -36,6 +41,39 @@ impl<T> Request<T> {
         &mut self.metadata
     }
 
+    /// Get a reference to the request extensions.
+    ///
+    /// Extensions are not sent over the wire; they carry typed values between
+    /// the transport, middleware and the service handling the request.
+    pub fn extensions(&self) -> &http::Extensions {
+        &self.extensions
+    }
+
+    /// Get a mutable reference to the request extensions.
+    pub fn extensions_mut(&mut self) -> &mut http::Extensions {
+        &mut self.extensions
+    }
+
+    /// Get the address of the remote peer this request was received from.
+    ///
+    /// This is only available on the server, when the service was wrapped in
+    /// a `WithConnectionInfo` for the connection.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.extensions
+            .get::<ConnectionInfo>()
+            .map(ConnectionInfo::remote_addr)
+    }
+
+    /// Get the local address of the connection this request was received on.
+    ///
+    /// This is only available on the server, when the service was wrapped in
+    /// a `WithConnectionInfo` for the connection.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.extensions
+            .get::<ConnectionInfo>()
+            .map(ConnectionInfo::local_addr)
+    }
+
     /// Consumes `self`, returning the message
     pub fn into_inner(self) -> T {
         self.message
@@ -47,6 +85,7 @@ impl<T> Request<T> {
         Request {
             metadata: MetadataMap::from_headers(head.headers),
             message,
+            extensions: head.extensions,
         }
     }
 
@@ -57,6 +96,7 @@ impl<T> Request<T> {
         *request.method_mut() = http::Method::POST;
         *request.uri_mut() = uri;
         *request.headers_mut() = self.metadata.into_headers();
+        *request.extensions_mut() = self.extensions;
 
         request
     }
@@ -70,6 +110,33 @@ impl<T> Request<T> {
         Request {
             metadata: self.metadata,
             message,
+            extensions: self.extensions,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_connection_addresses() {
+        let remote = "10.0.0.1:5000".parse().unwrap();
+        let local = "127.0.0.1:50051".parse().unwrap();
+
+        let mut http = http::Request::new(());
+        http.extensions_mut()
+            .insert(ConnectionInfo::new(remote, local));
+
+        let request = Request::from_http(http);
+        assert_eq!(request.remote_addr(), Some(remote));
+        assert_eq!(request.local_addr(), Some(local));
+    }
+
+    #[test]
+    fn no_addresses_without_connection_info() {
+        let request = Request::new(());
+        assert_eq!(request.remote_addr(), None);
+        assert_eq!(request.local_addr(), None);
+    }
+}
@@ -0,0 +1,579 @@
+//! Logging one record per completed call.
+
+use body::{Body, BoxBody, BytesBuf, HttpBody};
+use error::Error;
+use generic::server::ConnectionInfo;
+use metrics::Frames;
+use observe::{self, Observe};
+use Status;
+
+use bytes::Buf;
+use futures::{Future, Poll};
+use http;
+use tower_service::Service;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Writes an access log record for every call handled by a server service.
+///
+/// Records are written through the `log` crate, at the `info` level with
+/// the `tower_grpc::access` target, unless another `AccessSink` is set.
+///
+/// Only the keys of the request metadata are logged, unless a redaction
+/// hook is set with `redact`: their values are then logged too, except for
+/// those the hook matches, which are replaced by `<redacted>`.
+///
+/// ```rust,ignore
+/// let greeter = AccessLog::new(GreeterServer::new(Greet))
+///     .redact(|key| key == "authorization" || key == "x-api-key");
+///
+/// let router = Router::new().add_service(greeter);
+/// ```
+pub struct AccessLog<S> {
+    inner: S,
+    sink: Arc<dyn AccessSink>,
+    redact: Option<Arc<dyn Fn(&str) -> bool + Send + Sync>>,
+}
+
+/// A completed call.
+#[derive(Debug)]
+pub struct Record {
+    method: String,
+    peer: Option<SocketAddr>,
+    metadata: Vec<(String, Option<String>)>,
+    messages_received: usize,
+    messages_sent: usize,
+    bytes_received: usize,
+    bytes_sent: usize,
+    duration: Duration,
+    status: Status,
+}
+
+/// Receives access log records.
+pub trait AccessSink: Send + Sync + 'static {
+    /// Write the record of a completed call.
+    fn record(&self, record: Record);
+}
+
+/// Response future returned by `AccessLog`.
+pub struct ResponseFuture<F> {
+    inner: observe::ResponseFuture<F, Call>,
+}
+
+/// Response body returned by `AccessLog`, writing the record once the
+/// status of the call is known.
+pub struct ResponseBody<B> {
+    inner: observe::ResponseBody<B, Call>,
+}
+
+/// A request body counting the messages and bytes it carries.
+struct RequestBody {
+    inner: BoxBody,
+    frames: Frames,
+    counts: Arc<Counts>,
+}
+
+/// A call in progress.
+struct Call {
+    sink: Arc<dyn AccessSink>,
+    method: String,
+    peer: Option<SocketAddr>,
+    metadata: Vec<(String, Option<String>)>,
+    counts: Arc<Counts>,
+    /// Follows the framing of the response body.
+    frames: Frames,
+    start: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    messages_received: AtomicUsize,
+    messages_sent: AtomicUsize,
+    bytes_received: AtomicUsize,
+    bytes_sent: AtomicUsize,
+}
+
+/// Writes records through the `log` crate.
+struct Log;
+
+// ===== impl AccessLog =====
+
+impl<S> AccessLog<S> {
+    /// Log the calls handled by `inner`.
+    pub fn new(inner: S) -> Self {
+        AccessLog {
+            inner,
+            sink: Arc::new(Log),
+            redact: None,
+        }
+    }
+
+    /// Write records to `sink`, instead of through the `log` crate.
+    pub fn sink<K>(mut self, sink: K) -> Self
+    where
+        K: AccessSink,
+    {
+        self.sink = Arc::new(sink);
+        self
+    }
+
+    /// Log the values of the request metadata, along with their keys,
+    /// redacting those whose keys match `redact`.
+    ///
+    /// Nothing is redacted but what `redact` matches, so it should match
+    /// any credentials, such as `authorization`.
+    pub fn redact<F>(mut self, redact: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.redact = Some(Arc::new(redact));
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, B> Service<http::Request<BoxBody>> for AccessLog<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Body,
+{
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let metadata = request
+            .headers()
+            .iter()
+            .map(|(key, value)| {
+                let value = self.redact.as_ref().map(|redact| {
+                    if redact(key.as_str()) {
+                        "<redacted>".to_string()
+                    } else {
+                        String::from_utf8_lossy(value.as_bytes()).into_owned()
+                    }
+                });
+                (key.as_str().to_string(), value)
+            })
+            .collect();
+
+        let counts = Arc::new(Counts::default());
+        let call = Call {
+            sink: self.sink.clone(),
+            method: request.uri().path().to_string(),
+            peer: request
+                .extensions()
+                .get::<ConnectionInfo>()
+                .map(|info| info.remote_addr()),
+            metadata,
+            counts: counts.clone(),
+            frames: Frames::default(),
+            start: Instant::now(),
+        };
+
+        let request = request.map(move |inner| {
+            BoxBody::new(Box::new(RequestBody {
+                inner,
+                frames: Frames::default(),
+                counts,
+            }))
+        });
+
+        ResponseFuture {
+            inner: observe::ResponseFuture::new(self.inner.call(request), call),
+        }
+    }
+}
+
+impl<S> Clone for AccessLog<S>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        AccessLog {
+            inner: self.inner.clone(),
+            sink: self.sink.clone(),
+            redact: self.redact.clone(),
+        }
+    }
+}
+
+impl<S> super::NamedService for AccessLog<S>
+where
+    S: super::NamedService,
+{
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> fmt::Debug for AccessLog<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl Record =====
+
+impl Record {
+    /// Returns the `/package.Service/Method` path of the call.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the address of the client, if known.
+    ///
+    /// It is known when the server records `ConnectionInfo` on requests, as
+    /// `Builder` and `WithConnectionInfo` do.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// Returns the keys of the request metadata, along with their values
+    /// when `AccessLog::redact` is set.
+    pub fn metadata(&self) -> &[(String, Option<String>)] {
+        &self.metadata
+    }
+
+    /// Returns the number of request messages received.
+    pub fn messages_received(&self) -> usize {
+        self.messages_received
+    }
+
+    /// Returns the number of response messages sent.
+    pub fn messages_sent(&self) -> usize {
+        self.messages_sent
+    }
+
+    /// Returns the number of request body bytes received, including message
+    /// framing.
+    pub fn bytes_received(&self) -> usize {
+        self.bytes_received
+    }
+
+    /// Returns the number of response body bytes sent, including message
+    /// framing.
+    pub fn bytes_sent(&self) -> usize {
+        self.bytes_sent
+    }
+
+    /// Returns how long the call took, until its status was sent.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the final status of the call.
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "method={} peer=", self.method)?;
+        match self.peer {
+            Some(peer) => write!(f, "{}", peer)?,
+            None => f.write_str("-")?,
+        }
+
+        let duration =
+            self.duration.as_secs() as f64 * 1e3 + f64::from(self.duration.subsec_nanos()) / 1e6;
+        write!(
+            f,
+            " code={:?} message={:?} duration_ms={:.3} \
+             messages_received={} messages_sent={} bytes_received={} bytes_sent={}",
+            self.status.code(),
+            self.status.message(),
+            duration,
+            self.messages_received,
+            self.messages_sent,
+            self.bytes_received,
+            self.bytes_sent,
+        )?;
+
+        for &(ref key, ref value) in &self.metadata {
+            match *value {
+                Some(ref value) => write!(f, " {}={:?}", key, value)?,
+                None => write!(f, " {}=-", key)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// ===== impl Log =====
+
+impl AccessSink for Log {
+    fn record(&self, record: Record) {
+        info!(target: "tower_grpc::access", "{}", record);
+    }
+}
+
+// ===== impl Call =====
+
+impl Observe for Call {
+    fn data<T: Buf>(&mut self, buf: &T) {
+        let messages = self.frames.count(buf.bytes());
+        let counts = &self.counts;
+        counts.messages_sent.fetch_add(messages, Ordering::SeqCst);
+        counts
+            .bytes_sent
+            .fetch_add(buf.remaining(), Ordering::SeqCst);
+    }
+
+    fn finish(self, status: Status) {
+        let counts = &self.counts;
+
+        self.sink.record(Record {
+            method: self.method,
+            peer: self.peer,
+            metadata: self.metadata,
+            messages_received: counts.messages_received.load(Ordering::SeqCst),
+            messages_sent: counts.messages_sent.load(Ordering::SeqCst),
+            bytes_received: counts.bytes_received.load(Ordering::SeqCst),
+            bytes_sent: counts.bytes_sent.load(Ordering::SeqCst),
+            duration: self.start.elapsed(),
+            status,
+        });
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+        Ok(response.map(|inner| ResponseBody { inner }).into())
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("access_log::ResponseFuture").finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> HttpBody for ResponseBody<B>
+where
+    B: Body,
+{
+    type Item = B::Item;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll_buf()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where
+    B: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("access_log::ResponseBody")
+            .field("inner", self.inner.get_ref())
+            .finish()
+    }
+}
+
+// ===== impl RequestBody =====
+
+impl HttpBody for RequestBody {
+    type Item = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        HttpBody::is_end_stream(&self.inner)
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let buf = try_ready!(HttpBody::poll_buf(&mut self.inner));
+
+        if let Some(ref buf) = buf {
+            let messages = self.frames.count(buf.bytes());
+            let counts = &self.counts;
+            counts
+                .messages_received
+                .fetch_add(messages, Ordering::SeqCst);
+            counts
+                .bytes_received
+                .fetch_add(buf.remaining(), Ordering::SeqCst);
+        }
+
+        Ok(buf.into())
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        HttpBody::poll_trailers(&mut self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{Bytes, IntoBuf};
+    use futures::future::{self, FutureResult};
+    use futures::Async;
+    use std::sync::Mutex;
+    use Code;
+
+    #[derive(Clone, Default)]
+    struct Records(Arc<Mutex<Vec<Record>>>);
+
+    impl AccessSink for Records {
+        fn record(&self, record: Record) {
+            self.0.lock().unwrap().push(record);
+        }
+    }
+
+    /// Replies to `/test.Svc/Ok` with one message, and to anything else
+    /// with a trailers-only `NotFound` response.
+    struct Reply;
+
+    struct Message(Option<Bytes>, Option<http::HeaderMap>);
+
+    impl Service<http::Request<BoxBody>> for Reply {
+        type Response = http::Response<Message>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            if request.uri().path() != "/test.Svc/Ok" {
+                let mut headers = http::HeaderMap::new();
+                Status::new(Code::NotFound, "nope")
+                    .add_header(&mut headers)
+                    .unwrap();
+                let mut response = http::Response::new(Message(None, None));
+                *response.headers_mut() = headers;
+                return future::ok(response);
+            }
+
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::header::HeaderValue::from_static("0"));
+            let message = Bytes::from_static(&[0, 0, 0, 0, 2, 8, 1]);
+            future::ok(http::Response::new(Message(Some(message), Some(trailers))))
+        }
+    }
+
+    impl HttpBody for Message {
+        type Item = BytesBuf;
+        type Error = Status;
+
+        fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+            Ok(self.0.take().map(IntoBuf::into_buf).into())
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+            Ok(self.1.take().into())
+        }
+    }
+
+    fn call(log: &mut AccessLog<Reply>, path: &str) -> http::Response<ResponseBody<Message>> {
+        let mut request = http::Request::builder()
+            .uri(path)
+            .header("authorization", "Bearer secret")
+            .header("x-request-id", "42")
+            .body(BoxBody::empty())
+            .unwrap();
+        let info = ConnectionInfo::new(
+            "10.0.0.1:5000".parse().unwrap(),
+            "10.0.0.2:50051".parse().unwrap(),
+        );
+        request.extensions_mut().insert(info);
+
+        log.call(request).wait().unwrap()
+    }
+
+    #[test]
+    fn records_completed_calls() {
+        let records = Records::default();
+        let mut log = AccessLog::new(Reply).sink(records.clone());
+
+        let mut body = call(&mut log, "/test.Svc/Ok").into_body();
+        while let Async::Ready(Some(_)) = HttpBody::poll_buf(&mut body).unwrap() {}
+        HttpBody::poll_trailers(&mut body).unwrap();
+
+        let records = records.0.lock().unwrap();
+        assert_eq!(records.len(), 1);
+
+        let record = &records[0];
+        assert_eq!(record.method(), "/test.Svc/Ok");
+        assert_eq!(record.peer(), Some("10.0.0.1:5000".parse().unwrap()));
+        assert_eq!(record.status().code(), Code::Ok);
+        assert_eq!(record.messages_sent(), 1);
+        assert_eq!(record.bytes_sent(), 7);
+        assert_eq!(record.messages_received(), 0);
+
+        let metadata = record.metadata();
+        assert!(metadata.contains(&("authorization".to_string(), None)));
+        assert!(metadata.contains(&("x-request-id".to_string(), None)));
+
+        let line = record.to_string();
+        assert!(line.starts_with("method=/test.Svc/Ok peer=10.0.0.1:5000 code=Ok"));
+        assert!(line.contains(" authorization=-"));
+        assert!(!line.contains("secret"));
+    }
+
+    #[test]
+    fn records_trailers_only_status() {
+        let records = Records::default();
+        let mut log = AccessLog::new(Reply)
+            .sink(records.clone())
+            .redact(|key| key == "x-request-id");
+
+        drop(call(&mut log, "/test.Svc/Missing"));
+
+        let records = records.0.lock().unwrap();
+        let record = &records[0];
+        assert_eq!(record.status().code(), Code::NotFound);
+        assert_eq!(record.status().message(), "nope");
+
+        let metadata = record.metadata();
+        let value = |value: &str| Some(value.to_string());
+        assert!(metadata.contains(&("authorization".to_string(), value("Bearer secret"))));
+        assert!(metadata.contains(&("x-request-id".to_string(), value("<redacted>"))));
+    }
+}
@@ -0,0 +1,518 @@
+use super::limit::{ConcurrencyLimit, Limits};
+use super::router::{NamedService, Router};
+use body::{Body, BoxBody, BytesBuf};
+use error::Error;
+use generic::server::{ConnectionInfo, WithConnectionInfo};
+use Status;
+
+use futures::future::{self, Shared};
+use futures::sync::{mpsc, oneshot};
+use futures::{Async, Future, Poll, Stream};
+use h2;
+use http;
+use tokio_executor::{DefaultExecutor, Executor};
+use tokio_tcp::{Incoming, TcpListener, TcpStream};
+use tokio_timer::Delay;
+use tower_h2;
+use tower_service::Service;
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Configures and binds a gRPC server over HTTP/2.
+///
+/// ```rust,ignore
+/// let serve = Builder::new()
+///     .add_service(GreeterServer::new(Greet))
+///     .add_service(HealthServer::new(health))
+///     .drain_timeout(Duration::from_secs(10))
+///     .serve_with_shutdown(&addr, shutdown_signal)?;
+///
+/// tokio::run(serve.map_err(|e| eprintln!("server error: {}", e)));
+/// ```
+#[derive(Debug)]
+pub struct Builder {
+    router: Router,
+    limits: Limits,
+    http2_settings: h2::server::Builder,
+    tcp_nodelay: bool,
+    drain_timeout: Option<Duration>,
+}
+
+/// A future accepting connections and serving them, returned by `Builder`.
+///
+/// Once the shutdown signal fires, no new connections are accepted, a GOAWAY
+/// is sent on every open connection, and the future completes when all
+/// in-flight RPCs are done or the drain timeout elapses, whichever is first.
+/// Connections still open at that point are closed.
+pub struct Serve<F> {
+    incoming: Incoming,
+    local_addr: SocketAddr,
+    router: ConcurrencyLimit<Router>,
+    http2_settings: h2::server::Builder,
+    tcp_nodelay: bool,
+    drain_timeout: Option<Duration>,
+    state: State<F>,
+    /// Dropped to start a graceful shutdown of every connection.
+    shutdown_tx: Option<oneshot::Sender<()>>,
+    shutdown_rx: Shared<oneshot::Receiver<()>>,
+    /// Dropped along with `Serve` to close any remaining connections.
+    _abort_tx: oneshot::Sender<()>,
+    abort_rx: Shared<oneshot::Receiver<()>>,
+    /// Every connection holds a clone; `active_rx` ends once all are gone.
+    active_tx: Option<mpsc::Sender<()>>,
+    active_rx: mpsc::Receiver<()>,
+}
+
+enum State<F> {
+    Serving(F),
+    Draining(Option<Delay>),
+}
+
+/// Drives a single connection, starting a graceful shutdown when signaled.
+struct Connection<C, G> {
+    conn: C,
+    graceful_shutdown: G,
+    draining: bool,
+    shutdown: Shared<oneshot::Receiver<()>>,
+    abort: Shared<oneshot::Receiver<()>>,
+    _active: mpsc::Sender<()>,
+}
+
+// ===== impl Builder =====
+
+impl Builder {
+    /// Create a new `Builder` without any services.
+    pub fn new() -> Self {
+        Builder {
+            router: Router::new(),
+            limits: Limits::default(),
+            http2_settings: h2::server::Builder::new(),
+            tcp_nodelay: true,
+            drain_timeout: None,
+        }
+    }
+
+    /// Serve a generated service.
+    ///
+    /// See `Router::add_service`.
+    pub fn add_service<S, B>(mut self, service: S) -> Self
+    where
+        S: NamedService
+            + Service<http::Request<BoxBody>, Response = http::Response<B>>
+            + Clone
+            + Send
+            + 'static,
+        S::Error: Into<Error>,
+        S::Future: Send + 'static,
+        B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+    {
+        self.router = self.router.add_service(service);
+        self
+    }
+
+    /// Serve `service` under the service name `name`.
+    ///
+    /// See `Router::add_route`.
+    pub fn add_route<S, B>(mut self, name: &str, service: S) -> Self
+    where
+        S: Service<http::Request<BoxBody>, Response = http::Response<B>> + Clone + Send + 'static,
+        S::Error: Into<Error>,
+        S::Future: Send + 'static,
+        B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+    {
+        self.router = self.router.add_route(name, service);
+        self
+    }
+
+    /// Use `router` for routing requests, replacing any services added so
+    /// far.
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Allow at most `max` RPCs in flight across all connections.
+    ///
+    /// Calls over the limit are rejected with `Code::ResourceExhausted`
+    /// rather than queued. See `ConcurrencyLimit`.
+    pub fn max_concurrent_calls(mut self, max: usize) -> Self {
+        self.limits = self.limits.max_concurrent_calls(max);
+        self
+    }
+
+    /// Allow at most `max` RPCs in flight to the method at `path`, a full
+    /// `/package.Service/Method` request path.
+    ///
+    /// See `ConcurrencyLimit`.
+    pub fn max_concurrent_calls_per_method(mut self, path: &str, max: usize) -> Self {
+        self.limits = self.limits.max_concurrent_calls_per_method(path, max);
+        self
+    }
+
+    /// Set the HTTP/2 settings used for every connection.
+    pub fn http2_settings(mut self, settings: h2::server::Builder) -> Self {
+        self.http2_settings = settings;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on accepted connections. Defaults to `true`.
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.tcp_nodelay = enabled;
+        self
+    }
+
+    /// Limit how long in-flight RPCs may take to finish after shutdown is
+    /// signaled. Without a timeout, the server waits for all of them.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /// Bind `addr` and serve until the returned future is dropped.
+    pub fn serve(self, addr: &SocketAddr) -> io::Result<Serve<future::Empty<(), ()>>> {
+        self.serve_with_shutdown(addr, future::empty())
+    }
+
+    /// Bind `addr` and serve until `signal` completes, then shut down
+    /// gracefully.
+    ///
+    /// `signal` failing also triggers the shutdown.
+    pub fn serve_with_shutdown<F>(self, addr: &SocketAddr, signal: F) -> io::Result<Serve<F>>
+    where
+        F: Future,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let (abort_tx, abort_rx) = oneshot::channel();
+        let (active_tx, active_rx) = mpsc::channel(0);
+
+        Ok(Serve {
+            incoming: listener.incoming(),
+            local_addr,
+            router: ConcurrencyLimit::with_limits(self.router, self.limits),
+            http2_settings: self.http2_settings,
+            tcp_nodelay: self.tcp_nodelay,
+            drain_timeout: self.drain_timeout,
+            state: State::Serving(signal),
+            shutdown_tx: Some(shutdown_tx),
+            shutdown_rx: shutdown_rx.shared(),
+            _abort_tx: abort_tx,
+            abort_rx: abort_rx.shared(),
+            active_tx: Some(active_tx),
+            active_rx,
+        })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+// ===== impl Serve =====
+
+impl<F> Serve<F> {
+    /// Returns the address the server is bound to.
+    ///
+    /// Useful when binding to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    fn spawn(&mut self, sock: TcpStream) -> io::Result<()> {
+        sock.set_nodelay(self.tcp_nodelay)?;
+        let info = ConnectionInfo::new(sock.peer_addr()?, sock.local_addr()?);
+
+        let active = match self.active_tx {
+            Some(ref active) => active.clone(),
+            None => return Ok(()),
+        };
+
+        let service = WithConnectionInfo::new(self.router.clone(), info);
+        let mut h2 = tower_h2::Server::new(
+            service,
+            self.http2_settings.clone(),
+            DefaultExecutor::current(),
+        );
+
+        let conn = Connection::new(
+            h2.serve(sock),
+            |conn| conn.graceful_shutdown(),
+            self.shutdown_rx.clone(),
+            self.abort_rx.clone(),
+            active,
+        );
+
+        DefaultExecutor::current()
+            .spawn(Box::new(conn))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+    }
+
+    fn start_draining(&mut self) {
+        debug!("shutting down; draining connections");
+
+        // Dropping the senders signals every connection to shut down, and
+        // lets `active_rx` end once the last connection is done.
+        self.shutdown_tx.take();
+        self.active_tx.take();
+
+        let delay = self
+            .drain_timeout
+            .map(|timeout| Delay::new(Instant::now() + timeout));
+        self.state = State::Draining(delay);
+    }
+}
+
+impl<F> Future for Serve<F>
+where
+    F: Future,
+{
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match self.state {
+                State::Serving(ref mut signal) => match signal.poll() {
+                    Ok(Async::NotReady) => {}
+                    Ok(Async::Ready(_)) | Err(_) => {
+                        self.start_draining();
+                        continue;
+                    }
+                },
+                State::Draining(ref mut delay) => {
+                    let elapsed = match *delay {
+                        Some(ref mut delay) => match delay.poll() {
+                            Ok(Async::NotReady) => false,
+                            Ok(Async::Ready(())) => true,
+                            Err(e) => {
+                                warn!("drain timer failed: {}", e);
+                                true
+                            }
+                        },
+                        None => false,
+                    };
+
+                    if elapsed {
+                        debug!("drain timeout elapsed; closing remaining connections");
+                        return Ok(Async::Ready(()));
+                    }
+
+                    return match self.active_rx.poll() {
+                        Ok(Async::NotReady) => Ok(Async::NotReady),
+                        Ok(Async::Ready(_)) | Err(_) => Ok(Async::Ready(())),
+                    };
+                }
+            }
+
+            let sock = match self.incoming.poll() {
+                Ok(Async::Ready(Some(sock))) => sock,
+                Ok(Async::Ready(None)) => {
+                    self.start_draining();
+                    continue;
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(ref e) if is_connection_error(e) => {
+                    debug!("accept error: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let Err(e) = self.spawn(sock) {
+                debug!("failed to serve connection: {}", e);
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for Serve<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Serve")
+            .field("local_addr", &self.local_addr)
+            .field("router", &self.router)
+            .field("draining", &self.shutdown_tx.is_none())
+            .finish()
+    }
+}
+
+/// Errors that only affect the connection being accepted.
+fn is_connection_error(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset => true,
+        _ => false,
+    }
+}
+
+// ===== impl Connection =====
+
+impl<C, G> Connection<C, G>
+where
+    G: FnMut(&mut C),
+{
+    fn new(
+        conn: C,
+        graceful_shutdown: G,
+        shutdown: Shared<oneshot::Receiver<()>>,
+        abort: Shared<oneshot::Receiver<()>>,
+        active: mpsc::Sender<()>,
+    ) -> Self {
+        Connection {
+            conn,
+            graceful_shutdown,
+            draining: false,
+            shutdown,
+            abort,
+            _active: active,
+        }
+    }
+}
+
+impl<C, G> Future for Connection<C, G>
+where
+    C: Future<Item = ()>,
+    C::Error: fmt::Debug,
+    G: FnMut(&mut C),
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.abort.poll() {
+            Ok(Async::NotReady) => {}
+            // The server is gone, or gave up draining.
+            _ => return Ok(Async::Ready(())),
+        }
+
+        if !self.draining {
+            match self.shutdown.poll() {
+                Ok(Async::NotReady) => {}
+                _ => {
+                    self.draining = true;
+                    (self.graceful_shutdown)(&mut self.conn);
+                }
+            }
+        }
+
+        self.conn.poll().map_err(|e| debug!("h2 error: {:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::runtime::current_thread::Runtime;
+
+    type Signal = oneshot::Receiver<()>;
+
+    fn bind(rt: &mut Runtime, builder: Builder) -> (Serve<Signal>, oneshot::Sender<()>) {
+        let (signal_tx, signal_rx) = oneshot::channel();
+        let addr = "127.0.0.1:0".parse().unwrap();
+
+        let serve = rt
+            .block_on(future::lazy(|| {
+                builder.serve_with_shutdown(&addr, signal_rx)
+            }))
+            .unwrap();
+
+        (serve, signal_tx)
+    }
+
+    /// A connection to `serve` that never completes on its own, counting
+    /// the graceful shutdowns it was asked to do.
+    fn connect(
+        serve: &Serve<Signal>,
+    ) -> (
+        Connection<future::Empty<(), ()>, impl FnMut(&mut future::Empty<(), ()>)>,
+        Arc<AtomicUsize>,
+    ) {
+        let shutdowns = Arc::new(AtomicUsize::new(0));
+        let counter = shutdowns.clone();
+
+        let conn = Connection::new(
+            future::empty(),
+            move |_: &mut future::Empty<(), ()>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            },
+            serve.shutdown_rx.clone(),
+            serve.abort_rx.clone(),
+            serve.active_tx.clone().unwrap(),
+        );
+
+        (conn, shutdowns)
+    }
+
+    #[test]
+    fn shuts_down_when_signaled() {
+        let mut rt = Runtime::new().unwrap();
+        let (serve, signal) = bind(&mut rt, Builder::new());
+
+        signal.send(()).unwrap();
+        rt.block_on(serve).unwrap();
+    }
+
+    #[test]
+    fn drains_connections_gracefully() {
+        let mut rt = Runtime::new().unwrap();
+        let (mut serve, signal) = bind(&mut rt, Builder::new());
+        let (mut conn, shutdowns) = connect(&serve);
+
+        signal.send(()).unwrap();
+
+        let mut serve = rt
+            .block_on(future::lazy(move || {
+                // The connection is still open, so the server keeps draining.
+                assert!(serve.poll().unwrap().is_not_ready());
+                Ok::<_, ()>(serve)
+            }))
+            .unwrap();
+
+        let conn = rt
+            .block_on(future::lazy(move || {
+                // The connection is told to shut down, exactly once.
+                assert!(conn.poll().unwrap().is_not_ready());
+                assert!(conn.poll().unwrap().is_not_ready());
+                assert_eq!(shutdowns.load(Ordering::SeqCst), 1);
+                Ok::<_, ()>(conn)
+            }))
+            .unwrap();
+
+        rt.block_on(future::lazy(|| {
+            assert!(serve.poll().unwrap().is_not_ready());
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        // The server is done once the connection finished.
+        drop(conn);
+        rt.block_on(serve).unwrap();
+    }
+
+    #[test]
+    fn aborts_connections_after_drain_timeout() {
+        let mut rt = Runtime::new().unwrap();
+        let builder = Builder::new().drain_timeout(Duration::from_millis(10));
+        let (serve, signal) = bind(&mut rt, builder);
+        let (mut conn, _shutdowns) = connect(&serve);
+
+        signal.send(()).unwrap();
+
+        // The connection never finishes, so the server only completes once
+        // the drain timeout elapses.
+        rt.block_on(serve).unwrap();
+
+        // With the server gone, the remaining connection is closed.
+        let done = rt.block_on(future::lazy(move || conn.poll())).unwrap();
+        assert!(done.is_ready());
+    }
+}
@@ -0,0 +1,396 @@
+//! Limiting the number of in-flight calls.
+
+use super::router::{status_response, NamedService};
+use body::{Body, BoxBody, BytesBuf, HttpBody};
+use {Code, Status};
+
+use futures::{Async, Future, Poll};
+use http;
+use tower_service::Service;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Caps the number of calls in flight on a service, overall and per method.
+///
+/// A call is in flight from the moment it is received until its response
+/// body has been sent or dropped. Calls over a limit are not queued: they are
+/// answered right away with a trailers-only `Code::ResourceExhausted`
+/// response, leaving the client free to retry elsewhere.
+///
+/// Limits are checked when calls are made rather than in `poll_ready`, so
+/// they apply to generated `XServer` types, which are always ready. Clones
+/// share the same counters, so wrapping a service once before handing it to
+/// a server or `Router` limits it across every connection.
+///
+/// ```rust,ignore
+/// let greeter = ConcurrencyLimit::new(GreeterServer::new(Greet))
+///     .max_concurrent_calls(100)
+///     .max_concurrent_calls_per_method("/helloworld.Greeter/SayHello", 10);
+///
+/// let router = Router::new().add_service(greeter);
+/// ```
+#[derive(Clone)]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    limits: Limits,
+}
+
+/// Response future returned by `ConcurrencyLimit`.
+pub struct ResponseFuture<F> {
+    kind: Kind<F>,
+}
+
+enum Kind<F> {
+    Called(F, Option<Permit>),
+    Rejected(Option<Status>),
+}
+
+/// The limits shared by the clones of a `ConcurrencyLimit`.
+#[derive(Clone, Default)]
+pub(crate) struct Limits {
+    total: Option<Arc<Counter>>,
+    methods: Arc<HashMap<String, Arc<Counter>>>,
+}
+
+#[derive(Debug)]
+struct Counter {
+    max: usize,
+    in_flight: AtomicUsize,
+}
+
+/// Counts one call against each of its limits until dropped.
+#[derive(Default)]
+struct Permit {
+    total: Option<Arc<Counter>>,
+    method: Option<Arc<Counter>>,
+}
+
+/// A response body holding its call's permit.
+struct LimitBody<B> {
+    inner: B,
+    _permit: Permit,
+}
+
+// ===== impl ConcurrencyLimit =====
+
+impl<S> ConcurrencyLimit<S> {
+    /// Wrap `inner`, without any limits yet.
+    pub fn new(inner: S) -> Self {
+        ConcurrencyLimit::with_limits(inner, Limits::default())
+    }
+
+    pub(crate) fn with_limits(inner: S, limits: Limits) -> Self {
+        ConcurrencyLimit { inner, limits }
+    }
+
+    /// Allow at most `max` calls in flight across all methods.
+    pub fn max_concurrent_calls(mut self, max: usize) -> Self {
+        self.limits = self.limits.max_concurrent_calls(max);
+        self
+    }
+
+    /// Allow at most `max` calls in flight to the method at `path`.
+    ///
+    /// `path` is the full `/package.Service/Method` request path. Calls to
+    /// the method also count against the overall limit, if one is set.
+    pub fn max_concurrent_calls_per_method(mut self, path: &str, max: usize) -> Self {
+        self.limits = self.limits.max_concurrent_calls_per_method(path, max);
+        self
+    }
+
+    /// Returns the number of calls currently in flight.
+    ///
+    /// Only calls to methods with a limit are counted when no overall limit
+    /// is set.
+    pub fn in_flight(&self) -> usize {
+        match self.limits.total {
+            Some(ref total) => total.in_flight.load(Ordering::SeqCst),
+            None => self
+                .limits
+                .methods
+                .values()
+                .map(|method| method.in_flight.load(Ordering::SeqCst))
+                .sum(),
+        }
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, R, B> Service<http::Request<R>> for ConcurrencyLimit<S>
+where
+    S: Service<http::Request<R>, Response = http::Response<B>>,
+    B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, request: http::Request<R>) -> Self::Future {
+        let kind = match self.limits.acquire(request.uri().path()) {
+            Ok(permit) => Kind::Called(self.inner.call(request), Some(permit)),
+            Err(status) => {
+                debug!("rejecting {}: {}", request.uri().path(), status.message());
+                Kind::Rejected(Some(status))
+            }
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+impl<S> NamedService for ConcurrencyLimit<S>
+where
+    S: NamedService,
+{
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> fmt::Debug for ConcurrencyLimit<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimit")
+            .field("inner", &self.inner)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+// ===== impl Limits =====
+
+impl Limits {
+    pub(crate) fn max_concurrent_calls(mut self, max: usize) -> Self {
+        self.total = Some(Arc::new(Counter::new(max)));
+        self
+    }
+
+    pub(crate) fn max_concurrent_calls_per_method(mut self, path: &str, max: usize) -> Self {
+        Arc::make_mut(&mut self.methods).insert(path.to_string(), Arc::new(Counter::new(max)));
+        self
+    }
+
+    fn acquire(&self, path: &str) -> Result<Permit, Status> {
+        let mut permit = Permit::default();
+
+        if let Some(ref total) = self.total {
+            if !total.try_acquire() {
+                let message = format!("too many concurrent calls (limit {})", total.max);
+                return Err(Status::new(Code::ResourceExhausted, message));
+            }
+            permit.total = Some(total.clone());
+        }
+
+        if let Some(method) = self.methods.get(path) {
+            // Dropping `permit` releases the overall limit taken above.
+            if !method.try_acquire() {
+                let message = format!("too many concurrent calls to method (limit {})", method.max);
+                return Err(Status::new(Code::ResourceExhausted, message));
+            }
+            permit.method = Some(method.clone());
+        }
+
+        Ok(permit)
+    }
+}
+
+impl fmt::Debug for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut methods: Vec<_> = self
+            .methods
+            .iter()
+            .map(|(path, method)| (path, method.max))
+            .collect();
+        methods.sort();
+
+        f.debug_struct("Limits")
+            .field("total", &self.total.as_ref().map(|total| total.max))
+            .field("methods", &methods)
+            .finish()
+    }
+}
+
+// ===== impl Counter =====
+
+impl Counter {
+    fn new(max: usize) -> Self {
+        Counter {
+            max,
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        if self.in_flight.fetch_add(1, Ordering::SeqCst) < self.max {
+            return true;
+        }
+
+        self.release();
+        false
+    }
+
+    fn release(&self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// ===== impl Permit =====
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        for counter in self.total.iter().chain(self.method.iter()) {
+            counter.release();
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+{
+    type Item = http::Response<BoxBody>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.kind {
+            Kind::Called(ref mut future, ref mut permit) => {
+                let response = try_ready!(future.poll());
+                let permit = permit.take().expect("polled after complete");
+
+                let response = response.map(|inner| {
+                    BoxBody::new(Box::new(LimitBody {
+                        inner,
+                        _permit: permit,
+                    }))
+                });
+                Ok(response.into())
+            }
+            Kind::Rejected(ref mut status) => {
+                let status = status.take().expect("polled after complete");
+                Ok(Async::Ready(status_response(status)))
+            }
+        }
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("limit::ResponseFuture").finish()
+    }
+}
+
+// ===== impl LimitBody =====
+
+impl<B> HttpBody for LimitBody<B>
+where
+    B: Body<Item = BytesBuf, Error = Status>,
+{
+    type Item = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll_buf()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Never;
+    use futures::future::{self, FutureResult};
+
+    #[derive(Clone)]
+    struct Empty;
+
+    impl Service<http::Request<BoxBody>> for Empty {
+        type Response = http::Response<BoxBody>;
+        type Error = Never;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            future::ok(http::Response::new(BoxBody::empty()))
+        }
+    }
+
+    fn call(limit: &mut ConcurrencyLimit<Empty>, path: &str) -> http::Response<BoxBody> {
+        let request = http::Request::builder()
+            .uri(path)
+            .body(BoxBody::empty())
+            .unwrap();
+
+        limit.call(request).wait().unwrap()
+    }
+
+    fn status(response: &http::Response<BoxBody>) -> Option<Code> {
+        Status::from_header_map(response.headers()).map(|status| status.code())
+    }
+
+    #[test]
+    fn rejects_calls_over_the_limit() {
+        let mut limit = ConcurrencyLimit::new(Empty).max_concurrent_calls(2);
+        let mut other = limit.clone();
+
+        let first = call(&mut limit, "/test.Svc/A");
+        let second = call(&mut other, "/test.Svc/B");
+        assert_eq!(status(&first), None);
+        assert_eq!(status(&second), None);
+        assert_eq!(limit.in_flight(), 2);
+
+        let rejected = call(&mut limit, "/test.Svc/A");
+        assert_eq!(status(&rejected), Some(Code::ResourceExhausted));
+        assert_eq!(limit.in_flight(), 2);
+
+        // Finishing a call makes room for another.
+        drop(first);
+        assert_eq!(limit.in_flight(), 1);
+        assert_eq!(status(&call(&mut limit, "/test.Svc/A")), None);
+    }
+
+    #[test]
+    fn limits_methods_separately() {
+        let mut limit = ConcurrencyLimit::new(Empty)
+            .max_concurrent_calls(3)
+            .max_concurrent_calls_per_method("/test.Svc/A", 1);
+
+        let _first = call(&mut limit, "/test.Svc/A");
+
+        let rejected = call(&mut limit, "/test.Svc/A");
+        assert_eq!(status(&rejected), Some(Code::ResourceExhausted));
+        assert_eq!(limit.in_flight(), 1);
+
+        let other = call(&mut limit, "/test.Svc/B");
+        assert_eq!(status(&other), None);
+        assert_eq!(limit.in_flight(), 2);
+    }
+}
@@ -1,18 +1,39 @@
+pub mod access_log;
 pub mod client_streaming;
+pub mod limit;
+pub mod router;
 pub mod server_streaming;
 pub mod streaming;
 pub mod unary;
 pub mod unimplemented;
 
+#[cfg(feature = "tower-h2")]
+mod builder;
+
 use codec::{Codec, Streaming};
 use generic::server::{
     ClientStreamingService, Grpc, ServerStreamingService, StreamingService, UnaryService,
 };
-use Body;
+use {Body, Status};
+
+pub use self::access_log::AccessLog;
+#[cfg(feature = "tower-h2")]
+pub use self::builder::{Builder, Serve};
+pub use self::limit::ConcurrencyLimit;
+pub use self::router::{NamedService, Router};
+pub use generic::server::{ConnectionInfo, WithConnectionInfo};
 
+use futures::{Future, Stream};
 use http;
 use prost;
 
+/// A boxed response future, as returned by the methods of generated service
+/// traits with default `Unimplemented` bodies, and of generated mocks.
+pub type BoxFuture<T> = Box<dyn Future<Item = T, Error = Status> + Send>;
+
+/// A boxed stream of response messages, for server streaming methods.
+pub type BoxStream<T> = Box<dyn Stream<Item = T, Error = Status> + Send>;
+
 pub fn unary<T, B, R>(service: T, request: http::Request<B>) -> unary::ResponseFuture<T, B, R>
 where
     T: UnaryService<R>,
@@ -0,0 +1,395 @@
+use body::{Body, BoxBody, BytesBuf};
+use error::{Error, Never};
+use {Code, Status};
+
+use futures::future::{self, FutureResult};
+use futures::{Async, Future, Poll};
+use http;
+use tower_service::Service;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A service with a fully-qualified gRPC service name.
+///
+/// Implemented by generated `XServer` types.
+pub trait NamedService {
+    /// The `package.Service` name, as found in request paths.
+    const NAME: &'static str;
+}
+
+/// Dispatches requests to one of several services, by gRPC service name.
+///
+/// The service name is taken from the `/package.Service/` prefix of the
+/// request path. Requests for unknown services are sent to the fallback
+/// service, if there is one, or answered with `Code::Unimplemented`.
+/// Unknown methods of a known service are answered by that service.
+pub struct Router {
+    routes: HashMap<String, Box<dyn Route>>,
+    fallback: Option<Box<dyn Route>>,
+}
+
+/// Response future returned by `Router`.
+pub struct ResponseFuture {
+    kind: Kind,
+}
+
+enum Kind {
+    Route(RouteFuture),
+    Status(Option<Status>),
+}
+
+type RouteFuture = Box<dyn Future<Item = http::Response<BoxBody>, Error = Error> + Send>;
+
+/// A type-erased, cloneable service.
+trait Route: Send {
+    /// Wait for a clone of this service to be ready, and call it.
+    fn oneshot(&self, request: http::Request<BoxBody>) -> RouteFuture;
+
+    fn clone_route(&self) -> Box<dyn Route>;
+}
+
+struct Oneshot<T>
+where
+    T: Service<http::Request<BoxBody>>,
+{
+    service: T,
+    state: State<T::Future>,
+}
+
+enum State<F> {
+    NotReady(Option<http::Request<BoxBody>>),
+    Called(F),
+}
+
+// ===== impl Router =====
+
+impl Router {
+    /// Create a new `Router` without any routes.
+    pub fn new() -> Self {
+        Router {
+            routes: HashMap::new(),
+            fallback: None,
+        }
+    }
+
+    /// Route requests for a generated service to it.
+    pub fn add_service<S, B>(self, service: S) -> Self
+    where
+        S: NamedService
+            + Service<http::Request<BoxBody>, Response = http::Response<B>>
+            + Clone
+            + Send
+            + 'static,
+        S::Error: Into<Error>,
+        S::Future: Send + 'static,
+        B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+    {
+        self.add_route(S::NAME, service)
+    }
+
+    /// Route requests for the service named `name` to `service`.
+    ///
+    /// `name` is the fully-qualified `package.Service` name, without
+    /// slashes.
+    pub fn add_route<S, B>(mut self, name: &str, service: S) -> Self
+    where
+        S: Service<http::Request<BoxBody>, Response = http::Response<B>> + Clone + Send + 'static,
+        S::Error: Into<Error>,
+        S::Future: Send + 'static,
+        B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+    {
+        self.routes.insert(name.to_string(), Box::new(service));
+        self
+    }
+
+    /// Send requests for unknown services to `service`, instead of
+    /// responding with `Code::Unimplemented`.
+    pub fn fallback<S, B>(mut self, service: S) -> Self
+    where
+        S: Service<http::Request<BoxBody>, Response = http::Response<B>> + Clone + Send + 'static,
+        S::Error: Into<Error>,
+        S::Future: Send + 'static,
+        B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+    {
+        self.fallback = Some(Box::new(service));
+        self
+    }
+
+    fn route(&self, path: &str) -> Option<&dyn Route> {
+        service_name(path)
+            .and_then(|name| self.routes.get(name))
+            .or_else(|| self.fallback.as_ref())
+            .map(|route| &**route)
+    }
+}
+
+impl Service<http::Request<BoxBody>> for Router {
+    type Response = http::Response<BoxBody>;
+    type Error = Never;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // Readiness is checked on a clone of the routed service, once the
+        // request has been matched.
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let kind = match self.route(request.uri().path()) {
+            Some(route) => Kind::Route(route.oneshot(request)),
+            None => {
+                let message = format!("unknown service: {:?}", request.uri().path());
+                Kind::Status(Some(Status::new(Code::Unimplemented, message)))
+            }
+        };
+
+        ResponseFuture { kind }
+    }
+}
+
+#[cfg(feature = "tower-h2")]
+impl Service<http::Request<::tower_h2::RecvBody>> for Router {
+    type Response = http::Response<BoxBody>;
+    type Error = Never;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Service::<http::Request<BoxBody>>::poll_ready(self)
+    }
+
+    fn call(&mut self, request: http::Request<::tower_h2::RecvBody>) -> Self::Future {
+        let request = request.map(BoxBody::map_from);
+        Service::<http::Request<BoxBody>>::call(self, request)
+    }
+}
+
+#[cfg(feature = "tower-hyper")]
+impl Service<http::Request<::tower_hyper::Body>> for Router {
+    type Response = http::Response<BoxBody>;
+    type Error = Never;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Service::<http::Request<BoxBody>>::poll_ready(self)
+    }
+
+    fn call(&mut self, request: http::Request<::tower_hyper::Body>) -> Self::Future {
+        let request = request.map(BoxBody::map_from);
+        Service::<http::Request<BoxBody>>::call(self, request)
+    }
+}
+
+impl Service<()> for Router {
+    type Response = Self;
+    type Error = Never;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, _target: ()) -> Self::Future {
+        future::ok(self.clone())
+    }
+}
+
+impl Clone for Router {
+    fn clone(&self) -> Self {
+        Router {
+            routes: self
+                .routes
+                .iter()
+                .map(|(name, route)| (name.clone(), route.clone_route()))
+                .collect(),
+            fallback: self.fallback.as_ref().map(|route| route.clone_route()),
+        }
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut routes: Vec<_> = self.routes.keys().collect();
+        routes.sort();
+
+        f.debug_struct("Router")
+            .field("routes", &routes)
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+
+/// Returns the `package.Service` part of a `/package.Service/Method` path.
+fn service_name(path: &str) -> Option<&str> {
+    let mut parts = path.splitn(3, '/');
+
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(""), Some(service), Some(_)) => Some(service),
+        _ => None,
+    }
+}
+
+/// Build a trailers-only response carrying `status`.
+pub(crate) fn status_response(status: Status) -> http::Response<BoxBody> {
+    let mut response = http::Response::new(BoxBody::empty());
+
+    if let Err(err) = status.add_header(response.headers_mut()) {
+        err.add_header(response.headers_mut())
+            .expect("generated status header should be valid");
+    }
+
+    response
+}
+
+// ===== impl ResponseFuture =====
+
+impl Future for ResponseFuture {
+    type Item = http::Response<BoxBody>;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let status = match self.kind {
+            Kind::Route(ref mut fut) => match fut.poll() {
+                Ok(ready) => return Ok(ready),
+                Err(err) => {
+                    debug!("routed service failed: {}", err);
+                    Status::from_error(&*err)
+                }
+            },
+            Kind::Status(ref mut status) => status.take().expect("polled after complete"),
+        };
+
+        Ok(Async::Ready(status_response(status)))
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("router::ResponseFuture").finish()
+    }
+}
+
+// ===== impl Route =====
+
+impl<T, B> Route for T
+where
+    T: Service<http::Request<BoxBody>, Response = http::Response<B>> + Clone + Send + 'static,
+    T::Error: Into<Error>,
+    T::Future: Send + 'static,
+    B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+{
+    fn oneshot(&self, request: http::Request<BoxBody>) -> RouteFuture {
+        Box::new(Oneshot {
+            service: self.clone(),
+            state: State::NotReady(Some(request)),
+        })
+    }
+
+    fn clone_route(&self) -> Box<dyn Route> {
+        Box::new(self.clone())
+    }
+}
+
+// ===== impl Oneshot =====
+
+impl<T, B> Future for Oneshot<T>
+where
+    T: Service<http::Request<BoxBody>, Response = http::Response<B>>,
+    T::Error: Into<Error>,
+    B: Body<Item = BytesBuf, Error = Status> + Send + 'static,
+{
+    type Item = http::Response<BoxBody>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let request = match self.state {
+                State::NotReady(ref mut request) => {
+                    try_ready!(self.service.poll_ready().map_err(Into::into));
+                    request.take().expect("polled after complete")
+                }
+                State::Called(ref mut fut) => {
+                    let response = try_ready!(fut.poll().map_err(Into::into));
+                    let response = response.map(|body| BoxBody::new(Box::new(body)));
+                    return Ok(response.into());
+                }
+            };
+
+            self.state = State::Called(self.service.call(request));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::FutureResult;
+
+    #[derive(Clone)]
+    struct Named(&'static str);
+
+    impl NamedService for Named {
+        const NAME: &'static str = "test.Named";
+    }
+
+    impl Service<http::Request<BoxBody>> for Named {
+        type Response = http::Response<BoxBody>;
+        type Error = Never;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            let mut response = http::Response::new(BoxBody::empty());
+            response
+                .headers_mut()
+                .insert("x-route", http::header::HeaderValue::from_static(self.0));
+            future::ok(response)
+        }
+    }
+
+    fn call(router: &mut Router, path: &str) -> http::Response<BoxBody> {
+        let request = http::Request::builder()
+            .uri(path)
+            .body(BoxBody::empty())
+            .unwrap();
+
+        Service::<http::Request<BoxBody>>::call(router, request)
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn routes_by_service_name() {
+        let mut router = Router::new()
+            .add_service(Named("named"))
+            .add_route("test.Other", Named("other"));
+
+        let response = call(&mut router, "/test.Named/Method");
+        assert_eq!(response.headers()["x-route"], "named");
+
+        let response = call(&mut router, "/test.Other/Method");
+        assert_eq!(response.headers()["x-route"], "other");
+    }
+
+    #[test]
+    fn unknown_service_is_unimplemented() {
+        let mut router = Router::new().add_service(Named("named"));
+
+        let response = call(&mut router, "/test.Unknown/Method");
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::Unimplemented);
+    }
+
+    #[test]
+    fn unknown_service_uses_fallback() {
+        let mut router = Router::new()
+            .add_service(Named("named"))
+            .fallback(Named("fallback"));
+
+        let response = call(&mut router, "/test.Unknown/Method");
+        assert_eq!(response.headers()["x-route"], "fallback");
+    }
+}
@@ -0,0 +1,612 @@
+//! Callbacks for the lifecycle events of calls, to build telemetry on.
+
+use generic::server::ConnectionInfo;
+use {Code, Status};
+
+use futures::Poll;
+use http;
+use tower_service::Service;
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Receives the events of the calls made by a client, or handled by a
+/// server.
+///
+/// Every call starts with `begin` and finishes with a single `end`, with
+/// the other events in between. All methods do nothing by default, so that
+/// a handler only implements the events it is interested in.
+///
+/// A client reports its events once given a handler with
+/// `client::Grpc::stats_handler`; a server, when wrapped in `Stats`.
+pub trait Handler: Send + Sync + 'static {
+    /// Called when a call starts.
+    fn begin(&self, info: &RpcInfo) {
+        let _ = info;
+    }
+
+    /// Called with the headers sent: those of the request by a client, and
+    /// of the response by a server.
+    fn out_header(&self, info: &RpcInfo, headers: &http::HeaderMap) {
+        let _ = (info, headers);
+    }
+
+    /// Called with the headers received: those of the response by a client,
+    /// and of the request by a server.
+    ///
+    /// A trailers-only response is reported to `in_trailer` instead.
+    fn in_header(&self, info: &RpcInfo, headers: &http::HeaderMap) {
+        let _ = (info, headers);
+    }
+
+    /// Called for every message sent.
+    fn out_payload(&self, info: &RpcInfo, payload: &Payload) {
+        let _ = (info, payload);
+    }
+
+    /// Called for every message received.
+    fn in_payload(&self, info: &RpcInfo, payload: &Payload) {
+        let _ = (info, payload);
+    }
+
+    /// Called once the client has sent all of its messages.
+    ///
+    /// A server only reports it when it reads the end of the request, which
+    /// unary and server streaming methods don't do: they stop at the single
+    /// request message.
+    fn half_close(&self, info: &RpcInfo) {
+        let _ = info;
+    }
+
+    /// Called with the trailers sent by a server.
+    fn out_trailer(&self, info: &RpcInfo, trailers: &http::HeaderMap) {
+        let _ = (info, trailers);
+    }
+
+    /// Called with the trailers received by a client.
+    ///
+    /// A trailers-only response reports its headers as trailers. Only
+    /// streaming responses report their trailers otherwise: unary and client
+    /// streaming calls end as soon as their response message is received,
+    /// without waiting for the trailers that follow it.
+    fn in_trailer(&self, info: &RpcInfo, trailers: &http::HeaderMap) {
+        let _ = (info, trailers);
+    }
+
+    /// Called when a call ends, with its final status.
+    ///
+    /// Calls dropped before their status is known end with
+    /// `Code::Cancelled`.
+    fn end(&self, info: &RpcInfo, status: &Status) {
+        let _ = (info, status);
+    }
+}
+
+/// Describes the call an event belongs to.
+#[derive(Debug)]
+pub struct RpcInfo {
+    id: u64,
+    method: String,
+    client: bool,
+    authority: Option<String>,
+    peer: Option<SocketAddr>,
+    start: Instant,
+}
+
+/// A message sent or received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Payload<'a> {
+    data: &'a [u8],
+    wire_length: usize,
+}
+
+/// Reports the calls handled by a server service to a `Handler`.
+///
+/// The events themselves are reported as the requests are decoded and the
+/// responses encoded, so `inner` should be a generated `XServer` or a
+/// `Router` of them.
+///
+/// ```rust,ignore
+/// let greeter = Stats::new(GreeterServer::new(Greet), MyHandler::default());
+/// let router = Router::new().add_service(greeter);
+/// ```
+#[derive(Clone)]
+pub struct Stats<S> {
+    inner: S,
+    handler: SharedHandler,
+}
+
+/// A `Handler` shared by every call.
+#[derive(Clone)]
+pub(crate) struct SharedHandler(Arc<dyn Handler>);
+
+/// Reports the events of a single call to a `Handler`.
+///
+/// A default `Recorder` reports nothing.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    call: Option<Arc<Call>>,
+}
+
+struct Call {
+    handler: SharedHandler,
+    info: RpcInfo,
+    ended: AtomicBool,
+}
+
+// ===== impl RpcInfo =====
+
+impl RpcInfo {
+    fn new(method: &str, client: bool) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+        RpcInfo {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed) as u64,
+            method: method.to_string(),
+            client,
+            authority: None,
+            peer: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns an identifier for the call, unique within the process.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the path of the method called, as in `/package.Service/Method`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns whether the call was made by a client, rather than handled by
+    /// a server.
+    pub fn is_client(&self) -> bool {
+        self.client
+    }
+
+    /// Returns the authority a server was called with, if known.
+    pub fn authority(&self) -> Option<&str> {
+        self.authority.as_ref().map(|authority| &authority[..])
+    }
+
+    /// Returns the address of the client calling a server, if known.
+    ///
+    /// It is known when the server records `ConnectionInfo` on requests, as
+    /// `Builder` and `WithConnectionInfo` do.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// Returns when the call began.
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+}
+
+// ===== impl Payload =====
+
+impl<'a> Payload<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Payload {
+            data,
+            // Messages are never compressed, so only the gRPC frame header
+            // is added on the wire.
+            wire_length: data.len() + 5,
+        }
+    }
+
+    /// Returns the encoded, uncompressed message.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Returns the length of the encoded, uncompressed message.
+    pub fn length(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the length of the message on the wire, including its gRPC
+    /// frame header.
+    pub fn wire_length(&self) -> usize {
+        self.wire_length
+    }
+}
+
+// ===== impl Stats =====
+
+impl<S> Stats<S> {
+    /// Report the calls handled by `inner` to `handler`.
+    pub fn new<H>(inner: S, handler: H) -> Self
+    where
+        H: Handler,
+    {
+        Stats {
+            inner,
+            handler: SharedHandler::new(handler),
+        }
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for Stats<S>
+where
+    S: Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        // Picked up by the server codec, which reports the call's events.
+        request.extensions_mut().insert(self.handler.clone());
+        self.inner.call(request)
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<S> ::server::NamedService for Stats<S>
+where
+    S: ::server::NamedService,
+{
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> fmt::Debug for Stats<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stats").field("inner", &self.inner).finish()
+    }
+}
+
+// ===== impl SharedHandler =====
+
+impl SharedHandler {
+    pub(crate) fn new<H>(handler: H) -> Self
+    where
+        H: Handler,
+    {
+        SharedHandler(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for SharedHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedHandler").finish()
+    }
+}
+
+// ===== impl Recorder =====
+
+impl Recorder {
+    /// Begin reporting a call made by a client to `method` to `handler`, if
+    /// any.
+    pub(crate) fn client(handler: Option<&SharedHandler>, method: &str) -> Self {
+        match handler {
+            Some(handler) => Recorder::begin(handler, RpcInfo::new(method, true)),
+            None => Recorder::default(),
+        }
+    }
+
+    /// Begin reporting the call a server received as `request` to
+    /// `handler`, if any.
+    pub(crate) fn server<B>(handler: Option<&SharedHandler>, request: &http::Request<B>) -> Self {
+        let handler = match handler {
+            Some(handler) => handler,
+            None => return Recorder::default(),
+        };
+
+        let mut info = RpcInfo::new(request.uri().path(), false);
+        info.authority = request
+            .uri()
+            .authority_part()
+            .map(|authority| authority.as_str().to_string());
+        info.peer = request
+            .extensions()
+            .get::<ConnectionInfo>()
+            .map(|info| info.remote_addr());
+
+        Recorder::begin(handler, info)
+    }
+
+    fn begin(handler: &SharedHandler, info: RpcInfo) -> Self {
+        let handler = handler.clone();
+        (handler.0).begin(&info);
+
+        let call = Call {
+            handler,
+            info,
+            ended: AtomicBool::new(false),
+        };
+
+        Recorder {
+            call: Some(Arc::new(call)),
+        }
+    }
+
+    pub(crate) fn out_header(&self, headers: &http::HeaderMap) {
+        if let Some(ref call) = self.call {
+            call.handler.0.out_header(&call.info, headers);
+        }
+    }
+
+    pub(crate) fn in_header(&self, headers: &http::HeaderMap) {
+        if let Some(ref call) = self.call {
+            call.handler.0.in_header(&call.info, headers);
+        }
+    }
+
+    pub(crate) fn out_payload(&self, data: &[u8]) {
+        if let Some(ref call) = self.call {
+            call.handler.0.out_payload(&call.info, &Payload::new(data));
+        }
+    }
+
+    pub(crate) fn in_payload(&self, data: &[u8]) {
+        if let Some(ref call) = self.call {
+            call.handler.0.in_payload(&call.info, &Payload::new(data));
+        }
+    }
+
+    pub(crate) fn half_close(&self) {
+        if let Some(ref call) = self.call {
+            call.handler.0.half_close(&call.info);
+        }
+    }
+
+    pub(crate) fn out_trailer(&self, trailers: &http::HeaderMap) {
+        if let Some(ref call) = self.call {
+            call.handler.0.out_trailer(&call.info, trailers);
+        }
+    }
+
+    pub(crate) fn in_trailer(&self, trailers: &http::HeaderMap) {
+        if let Some(ref call) = self.call {
+            call.handler.0.in_trailer(&call.info, trailers);
+        }
+    }
+
+    /// Returns whether events are reported to a handler at all.
+    pub(crate) fn is_recording(&self) -> bool {
+        self.call.is_some()
+    }
+
+    /// Report the end of the call, unless it already ended.
+    pub(crate) fn end(&self, status: &Status) {
+        if let Some(ref call) = self.call {
+            call.end(status);
+        }
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorder")
+            .field("info", &self.call.as_ref().map(|call| &call.info))
+            .finish()
+    }
+}
+
+// ===== impl Call =====
+
+impl Call {
+    fn end(&self, status: &Status) {
+        if !self.ended.swap(true, Ordering::SeqCst) {
+            self.handler.0.end(&self.info, status);
+        }
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.end(&Status::new(
+            Code::Cancelled,
+            "call dropped before it ended",
+        ));
+    }
+}
+
+#[cfg(all(test, feature = "protobuf"))]
+mod tests {
+    use super::*;
+    use body::{BoxBody, HttpBody};
+    use client::Grpc;
+    use codec::Encoder;
+    use generic::Encode;
+    use test_util::Server;
+    use Request;
+
+    use futures::future::{self, FutureResult};
+    use futures::{stream, Async, Future, Stream};
+
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<String>>>);
+
+    impl Handler for Events {
+        fn begin(&self, info: &RpcInfo) {
+            self.push(format!("begin {}", info.method()));
+        }
+
+        fn out_header(&self, _: &RpcInfo, _: &http::HeaderMap) {
+            self.push("out_header".to_string());
+        }
+
+        fn in_header(&self, _: &RpcInfo, _: &http::HeaderMap) {
+            self.push("in_header".to_string());
+        }
+
+        fn out_payload(&self, _: &RpcInfo, payload: &Payload) {
+            self.push(format!(
+                "out_payload {} {}",
+                payload.length(),
+                payload.wire_length()
+            ));
+        }
+
+        fn in_payload(&self, _: &RpcInfo, payload: &Payload) {
+            self.push(format!(
+                "in_payload {} {}",
+                payload.length(),
+                payload.wire_length()
+            ));
+        }
+
+        fn half_close(&self, _: &RpcInfo) {
+            self.push("half_close".to_string());
+        }
+
+        fn out_trailer(&self, _: &RpcInfo, _: &http::HeaderMap) {
+            self.push("out_trailer".to_string());
+        }
+
+        fn in_trailer(&self, _: &RpcInfo, _: &http::HeaderMap) {
+            self.push("in_trailer".to_string());
+        }
+
+        fn end(&self, _: &RpcInfo, status: &Status) {
+            self.push(format!("end {:?}", status.code()));
+        }
+    }
+
+    impl Events {
+        fn push(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn take(&self) -> Vec<String> {
+            ::std::mem::replace(&mut *self.0.lock().unwrap(), Vec::new())
+        }
+    }
+
+    /// Reads the whole request, replying with the given `u32` message, if
+    /// any.
+    struct Transport(Option<u32>);
+
+    impl Service<http::Request<BoxBody>> for Transport {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            let mut body = request.into_body();
+            while let Async::Ready(Some(_)) = HttpBody::poll_buf(&mut body).unwrap() {}
+
+            let reply = Encode::response(Encoder::new(), stream::iter_ok::<_, Status>(self.0));
+            future::ok(http::Response::new(BoxBody::new(Box::new(reply))))
+        }
+    }
+
+    fn request(message: u32) -> http::Request<BoxBody> {
+        let body = Encode::request(Encoder::new(), stream::once(Ok::<_, Status>(message)));
+        http::Request::builder()
+            .uri("/test.Svc/Method")
+            .body(BoxBody::new(Box::new(body)))
+            .unwrap()
+    }
+
+    #[test]
+    fn reports_client_events() {
+        let events = Events::default();
+        let mut client = Grpc::new(Transport(Some(7))).stats_handler(events.clone());
+
+        let path = http::uri::PathAndQuery::from_static("/test.Svc/Method");
+        let response = client
+            .server_streaming::<_, u32, _>(Request::new(3u32), path)
+            .wait()
+            .unwrap();
+        let messages: Vec<_> = response.into_inner().wait().collect();
+        assert_eq!(messages.len(), 1);
+
+        assert_eq!(
+            events.take(),
+            [
+                "begin /test.Svc/Method",
+                "out_header",
+                "out_payload 2 7",
+                "half_close",
+                "in_header",
+                "in_payload 2 7",
+                "in_trailer",
+                "end Ok",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_unary_message() {
+        let events = Events::default();
+        let mut client = Grpc::new(Transport(None)).stats_handler(events.clone());
+
+        let path = http::uri::PathAndQuery::from_static("/test.Svc/Method");
+        let status = client
+            .unary::<_, u32, _>(Request::new(3u32), path)
+            .wait()
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+
+        assert_eq!(
+            events.take(),
+            [
+                "begin /test.Svc/Method",
+                "out_header",
+                "out_payload 2 7",
+                "half_close",
+                "in_header",
+                "in_trailer",
+                "end Internal",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_server_events() {
+        let events = Events::default();
+        let mut service = Stats::new(Server, events.clone());
+
+        let mut body = service.call(request(3)).wait().unwrap().into_body();
+        while let Async::Ready(Some(_)) = HttpBody::poll_buf(&mut body).unwrap() {}
+        HttpBody::poll_trailers(&mut body).unwrap();
+
+        assert_eq!(
+            events.take(),
+            [
+                "begin /test.Svc/Method",
+                "in_header",
+                "in_payload 2 7",
+                "out_header",
+                "out_payload 2 7",
+                "out_trailer",
+                "end Ok",
+            ]
+        );
+
+        // Dropping a call before its status is sent cancels it.
+        drop(service.call(request(3)).wait().unwrap());
+        assert_eq!(events.take().last().unwrap(), "end Cancelled");
+    }
+}
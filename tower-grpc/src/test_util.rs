@@ -0,0 +1,51 @@
+//! Services shared by the tests of several modules.
+
+use body::BoxBody;
+use error::Never;
+use server::{self, unary};
+use {Request, Response, Status};
+
+use futures::future::{self, FutureResult};
+use futures::Poll;
+use http;
+use tower_service::Service;
+
+/// Replies to a `u32` with twice its value.
+#[derive(Clone, Debug)]
+pub(crate) struct Double;
+
+/// Serves `Double` as a unary method.
+#[derive(Debug)]
+pub(crate) struct Server;
+
+// ===== impl Double =====
+
+impl Service<Request<u32>> for Double {
+    type Response = Response<u32>;
+    type Error = Status;
+    type Future = FutureResult<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: Request<u32>) -> Self::Future {
+        future::ok(Response::new(request.into_inner() * 2))
+    }
+}
+
+// ===== impl Server =====
+
+impl Service<http::Request<BoxBody>> for Server {
+    type Response = http::Response<::codec::Encode<unary::Once<u32>>>;
+    type Error = Never;
+    type Future = unary::ResponseFuture<Double, BoxBody, u32>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        server::unary(Double, request)
+    }
+}
@@ -0,0 +1,784 @@
+//! Propagating trace context and recording a span for every call.
+//!
+//! The context of a trace is carried in request metadata, using the W3C
+//! `traceparent` and `tracestate` headers, the binary `grpc-trace-bin`
+//! format, or both. Spans are handed to a `SpanSink` once their call is
+//! done.
+
+use body::{Body, HttpBody};
+use error::Error;
+use metadata::{MetadataMap, MetadataValue};
+use observe::{self, Observe};
+use {Code, Status};
+
+use futures::{Future, Poll};
+use http;
+use rand::{self, Rng};
+use tower_service::Service;
+
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+const GRPC_TRACE_BIN: &str = "grpc-trace-bin";
+
+/// Propagates trace context on the calls made to, or handled by, a service,
+/// recording a span for each of them.
+///
+/// On the client, the span of a call is a child of the `SpanContext` found in
+/// the request extensions, if any, or else the root of a new trace. Its
+/// context is sent in the request metadata.
+///
+/// On the server, the span of a call is a child of the context received in
+/// the request metadata, if any. Its `SpanContext` is added to the request
+/// extensions, where handlers can find it, and copy it to the requests they
+/// make in turn.
+///
+/// ```rust,ignore
+/// let sink = InMemorySink::new();
+///
+/// let greeter = Trace::server(GreeterServer::new(Greet), sink.clone());
+/// let router = Router::new().add_service(greeter);
+/// ```
+pub struct Trace<S> {
+    inner: S,
+    kind: SpanKind,
+    format: Format,
+    sink: Arc<dyn SpanSink>,
+}
+
+/// The formats trace context is sent in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The W3C `traceparent` and `tracestate` headers.
+    TraceContext,
+    /// The binary `grpc-trace-bin` header.
+    Binary,
+    /// Both the W3C and binary headers.
+    Both,
+}
+
+/// Identifies a span, and the trace it is part of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpanContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    sampled: bool,
+    trace_state: Option<String>,
+}
+
+/// Whether a span is recorded by the client or the server of a call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpanKind {
+    Client,
+    Server,
+}
+
+/// A finished span, recording a single call.
+#[derive(Clone, Debug)]
+pub struct Span {
+    context: SpanContext,
+    parent_span_id: Option<[u8; 8]>,
+    kind: SpanKind,
+    method: String,
+    code: Code,
+    start: SystemTime,
+    duration: Duration,
+}
+
+/// Receives finished spans, typically to export them.
+///
+/// Only the spans of sampled traces are exported.
+pub trait SpanSink: Send + Sync + 'static {
+    /// Export a finished span.
+    fn export(&self, span: Span);
+}
+
+/// A `SpanSink` keeping spans in memory, mostly useful in tests.
+///
+/// Clones share the same spans.
+#[derive(Clone, Debug, Default)]
+pub struct InMemorySink {
+    spans: Arc<Mutex<Vec<Span>>>,
+}
+
+/// Response future returned by `Trace`.
+pub struct ResponseFuture<F> {
+    inner: observe::ResponseFuture<F, Pending>,
+}
+
+/// Response body returned by `Trace`, finishing the span once the status of
+/// the call is known.
+pub struct ResponseBody<B> {
+    inner: observe::ResponseBody<B, Pending>,
+}
+
+/// A span in progress.
+struct Pending {
+    sink: Arc<dyn SpanSink>,
+    context: SpanContext,
+    parent_span_id: Option<[u8; 8]>,
+    kind: SpanKind,
+    method: String,
+    start: SystemTime,
+    started: Instant,
+}
+
+// ===== impl Trace =====
+
+impl<S> Trace<S> {
+    /// Trace the calls made by the client service `inner`, exporting their
+    /// spans to `sink`.
+    pub fn client<K>(inner: S, sink: K) -> Self
+    where
+        K: SpanSink,
+    {
+        Trace::new(inner, SpanKind::Client, sink)
+    }
+
+    /// Trace the calls handled by the server service `inner`, exporting
+    /// their spans to `sink`.
+    pub fn server<K>(inner: S, sink: K) -> Self
+    where
+        K: SpanSink,
+    {
+        Trace::new(inner, SpanKind::Server, sink)
+    }
+
+    fn new<K>(inner: S, kind: SpanKind, sink: K) -> Self
+    where
+        K: SpanSink,
+    {
+        Trace {
+            inner,
+            kind,
+            format: Format::Both,
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// Set the formats trace context is sent in by a client. Defaults to
+    /// `Format::Both`.
+    ///
+    /// Servers accept any of them, preferring `traceparent` when both are
+    /// received.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Consumes `self`, returning the inner service.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, R, B> Service<http::Request<R>> for Trace<S>
+where
+    S: Service<http::Request<R>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Body,
+{
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, mut request: http::Request<R>) -> Self::Future {
+        let parent = match self.kind {
+            SpanKind::Client => request.extensions().get::<SpanContext>().cloned(),
+            SpanKind::Server => with_metadata(request.headers_mut(), |m| SpanContext::extract(m)),
+        };
+
+        let context = match parent {
+            Some(ref parent) => parent.child(),
+            None => SpanContext::new_root(),
+        };
+
+        match self.kind {
+            SpanKind::Client => {
+                let format = self.format;
+                with_metadata(request.headers_mut(), |m| context.inject(m, format));
+            }
+            SpanKind::Server => {
+                request.extensions_mut().insert(context.clone());
+            }
+        }
+
+        let span = Pending {
+            sink: self.sink.clone(),
+            context,
+            parent_span_id: parent.map(|parent| parent.span_id),
+            kind: self.kind,
+            method: request.uri().path().to_string(),
+            start: SystemTime::now(),
+            started: Instant::now(),
+        };
+
+        ResponseFuture {
+            inner: observe::ResponseFuture::new(self.inner.call(request), span),
+        }
+    }
+}
+
+impl<S> Clone for Trace<S>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Trace {
+            inner: self.inner.clone(),
+            kind: self.kind,
+            format: self.format,
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl<S> ::server::NamedService for Trace<S>
+where
+    S: ::server::NamedService,
+{
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> fmt::Debug for Trace<S>
+where
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("inner", &self.inner)
+            .field("kind", &self.kind)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+/// Runs `f` on `headers` viewed as metadata.
+fn with_metadata<T, F>(headers: &mut http::HeaderMap, f: F) -> T
+where
+    F: FnOnce(&mut MetadataMap) -> T,
+{
+    let mut metadata = MetadataMap::from_headers(mem::replace(headers, http::HeaderMap::new()));
+    let result = f(&mut metadata);
+    *headers = metadata.into_headers();
+    result
+}
+
+// ===== impl SpanContext =====
+
+impl SpanContext {
+    /// Create the context of the first span of a new, sampled trace.
+    pub fn new_root() -> Self {
+        SpanContext {
+            trace_id: random_id(),
+            span_id: random_id(),
+            sampled: true,
+            trace_state: None,
+        }
+    }
+
+    /// Create the context of a new span, a child of this one.
+    pub fn child(&self) -> Self {
+        SpanContext {
+            span_id: random_id(),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the ID of the trace.
+    pub fn trace_id(&self) -> [u8; 16] {
+        self.trace_id
+    }
+
+    /// Returns the ID of the span.
+    pub fn span_id(&self) -> [u8; 8] {
+        self.span_id
+    }
+
+    /// Returns whether the trace is sampled.
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
+
+    /// Returns the vendor-specific `tracestate` of the trace, if any.
+    pub fn trace_state(&self) -> Option<&str> {
+        self.trace_state.as_ref().map(|state| &state[..])
+    }
+
+    /// Read the context sent in `metadata`, if any.
+    ///
+    /// `traceparent` is preferred over `grpc-trace-bin` when both are
+    /// present.
+    pub fn extract(metadata: &MetadataMap) -> Option<Self> {
+        let traceparent = metadata
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(SpanContext::from_traceparent);
+
+        if let Some(mut context) = traceparent {
+            let states: Vec<_> = metadata
+                .get_all(TRACESTATE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            if !states.is_empty() {
+                context.trace_state = Some(states.join(","));
+            }
+            return Some(context);
+        }
+
+        metadata
+            .get_bin(GRPC_TRACE_BIN)
+            .and_then(|value| value.to_bytes().ok())
+            .and_then(|bytes| SpanContext::from_grpc_trace_bin(&bytes))
+    }
+
+    /// Send this context in `metadata`, in `format`.
+    pub fn inject(&self, metadata: &mut MetadataMap, format: Format) {
+        if format != Format::Binary {
+            let traceparent = MetadataValue::from_str(&self.to_traceparent())
+                .expect("traceparent is a valid header value");
+            metadata.insert(TRACEPARENT, traceparent);
+
+            let state = self
+                .trace_state()
+                .and_then(|state| MetadataValue::from_str(state).ok());
+            match state {
+                Some(state) => {
+                    metadata.insert(TRACESTATE, state);
+                }
+                None => {
+                    metadata.remove(TRACESTATE);
+                }
+            }
+        }
+
+        if format != Format::TraceContext {
+            let bin = MetadataValue::from_bytes(&self.to_grpc_trace_bin());
+            metadata.insert_bin(GRPC_TRACE_BIN, bin);
+        }
+    }
+
+    /// Returns the `traceparent` header value for this context.
+    pub fn to_traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            hex(&self.trace_id),
+            hex(&self.span_id),
+            self.sampled as u8
+        )
+    }
+
+    /// Parse a `traceparent` header value.
+    pub fn from_traceparent(value: &str) -> Option<Self> {
+        let parts: Vec<_> = value.trim().split('-').collect();
+        if parts.len() < 4 || parts[0].len() != 2 {
+            return None;
+        }
+
+        // Later versions may add fields, but must keep the first four.
+        let version = unhex_byte(parts[0])?;
+        if version == 0xff || (version == 0 && parts.len() != 4) {
+            return None;
+        }
+
+        let mut trace_id = [0; 16];
+        let mut span_id = [0; 8];
+        unhex(parts[1], &mut trace_id)?;
+        unhex(parts[2], &mut span_id)?;
+        if parts[3].len() != 2 || trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        let flags = unhex_byte(parts[3])?;
+
+        Some(SpanContext {
+            trace_id,
+            span_id,
+            sampled: flags & 1 == 1,
+            trace_state: None,
+        })
+    }
+
+    /// Returns the `grpc-trace-bin` header value for this context, before
+    /// base64 encoding.
+    pub fn to_grpc_trace_bin(&self) -> Vec<u8> {
+        let mut bin = Vec::with_capacity(29);
+        // Version, followed by each field ID and value.
+        bin.push(0);
+        bin.push(0);
+        bin.extend_from_slice(&self.trace_id);
+        bin.push(1);
+        bin.extend_from_slice(&self.span_id);
+        bin.push(2);
+        bin.push(self.sampled as u8);
+        bin
+    }
+
+    /// Parse a `grpc-trace-bin` header value, after base64 decoding.
+    pub fn from_grpc_trace_bin(bin: &[u8]) -> Option<Self> {
+        if bin.first() != Some(&0) {
+            return None;
+        }
+
+        let mut trace_id = None;
+        let mut span_id = None;
+        let mut sampled = false;
+        let mut rest = &bin[1..];
+
+        // Fields are in order of their ID; parsing stops at an unknown one.
+        loop {
+            match rest.first() {
+                Some(&0) if rest.len() >= 17 => {
+                    let mut id = [0; 16];
+                    id.copy_from_slice(&rest[1..17]);
+                    trace_id = Some(id);
+                    rest = &rest[17..];
+                }
+                Some(&1) if rest.len() >= 9 => {
+                    let mut id = [0; 8];
+                    id.copy_from_slice(&rest[1..9]);
+                    span_id = Some(id);
+                    rest = &rest[9..];
+                }
+                Some(&2) if rest.len() >= 2 => {
+                    sampled = rest[1] & 1 == 1;
+                    rest = &rest[2..];
+                }
+                _ => break,
+            }
+        }
+
+        match (trace_id, span_id) {
+            (Some(trace_id), Some(span_id)) if trace_id != [0; 16] && span_id != [0; 8] => {
+                Some(SpanContext {
+                    trace_id,
+                    span_id,
+                    sampled,
+                    trace_state: None,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+fn random_id<T>() -> T
+where
+    T: Default + PartialEq,
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
+{
+    let mut rng = rand::thread_rng();
+    loop {
+        // All-zero IDs are invalid.
+        let id = rng.gen();
+        if id != T::default() {
+            return id;
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decodes lowercase hex `src` into `dst`, which it must fill exactly.
+fn unhex(src: &str, dst: &mut [u8]) -> Option<()> {
+    if src.len() != dst.len() * 2 {
+        return None;
+    }
+
+    for (i, byte) in dst.iter_mut().enumerate() {
+        *byte = unhex_byte(src.get(i * 2..i * 2 + 2)?)?;
+    }
+    Some(())
+}
+
+fn unhex_byte(src: &str) -> Option<u8> {
+    let lowercase = src.bytes().all(|b| match b {
+        b'0'..=b'9' | b'a'..=b'f' => true,
+        _ => false,
+    });
+    if src.len() != 2 || !lowercase {
+        return None;
+    }
+    u8::from_str_radix(src, 16).ok()
+}
+
+// ===== impl Span =====
+
+impl Span {
+    /// Returns the context of the span.
+    pub fn context(&self) -> &SpanContext {
+        &self.context
+    }
+
+    /// Returns the ID of the parent span, if it isn't the root of its trace.
+    pub fn parent_span_id(&self) -> Option<[u8; 8]> {
+        self.parent_span_id
+    }
+
+    /// Returns whether the span was recorded by the client or the server.
+    pub fn kind(&self) -> SpanKind {
+        self.kind
+    }
+
+    /// Returns the `/package.Service/Method` path of the call.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the final status code of the call.
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    /// Returns when the call started.
+    pub fn start(&self) -> SystemTime {
+        self.start
+    }
+
+    /// Returns how long the call took.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+// ===== impl InMemorySink =====
+
+impl InMemorySink {
+    /// Create a new, empty `InMemorySink`.
+    pub fn new() -> Self {
+        InMemorySink::default()
+    }
+
+    /// Returns the spans exported so far.
+    pub fn spans(&self) -> Vec<Span> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// Removes and returns the spans exported so far.
+    pub fn take(&self) -> Vec<Span> {
+        mem::replace(&mut *self.spans.lock().unwrap(), Vec::new())
+    }
+}
+
+impl SpanSink for InMemorySink {
+    fn export(&self, span: Span) {
+        self.spans.lock().unwrap().push(span);
+    }
+}
+
+// ===== impl Pending =====
+
+impl Observe for Pending {
+    fn finish(self, status: Status) {
+        if !self.context.sampled {
+            return;
+        }
+
+        self.sink.export(Span {
+            context: self.context,
+            parent_span_id: self.parent_span_id,
+            kind: self.kind,
+            method: self.method,
+            code: status.code(),
+            start: self.start,
+            duration: self.started.elapsed(),
+        });
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let response = try_ready!(self.inner.poll());
+        Ok(response.map(|inner| ResponseBody { inner }).into())
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("trace::ResponseFuture").finish()
+    }
+}
+
+// ===== impl ResponseBody =====
+
+impl<B> HttpBody for ResponseBody<B>
+where
+    B: Body,
+{
+    type Item = B::Item;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_buf(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll_buf()
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        self.inner.poll_trailers()
+    }
+}
+
+impl<B> fmt::Debug for ResponseBody<B>
+where
+    B: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("trace::ResponseBody")
+            .field("inner", self.inner.get_ref())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use body::BoxBody;
+    use futures::future::{self, FutureResult};
+
+    /// Replies with a trailers-only OK response, keeping the span context
+    /// it was called with.
+    #[derive(Clone, Default)]
+    struct Handler {
+        seen: Arc<Mutex<Option<SpanContext>>>,
+    }
+
+    impl Service<http::Request<BoxBody>> for Handler {
+        type Response = http::Response<BoxBody>;
+        type Error = Status;
+        type Future = FutureResult<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(().into())
+        }
+
+        fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+            *self.seen.lock().unwrap() = request.extensions().get::<SpanContext>().cloned();
+
+            let mut response = http::Response::new(BoxBody::empty());
+            response
+                .headers_mut()
+                .insert("grpc-status", http::header::HeaderValue::from_static("0"));
+            future::ok(response)
+        }
+    }
+
+    #[test]
+    fn traceparent_round_trips() {
+        let value = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let context = SpanContext::from_traceparent(value).unwrap();
+        assert!(context.is_sampled());
+        assert_eq!(
+            context.span_id(),
+            [0xb7, 0xad, 0x6b, 0x71, 0x69, 0x20, 0x33, 0x31]
+        );
+        assert_eq!(context.to_traceparent(), value);
+
+        for invalid in &[
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331",
+            "00-0AF7651916CD43DD8448EB211C80319C-b7ad6b7169203331-01",
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01",
+            "ff-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01-extra",
+        ] {
+            assert_eq!(SpanContext::from_traceparent(invalid), None, "{}", invalid);
+        }
+
+        // Later versions may add fields.
+        let future = "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00-extra";
+        assert!(!SpanContext::from_traceparent(future).unwrap().is_sampled());
+    }
+
+    #[test]
+    fn grpc_trace_bin_round_trips() {
+        let context = SpanContext::new_root();
+        let bin = context.to_grpc_trace_bin();
+        assert_eq!(bin.len(), 29);
+        assert_eq!(SpanContext::from_grpc_trace_bin(&bin), Some(context));
+
+        let mut metadata = MetadataMap::new();
+        let context = SpanContext::new_root();
+        context.inject(&mut metadata, Format::Binary);
+        assert!(!metadata.contains_key(TRACEPARENT));
+        assert_eq!(SpanContext::extract(&metadata), Some(context));
+    }
+
+    #[test]
+    fn extracts_tracestate_with_traceparent() {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            TRACEPARENT,
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                .parse()
+                .unwrap(),
+        );
+        metadata.append(TRACESTATE, "congo=t61rcWkgMzE".parse().unwrap());
+        metadata.append(TRACESTATE, "rojo=00f067aa0ba902b7".parse().unwrap());
+
+        let context = SpanContext::extract(&metadata).unwrap();
+        assert_eq!(
+            context.trace_state(),
+            Some("congo=t61rcWkgMzE,rojo=00f067aa0ba902b7")
+        );
+    }
+
+    #[test]
+    fn propagates_from_client_to_server() {
+        let sink = InMemorySink::new();
+        let handler = Handler::default();
+        let server = Trace::server(handler.clone(), sink.clone());
+        let mut client = Trace::client(server, sink.clone()).format(Format::TraceContext);
+
+        let parent = SpanContext::new_root();
+        let mut request = http::Request::builder()
+            .uri("/test.Svc/Method")
+            .body(BoxBody::empty())
+            .unwrap();
+        request.extensions_mut().insert(parent.clone());
+
+        let response = client.call(request).wait().unwrap();
+        drop(response);
+
+        let spans = sink.take();
+        assert_eq!(spans.len(), 2);
+        let client = spans.iter().find(|s| s.kind() == SpanKind::Client).unwrap();
+        let server = spans.iter().find(|s| s.kind() == SpanKind::Server).unwrap();
+
+        assert_eq!(client.parent_span_id(), Some(parent.span_id()));
+        assert_eq!(server.parent_span_id(), Some(client.context().span_id()));
+        assert_eq!(server.context().trace_id(), parent.trace_id());
+        assert_eq!(server.method(), "/test.Svc/Method");
+        assert_eq!(server.code(), Code::Ok);
+        assert_eq!(client.code(), Code::Ok);
+
+        let seen = handler.seen.lock().unwrap().clone();
+        assert_eq!(seen.as_ref(), Some(server.context()));
+    }
+}